@@ -1,8 +1,10 @@
 use adenosine::app_bsky;
+use adenosine::app_bsky::text::extract_entities;
 use adenosine::auth::parse_did_from_jwt;
+use adenosine::com_atproto;
 use adenosine::created_at_now;
 use adenosine::identifiers::*;
-use adenosine::xrpc::{XrpcClient, XrpcMethod};
+use adenosine::xrpc::{is_not_found, XrpcClient, XrpcMethod};
 use adenosine_cli::*;
 use anyhow::anyhow;
 use serde_json::{json, Value};
@@ -84,7 +86,9 @@ struct Opt {
 enum AccountCommand {
     /// Register a new account
     ///
-    /// Does not (yet) support invite codes or email verification.
+    /// By default the PDS creates a new DID for the account. An existing did:web identity can be
+    /// used instead, if its `did.json` lists the PDS as `AtpPersonalDataServer`, along with the PDS
+    /// signing key.
     ///
     /// This will return a JWT token that you should assign to the `ATP_AUTH_TOKEN` environment
     /// variable.
@@ -103,9 +107,50 @@ enum AccountCommand {
 
         #[structopt(long, short)]
         invite_code: Option<String>,
+
+        /// Existing did:web identity to register with
+        #[structopt(long)]
+        did: Option<String>,
     },
     /// Delete the currently logged-in account (danger!)
-    Delete,
+    ///
+    /// Without a token, requests a confirmation token from the PDS, which is sent by email. Run
+    /// again with the token to actually delete the account.
+    Delete {
+        #[structopt(long, short)]
+        password: String,
+
+        #[structopt(long, short)]
+        token: Option<String>,
+    },
+    /// Hide the currently logged-in account and repository, until re-activated
+    Deactivate,
+    /// Re-activate the currently logged-in account
+    Activate,
+    /// Move the currently logged-in account (did:plc only) to another PDS
+    ///
+    /// The new PDS imports the repository, and creates an account (with a new password) for the
+    /// existing DID. The DID is updated to point at the new PDS, and the account on the current
+    /// PDS is deactivated.
+    Migrate {
+        /// HTTP(S) URL of the new PDS
+        #[structopt(long)]
+        to_pds_host: String,
+
+        #[structopt(long, short)]
+        email: String,
+
+        /// Password for the account on the new PDS
+        #[structopt(long, short)]
+        password: String,
+
+        /// Handle on the new PDS, if different from current handle
+        #[structopt(long = "--username", short = "-u")]
+        handle: Option<String>,
+
+        #[structopt(long, short)]
+        invite_code: Option<String>,
+    },
     /// Create a new authenticated session
     ///
     /// This will return a JWT token that you should assign to the `ATP_AUTH_TOKEN` environment
@@ -123,7 +168,15 @@ enum AccountCommand {
     Logout,
     /// Fetches account metadata for the current session
     Info,
+    /// Switch the current account to a new handle
+    ///
+    /// Handles outside of the PDS registration domain must already resolve to the account DID
+    /// (via `https://<handle>/.well-known/atproto-did`).
+    Handle { handle: String },
     // TODO: CreateRevocationKey or CreateDid
+    /// List invite codes created for the current account, and who used them
+    Invites,
+    /// Create a new invite code (requires admin password)
     CreateInvite {
         #[structopt(short = "-u", default_value = "1")]
         uses: u64,
@@ -157,9 +210,27 @@ enum RepoCommand {
 #[derive(StructOpt)]
 enum BskyCommand {
     /// Fetch the account feed for a specific user (or self, by default)
-    Feed { name: Option<DidOrHost> },
+    Feed {
+        name: Option<DidOrHost>,
+
+        /// Maximum number of posts to fetch
+        #[structopt(long)]
+        limit: Option<u64>,
+
+        /// Fetch the page of older posts following this cursor (from a previous response)
+        #[structopt(long)]
+        cursor: Option<String>,
+    },
     /// Fetch timeline for currently logged-in account
-    Timeline,
+    Timeline {
+        /// Maximum number of posts to fetch
+        #[structopt(long)]
+        limit: Option<u64>,
+
+        /// Fetch the page of older posts following this cursor (from a previous response)
+        #[structopt(long)]
+        cursor: Option<String>,
+    },
     /// View post thread (parent and child replies)
     Thread {
         uri: AtUri,
@@ -169,9 +240,47 @@ enum BskyCommand {
         depth: Option<u64>,
     },
     /// Fetch notification feed
-    Notifications,
+    Notifications {
+        /// Maximum number of notifications to fetch
+        #[structopt(long)]
+        limit: Option<u64>,
+
+        /// Fetch the page of older notifications following this cursor
+        #[structopt(long)]
+        cursor: Option<String>,
+
+        /// Mark all notifications as seen (read) after fetching
+        #[structopt(long)]
+        mark_seen: bool,
+    },
     /// Create a new 'post' record
-    Post { text: String },
+    Post {
+        text: String,
+
+        /// Post as a reply to the post with this AT URI
+        #[structopt(long)]
+        reply_to: Option<AtUri>,
+
+        /// Attach an image file (png, jpeg, gif, or webp); can be repeated, up to 4 times
+        #[structopt(long, parse(from_os_str))]
+        image: Vec<std::path::PathBuf>,
+
+        /// Alt text (description) for the corresponding --image
+        #[structopt(long)]
+        alt: Vec<String>,
+
+        /// Attach an external link card for this URL
+        #[structopt(long)]
+        link: Option<String>,
+
+        /// Title for the --link card (defaults to the URL)
+        #[structopt(long)]
+        link_title: Option<String>,
+
+        /// Description for the --link card
+        #[structopt(long)]
+        link_description: Option<String>,
+    },
     /// Create a 'repost' record for the target by AT URI
     Repost { uri: AtUri },
     /// Create a 'like' record for the target by AT URI
@@ -180,18 +289,42 @@ enum BskyCommand {
     Follow { uri: DidOrHost },
     // TODO: Unlike { uri: String, },
     // TODO: Unfollow { uri: String, },
-    /* TODO:
+    /// List accounts followed by a user (or self, by default)
     Follows {
-        name: String,
+        name: Option<DidOrHost>,
+
+        /// Fetch the page following this cursor (from a previous response)
+        #[structopt(long)]
+        cursor: Option<String>,
     },
+    /// List followers of a user (or self, by default)
     Followers {
-        name: String,
+        name: Option<DidOrHost>,
+
+        /// Fetch the page following this cursor (from a previous response)
+        #[structopt(long)]
+        cursor: Option<String>,
     },
-    */
     /// Display a profile record (or self if not provided)
-    Profile { name: Option<DidOrHost> },
-    /// Query by partial handle
-    SearchUsers { query: String },
+    Profile {
+        name: Option<DidOrHost>,
+
+        /// Upload an image file (png, jpeg, gif, or webp) and set it as own avatar
+        #[structopt(long, parse(from_os_str))]
+        set_avatar: Option<std::path::PathBuf>,
+
+        /// Upload an image file (png, jpeg, gif, or webp) and set it as own banner
+        #[structopt(long, parse(from_os_str))]
+        set_banner: Option<std::path::PathBuf>,
+    },
+    /// Search for accounts by (partial) handle, display name, or description
+    SearchUsers {
+        query: String,
+
+        /// Fetch the page of results following this cursor (from a previous response)
+        #[structopt(long)]
+        cursor: Option<String>,
+    },
 }
 
 #[derive(StructOpt)]
@@ -339,6 +472,27 @@ fn require_auth_did(opt: &Opt, xrpc_client: &mut XrpcClient) -> Result<Did> {
     xrpc_client.auth_did()
 }
 
+/// Uploads an image file as a blob, returning the blob reference (CID and MIME type) for records
+fn upload_blob(xrpc_client: &XrpcClient, path: &std::path::Path) -> Result<Value> {
+    let mime_type = image_mime_type(path)?;
+    let bytes = std::fs::read(path)?;
+    let resp = xrpc_client
+        .post_bytes(
+            &Nsid::from_str("com.atproto.blob.upload")?,
+            None,
+            mime_type,
+            bytes,
+        )?
+        .ok_or(anyhow!("expected response from blob upload"))?;
+    let cid = resp["cid"]
+        .as_str()
+        .ok_or(anyhow!("expected 'cid' in blob upload response"))?;
+    Ok(json!({
+        "cid": cid,
+        "mimeType": mime_type,
+    }))
+}
+
 fn run(opt: Opt) -> Result<()> {
     let mut xrpc_client = XrpcClient::new(
         opt.pds_host.clone(),
@@ -375,7 +529,7 @@ fn run(opt: Opt) -> Result<()> {
             xrpc_client.get(&Nsid::from_str("com.atproto.repo.describe")?, Some(params))?
         }
         Command::Resolve { name } => {
-            params.insert("name".to_string(), name.to_string());
+            params.insert("handle".to_string(), name.to_string());
             xrpc_client.get(&Nsid::from_str("com.atproto.handle.resolve")?, Some(params))?
         }
         Command::Get { uri, cid } => {
@@ -415,14 +569,22 @@ fn run(opt: Opt) -> Result<()> {
             } else if uri.collection.is_some() && uri.record.is_none() {
                 // if a collection, but no record, list the records (with extracted timestamps)
                 params.insert("collection".to_string(), uri.collection.unwrap());
-                let records = xrpc_client
-                    .get(
-                        &Nsid::from_str("com.atproto.repo.listRecords")?,
-                        Some(params),
-                    )?
-                    .ok_or(anyhow!("expected a repoListRecords response"))?;
-                for r in records["records"].as_array().unwrap_or(&vec![]).iter() {
-                    println!("{}", r["uri"].as_str().unwrap());
+                params.insert("limit".to_string(), "100".to_string());
+                // follow cursors until all records have been listed
+                loop {
+                    let records = xrpc_client
+                        .get(
+                            &Nsid::from_str("com.atproto.repo.listRecords")?,
+                            Some(params.clone()),
+                        )?
+                        .ok_or(anyhow!("expected a repoListRecords response"))?;
+                    for r in records["records"].as_array().unwrap_or(&vec![]).iter() {
+                        println!("{}", r["uri"].as_str().unwrap());
+                    }
+                    match records["cursor"].as_str() {
+                        Some(cursor) => params.insert("cursor".to_string(), cursor.to_string()),
+                        None => break,
+                    };
                 }
             } else {
                 return Err(anyhow!("got too much of a URI to 'ls'"));
@@ -463,6 +625,7 @@ fn run(opt: Opt) -> Result<()> {
             // fetch existing, extend map with fields, put the updated value
             let mut record = xrpc_client
                 .get(&Nsid::from_str("com.atproto.repo.getRecord")?, Some(params))?
+                .map(|existing| existing["value"].clone())
                 .unwrap_or(json!({}));
             update_value_from_fields(fields.clone(), &mut record);
             xrpc_client.post(
@@ -517,6 +680,7 @@ fn run(opt: Opt) -> Result<()> {
                     password,
                     recovery_key,
                     invite_code,
+                    did,
                 },
         } => {
             let mut body = json!({
@@ -530,6 +694,9 @@ fn run(opt: Opt) -> Result<()> {
             if let Some(code) = invite_code {
                 body["inviteCode"] = json!(code);
             }
+            if let Some(did) = did {
+                body["did"] = json!(did);
+            }
             xrpc_client.post(
                 &Nsid::from_str("com.atproto.account.create")?,
                 None,
@@ -553,11 +720,132 @@ fn run(opt: Opt) -> Result<()> {
             cmd: AccountCommand::Logout,
         } => xrpc_client.post(&Nsid::from_str("com.atproto.session.delete")?, None, None)?,
         Command::Account {
-            cmd: AccountCommand::Delete,
-        } => xrpc_client.post(&Nsid::from_str("com.atproto.account.delete")?, None, None)?,
+            cmd:
+                AccountCommand::Delete {
+                    ref password,
+                    ref token,
+                },
+        } => {
+            let did = require_auth_did(&opt, &mut xrpc_client)?;
+            match token {
+                None => xrpc_client.post(
+                    &Nsid::from_str("com.atproto.account.requestDelete")?,
+                    None,
+                    None,
+                )?,
+                Some(token) => xrpc_client.post(
+                    &Nsid::from_str("com.atproto.account.delete")?,
+                    None,
+                    Some(json!({
+                        "did": did.to_string(),
+                        "password": password,
+                        "token": token,
+                    })),
+                )?,
+            }
+        }
+        Command::Account {
+            cmd: AccountCommand::Deactivate,
+        } => {
+            require_auth_did(&opt, &mut xrpc_client)?;
+            xrpc_client.post(
+                &Nsid::from_str("com.atproto.account.deactivate")?,
+                None,
+                None,
+            )?
+        }
+        Command::Account {
+            cmd: AccountCommand::Activate,
+        } => {
+            require_auth_did(&opt, &mut xrpc_client)?;
+            xrpc_client.post(&Nsid::from_str("com.atproto.account.activate")?, None, None)?
+        }
+        Command::Account {
+            cmd:
+                AccountCommand::Migrate {
+                    ref to_pds_host,
+                    ref email,
+                    ref password,
+                    ref handle,
+                    ref invite_code,
+                },
+        } => {
+            let did = require_auth_did(&opt, &mut xrpc_client)?;
+            let new_client = XrpcClient::new(to_pds_host.clone(), None, None)?;
+            let handle = match handle {
+                Some(h) => h.clone(),
+                None => {
+                    let resp = xrpc_client
+                        .get(&Nsid::from_str("com.atproto.session.get")?, None)?
+                        .ok_or(anyhow!("expected session info"))?;
+                    resp["handle"]
+                        .as_str()
+                        .ok_or(anyhow!("expected handle in session info"))?
+                        .to_string()
+                }
+            };
+            let new_config = new_client
+                .get(
+                    &Nsid::from_str("com.atproto.server.getAccountsConfig")?,
+                    None,
+                )?
+                .ok_or(anyhow!("expected accounts config from new PDS"))?;
+            debug!("signing DID update operation on current PDS");
+            let resp = xrpc_client
+                .post(
+                    &Nsid::from_str("com.atproto.account.requestMigration")?,
+                    None,
+                    Some(json!({
+                        "service": new_config["publicUrl"],
+                        "signingKey": new_config["signingKey"],
+                    })),
+                )?
+                .ok_or(anyhow!("expected DID operation from current PDS"))?;
+            let op = resp["op"].clone();
+            debug!("importing account and repository on new PDS");
+            let mut body = json!({
+                "did": did.to_string(),
+                "handle": handle,
+                "email": email,
+                "password": password,
+                "fromPds": opt.pds_host,
+                "op": op,
+            });
+            if let Some(code) = invite_code {
+                body["inviteCode"] = json!(code);
+            }
+            let sess = new_client.post(
+                &Nsid::from_str("com.atproto.account.importMigration")?,
+                None,
+                Some(body),
+            )?;
+            debug!("deactivating account on current PDS");
+            xrpc_client.post(
+                &Nsid::from_str("com.atproto.account.completeMigration")?,
+                None,
+                Some(json!({ "op": op })),
+            )?;
+            sess
+        }
         Command::Account {
             cmd: AccountCommand::Info,
         } => xrpc_client.get(&Nsid::from_str("com.atproto.account.get")?, None)?,
+        Command::Account {
+            cmd: AccountCommand::Handle { ref handle },
+        } => {
+            require_auth_did(&opt, &mut xrpc_client)?;
+            xrpc_client.post(
+                &Nsid::from_str("com.atproto.handle.update")?,
+                None,
+                Some(json!({ "handle": handle })),
+            )?
+        }
+        Command::Account {
+            cmd: AccountCommand::Invites,
+        } => {
+            require_auth_did(&opt, &mut xrpc_client)?;
+            xrpc_client.get(&Nsid::from_str("com.atproto.account.getInviteCodes")?, None)?
+        }
         Command::Account {
             cmd: AccountCommand::CreateInvite { uses },
         } => xrpc_client.post(
@@ -611,13 +899,24 @@ fn run(opt: Opt) -> Result<()> {
             )?
         }
         Command::Bsky {
-            cmd: BskyCommand::Feed { ref name },
+            cmd:
+                BskyCommand::Feed {
+                    ref name,
+                    limit,
+                    ref cursor,
+                },
         } => {
             let name = name
                 .as_ref()
                 .map(|v| v.to_string())
                 .unwrap_or(require_auth_did(&opt, &mut xrpc_client)?.to_string());
             params.insert("author".to_string(), name);
+            if let Some(limit) = limit {
+                params.insert("limit".to_string(), limit.to_string());
+            }
+            if let Some(cursor) = cursor {
+                params.insert("cursor".to_string(), cursor.to_string());
+            }
             let resp = xrpc_client.get(
                 &Nsid::from_str("app.bsky.feed.getAuthorFeed")?,
                 Some(params),
@@ -633,16 +932,26 @@ fn run(opt: Opt) -> Result<()> {
                     let fi: app_bsky::FeedPostView = serde_json::from_value(val)?;
                     pretty::pp_feed_post_view(&fi)?;
                 }
+                if let Some(cursor) = resp["cursor"].as_str() {
+                    println!("(more posts: --cursor {cursor})");
+                }
                 None
             } else {
                 Some(resp)
             }
         }
         Command::Bsky {
-            cmd: BskyCommand::Timeline,
+            cmd: BskyCommand::Timeline { limit, ref cursor },
         } => {
             require_auth_did(&opt, &mut xrpc_client)?;
-            let resp = xrpc_client.get(&Nsid::from_str("app.bsky.feed.getTimeline")?, None)?;
+            if let Some(limit) = limit {
+                params.insert("limit".to_string(), limit.to_string());
+            }
+            if let Some(cursor) = cursor {
+                params.insert("cursor".to_string(), cursor.to_string());
+            }
+            let resp =
+                xrpc_client.get(&Nsid::from_str("app.bsky.feed.getTimeline")?, Some(params))?;
             let resp = resp.ok_or(anyhow!("expected resp from getTimeline"))?;
             if atty::is(atty::Stream::Stdout) {
                 for val in resp["feed"]
@@ -655,6 +964,9 @@ fn run(opt: Opt) -> Result<()> {
                     let fi: app_bsky::FeedPostView = serde_json::from_value(val)?;
                     pretty::pp_feed_post_view(&fi)?;
                 }
+                if let Some(cursor) = resp["cursor"].as_str() {
+                    println!("(more posts: --cursor {cursor})");
+                }
                 None
             } else {
                 Some(resp)
@@ -685,25 +997,140 @@ fn run(opt: Opt) -> Result<()> {
             }
         }
         Command::Bsky {
-            cmd: BskyCommand::Notifications,
+            cmd:
+                BskyCommand::Notifications {
+                    limit,
+                    ref cursor,
+                    mark_seen,
+                },
         } => {
             require_auth_did(&opt, &mut xrpc_client)?;
-            xrpc_client.get(&Nsid::from_str("app.bsky.notification.list")?, None)?
+            if let Some(limit) = limit {
+                params.insert("limit".to_string(), limit.to_string());
+            }
+            if let Some(cursor) = cursor {
+                params.insert("cursor".to_string(), cursor.to_string());
+            }
+            let seen_at = created_at_now();
+            let resp =
+                xrpc_client.get(&Nsid::from_str("app.bsky.notification.list")?, Some(params))?;
+            let resp = resp.ok_or(anyhow!("expected resp from notification.list"))?;
+            if mark_seen {
+                xrpc_client.post(
+                    &Nsid::from_str("app.bsky.notification.updateSeen")?,
+                    None,
+                    Some(json!({ "seenAt": seen_at })),
+                )?;
+            }
+            if atty::is(atty::Stream::Stdout) {
+                for val in resp["notifications"]
+                    .as_array()
+                    .ok_or(anyhow!("expected notifications from notification.list"))?
+                    .iter()
+                {
+                    let notif: app_bsky::Notification = serde_json::from_value(val.clone())?;
+                    pretty::pp_notification(&notif)?;
+                }
+                if let Some(cursor) = resp["cursor"].as_str() {
+                    println!("(more notifications: --cursor {cursor})");
+                }
+                None
+            } else {
+                Some(resp)
+            }
         }
         Command::Bsky {
-            cmd: BskyCommand::Post { ref text },
+            cmd:
+                BskyCommand::Post {
+                    ref text,
+                    ref reply_to,
+                    ref image,
+                    ref alt,
+                    ref link,
+                    ref link_title,
+                    ref link_description,
+                },
         } => {
+            if image.len() > 4 {
+                return Err(anyhow!("at most 4 images can be attached to a post"));
+            }
+            if alt.len() > image.len() {
+                return Err(anyhow!("more --alt texts than --image files"));
+            }
             let did = require_auth_did(&opt, &mut xrpc_client)?;
+            // mentions of handles which the PDS doesn't know (404) are posted as plain text; any
+            // other error fails the post
+            let entities = extract_entities(text, |handle| {
+                let mut params: HashMap<String, String> = HashMap::new();
+                params.insert("handle".to_string(), handle.to_string());
+                let resp = match xrpc_client
+                    .get(&Nsid::from_str("com.atproto.handle.resolve")?, Some(params))
+                {
+                    Ok(resp) => resp,
+                    Err(e) if is_not_found(&e) => None,
+                    Err(e) => return Err(e),
+                };
+                Ok(resp
+                    .and_then(|v| v["did"].as_str().map(|v| v.to_string()))
+                    .and_then(|v| Did::from_str(&v).ok()))
+            })?;
+            let mut record = json!({
+                "text": text,
+                "createdAt": created_at_now(),
+            });
+            if !entities.is_empty() {
+                record["entities"] = json!(entities);
+            }
+            let mut embed = json!({});
+            if !image.is_empty() {
+                let mut images = vec![];
+                for (i, path) in image.iter().enumerate() {
+                    images.push(json!({
+                        "image": upload_blob(&xrpc_client, path)?,
+                        "alt": alt.get(i).cloned().unwrap_or_default(),
+                    }));
+                }
+                embed["images"] = json!(images);
+            }
+            if let Some(url) = link {
+                embed["external"] = json!({
+                    "uri": url,
+                    "title": link_title.as_ref().unwrap_or(url),
+                    "description": link_description.clone().unwrap_or_default(),
+                });
+            }
+            if !image.is_empty() || link.is_some() {
+                record["embed"] = embed;
+            }
+            if let Some(parent_uri) = reply_to {
+                params.insert("user".to_string(), parent_uri.repository.to_string());
+                params.insert(
+                    "collection".to_string(),
+                    parent_uri
+                        .collection
+                        .clone()
+                        .ok_or(anyhow!("collection required"))?,
+                );
+                params.insert(
+                    "rkey".to_string(),
+                    parent_uri
+                        .record
+                        .clone()
+                        .ok_or(anyhow!("record key required"))?,
+                );
+                let parent = xrpc_client
+                    .get(&Nsid::from_str("com.atproto.repo.getRecord")?, Some(params))?;
+                let parent: com_atproto::repo::RecordView =
+                    serde_json::from_value(parent.ok_or(anyhow!("expected record in reponse"))?)?;
+                record["reply"] = json!(app_bsky::PostReply::new(&parent));
+            }
             xrpc_client.post(
                 &Nsid::from_str("com.atproto.repo.createRecord")?,
                 None,
                 Some(json!({
                     "did": did,
                     "collection": "app.bsky.feed.post",
-                    "record": {
-                        "text": text,
-                        "createdAt": created_at_now(),
-                    },
+                    "record": record,
                 })),
             )?
         }
@@ -797,8 +1224,104 @@ fn run(opt: Opt) -> Result<()> {
             )?
         }
         Command::Bsky {
-            cmd: BskyCommand::Profile { ref name },
+            cmd:
+                BskyCommand::Follows {
+                    ref name,
+                    ref cursor,
+                },
+        } => {
+            let name = name
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or(require_auth_did(&opt, &mut xrpc_client)?.to_string());
+            params.insert("user".to_string(), name);
+            if let Some(cursor) = cursor {
+                params.insert("cursor".to_string(), cursor.to_string());
+            }
+            let resp = xrpc_client
+                .get(&Nsid::from_str("app.bsky.graph.getFollows")?, Some(params))?
+                .ok_or(anyhow!("expected resp from getFollows"))?;
+            if atty::is(atty::Stream::Stdout) {
+                for val in resp["follows"]
+                    .as_array()
+                    .ok_or(anyhow!("expected follows from getFollows"))?
+                    .iter()
+                {
+                    let ft: app_bsky::FollowTarget = serde_json::from_value(val.clone())?;
+                    pretty::pp_follow_target(&ft)?;
+                }
+                if let Some(cursor) = resp["cursor"].as_str() {
+                    println!("(more: --cursor {cursor})");
+                }
+                None
+            } else {
+                Some(resp)
+            }
+        }
+        Command::Bsky {
+            cmd:
+                BskyCommand::Followers {
+                    ref name,
+                    ref cursor,
+                },
+        } => {
+            let name = name
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or(require_auth_did(&opt, &mut xrpc_client)?.to_string());
+            params.insert("user".to_string(), name);
+            if let Some(cursor) = cursor {
+                params.insert("cursor".to_string(), cursor.to_string());
+            }
+            let resp = xrpc_client
+                .get(
+                    &Nsid::from_str("app.bsky.graph.getFollowers")?,
+                    Some(params),
+                )?
+                .ok_or(anyhow!("expected resp from getFollowers"))?;
+            if atty::is(atty::Stream::Stdout) {
+                for val in resp["followers"]
+                    .as_array()
+                    .ok_or(anyhow!("expected followers from getFollowers"))?
+                    .iter()
+                {
+                    let ft: app_bsky::FollowTarget = serde_json::from_value(val.clone())?;
+                    pretty::pp_follow_target(&ft)?;
+                }
+                if let Some(cursor) = resp["cursor"].as_str() {
+                    println!("(more: --cursor {cursor})");
+                }
+                None
+            } else {
+                Some(resp)
+            }
+        }
+        Command::Bsky {
+            cmd:
+                BskyCommand::Profile {
+                    ref name,
+                    ref set_avatar,
+                    ref set_banner,
+                },
         } => {
+            if set_avatar.is_some() || set_banner.is_some() {
+                if name.is_some() {
+                    return Err(anyhow!("can only update own profile"));
+                }
+                require_auth_did(&opt, &mut xrpc_client)?;
+                let mut update = json!({});
+                if let Some(path) = set_avatar {
+                    update["avatar"] = upload_blob(&xrpc_client, path)?;
+                }
+                if let Some(path) = set_banner {
+                    update["banner"] = upload_blob(&xrpc_client, path)?;
+                }
+                xrpc_client.post(
+                    &Nsid::from_str("app.bsky.actor.updateProfile")?,
+                    None,
+                    Some(update),
+                )?;
+            }
             let name = name
                 .as_ref()
                 .map(|v| v.to_string())
@@ -807,10 +1330,35 @@ fn run(opt: Opt) -> Result<()> {
             xrpc_client.get(&Nsid::from_str("app.bsky.actor.getProfile")?, Some(params))?
         }
         Command::Bsky {
-            cmd: BskyCommand::SearchUsers { query },
+            cmd:
+                BskyCommand::SearchUsers {
+                    ref query,
+                    ref cursor,
+                },
         } => {
-            params.insert("term".to_string(), query);
-            xrpc_client.get(&Nsid::from_str("app.bsky.actor.search")?, Some(params))?
+            params.insert("term".to_string(), query.to_string());
+            if let Some(cursor) = cursor {
+                params.insert("before".to_string(), cursor.to_string());
+            }
+            let resp = xrpc_client
+                .get(&Nsid::from_str("app.bsky.actor.search")?, Some(params))?
+                .ok_or(anyhow!("expected resp from search"))?;
+            if atty::is(atty::Stream::Stdout) {
+                for val in resp["users"]
+                    .as_array()
+                    .ok_or(anyhow!("expected users from search"))?
+                    .iter()
+                {
+                    let user: app_bsky::ActorSearchView = serde_json::from_value(val.clone())?;
+                    pretty::pp_actor_search_view(&user)?;
+                }
+                if let Some(cursor) = resp["cursor"].as_str() {
+                    println!("(more: --cursor {cursor})");
+                }
+                None
+            } else {
+                Some(resp)
+            }
         }
     };
     print_result_json(result)?;
@@ -75,6 +75,36 @@ fn test_argfield() {
     assert!(ArgField::from_str("text=\"other value\"").is_ok());
 }
 
+/// Guesses the MIME type of an image file (for blob uploads) from the file extension
+pub fn image_mime_type(path: &std::path::Path) -> Result<&'static str> {
+    let ext = path
+        .extension()
+        .and_then(|v| v.to_str())
+        .map(|v| v.to_lowercase());
+    match ext.as_deref() {
+        Some("png") => Ok("image/png"),
+        Some("jpg") | Some("jpeg") => Ok("image/jpeg"),
+        Some("gif") => Ok("image/gif"),
+        Some("webp") => Ok("image/webp"),
+        _ => Err(anyhow!(
+            "unsupported image type (expected png, jpeg, gif, or webp): {}",
+            path.display()
+        )),
+    }
+}
+
+#[test]
+fn test_image_mime_type() {
+    use std::path::Path;
+    assert_eq!(image_mime_type(Path::new("a.png")).unwrap(), "image/png");
+    assert_eq!(
+        image_mime_type(Path::new("dir/b.JPG")).unwrap(),
+        "image/jpeg"
+    );
+    assert!(image_mime_type(Path::new("c.svg")).is_err());
+    assert!(image_mime_type(Path::new("jpeg")).is_err());
+}
+
 // TODO: what should type signature actually be here...
 pub fn update_params_from_fields(fields: &[ArgField], params: &mut HashMap<String, String>) {
     for f in fields.iter() {
@@ -1,4 +1,6 @@
-use adenosine::app_bsky::{FeedPostView, PostView, ThreadPostView};
+use adenosine::app_bsky::{
+    ActorSearchView, FeedPostView, FollowTarget, Notification, PostView, ThreadPostView,
+};
 use anyhow::Result;
 use std::io::Write;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
@@ -150,3 +152,77 @@ pub fn pp_post_view(pv: &PostView) -> Result<()> {
     stdout.reset()?;
     Ok(())
 }
+
+pub fn pp_notification(notif: &Notification) -> Result<()> {
+    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+
+    let action = match notif.reason.as_str() {
+        "like" => "liked your post",
+        "repost" => "reposted your post",
+        "follow" => "followed you",
+        "mention" => "mentioned you",
+        "reply" => "replied to your post",
+        other => other,
+    };
+    stdout.set_color(
+        ColorSpec::new()
+            .set_fg(Some(Color::Yellow))
+            .set_bold(!notif.isRead),
+    )?;
+    write!(&mut stdout, "@{}", notif.author.handle)?;
+    stdout.reset()?;
+    stdout.set_color(ColorSpec::new().set_bold(!notif.isRead))?;
+    write!(&mut stdout, " {:<40}", action)?;
+    stdout.reset()?;
+    stdout.set_color(ColorSpec::new().set_dimmed(true))?;
+    writeln!(&mut stdout, " {}", notif.indexedAt)?;
+    if let Some(subject) = &notif.reasonSubject {
+        stdout.set_color(ColorSpec::new().set_dimmed(true).set_italic(true))?;
+        writeln!(&mut stdout, "{subject}")?;
+    }
+    stdout.reset()?;
+    if let Some(text) = notif.record["text"].as_str() {
+        if notif.reason == "mention" || notif.reason == "reply" {
+            writeln!(&mut stdout, "{text}")?;
+        }
+    }
+    writeln!(&mut stdout)?;
+    Ok(())
+}
+
+pub fn pp_follow_target(ft: &FollowTarget) -> Result<()> {
+    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+
+    stdout.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+    write!(&mut stdout, "@{:<40.40}", ft.handle)?;
+    stdout.reset()?;
+    write!(
+        &mut stdout,
+        " {:<30.30}",
+        ft.displayName.as_deref().unwrap_or("")
+    )?;
+    stdout.set_color(ColorSpec::new().set_dimmed(true))?;
+    writeln!(&mut stdout, " {}", ft.did)?;
+    stdout.reset()?;
+    Ok(())
+}
+
+pub fn pp_actor_search_view(user: &ActorSearchView) -> Result<()> {
+    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+
+    stdout.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+    write!(&mut stdout, "@{:<40.40}", user.handle)?;
+    stdout.reset()?;
+    write!(
+        &mut stdout,
+        " {:<30.30}",
+        user.displayName.as_deref().unwrap_or("")
+    )?;
+    stdout.set_color(ColorSpec::new().set_dimmed(true))?;
+    writeln!(&mut stdout, " {}", user.did)?;
+    stdout.reset()?;
+    if let Some(description) = &user.description {
+        writeln!(&mut stdout, "    {}", description.replace('\n', " "))?;
+    }
+    Ok(())
+}
@@ -1,10 +1,12 @@
 use adenosine::com_atproto;
 use adenosine::crypto::KeyPair;
+use adenosine::identifiers::Did;
 use adenosine::mst;
 use adenosine::repo::RepoStore;
 use adenosine_pds::*;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde_json::json;
+use std::str::FromStr;
 
 use log::{self, debug};
 use structopt::StructOpt;
@@ -72,14 +74,54 @@ enum Command {
         #[structopt(long = "--registration-domain", env = "ATP_PDS_REGISTRATION_DOMAIN")]
         registration_domain: Option<String>,
 
-        /// Optionally, require an invite code to sign up. This is just a single secret value.
-        #[structopt(long = "--invite-code", env = "ATP_PDS_INVITE_CODE")]
-        invite_code: Option<String>,
+        /// Require an invite code to sign up. Use 'create-invite-code' to create codes (admins can
+        /// also use the 'com.atproto.account.createInviteCode' endpoint).
+        #[structopt(long = "--invite-code-required", env = "ATP_PDS_INVITE_CODE_REQUIRED")]
+        invite_code_required: bool,
+
+        /// Password for admin endpoints (HTTP basic auth, with username "admin"). Admin endpoints
+        /// are disabled if not provided.
+        #[structopt(
+            long = "--admin-password",
+            env = "ATP_PDS_ADMIN_PASSWORD",
+            hide_env_values = true
+        )]
+        admin_password: Option<String>,
 
         /// Optionally, override domain name check and force the homepage to display the account
         /// page for this handle
         #[structopt(long = "--homepage-handle", env = "ATP_PDS_HOMEPAGE_HANDLE")]
         homepage_handle: Option<String>,
+
+        /// SMTP relay ("host:port") for outgoing mail (email verification, password resets).
+        /// Plain SMTP, with no TLS or auth. If not provided, mail is printed to stdout instead.
+        #[structopt(long = "--smtp-server", env = "ATP_PDS_SMTP_SERVER")]
+        smtp_server: Option<String>,
+
+        /// "From" address for outgoing mail
+        #[structopt(
+            long = "--mail-from",
+            env = "ATP_PDS_MAIL_FROM",
+            default_value = "adenosine-pds@localhost"
+        )]
+        mail_from: String,
+
+        /// PLC directory server, for resolving did:plc identifiers of remote accounts
+        #[structopt(
+            long = "--plc-host",
+            env = "ATP_PLC_HOST",
+            default_value = "https://plc.directory"
+        )]
+        plc_host: String,
+
+        /// Seconds between syncs of remote repositories followed by local accounts. Set to 0 to
+        /// disable
+        #[structopt(
+            long = "--remote-sync-interval",
+            env = "ATP_PDS_REMOTE_SYNC_INTERVAL",
+            default_value = "300"
+        )]
+        remote_sync_interval: u64,
     },
 
     /// Helper to import an IPLD CARv1 file in to sqlite data store
@@ -128,6 +170,69 @@ enum Command {
         #[structopt(long, short)]
         did_plc: bool,
     },
+
+    /// Crawl and mirror remote repositories, starting from seed DIDs and following links (follows
+    /// and likes) to other accounts. The crawl frontier is persisted, so an interrupted crawl can be
+    /// resumed by running again.
+    Spider {
+        /// DIDs to add to the crawl frontier
+        seeds: Vec<String>,
+
+        /// Number of repositories to fetch in parallel
+        #[structopt(long, default_value = "4")]
+        concurrency: usize,
+
+        /// Milliseconds for each worker to wait between fetches
+        #[structopt(long = "--delay-ms", default_value = "1000")]
+        delay_ms: u64,
+
+        /// Stop after processing this many repositories
+        #[structopt(long = "--max-repos")]
+        max_repos: Option<u64>,
+
+        /// PLC directory server, for resolving did:plc identifiers
+        #[structopt(
+            long = "--plc-host",
+            env = "ATP_PLC_HOST",
+            default_value = "https://plc.directory"
+        )]
+        plc_host: String,
+
+        /// Re-try DIDs which failed in previous runs
+        #[structopt(long = "--retry-errors")]
+        retry_errors: bool,
+    },
+
+    /// Drop and re-generate the application index tables (posts, likes, reposts, follows) from the
+    /// current contents of repositories
+    Reindex {
+        /// DID of the repository to re-index
+        did: Option<String>,
+
+        /// Re-index all repositories (local accounts and synced remote repositories)
+        #[structopt(long)]
+        all: bool,
+    },
+
+    /// Set a new password for an existing account. Also logs out all existing sessions.
+    ResetPassword {
+        #[structopt(long, short)]
+        handle: String,
+
+        #[structopt(long, short)]
+        password: String,
+    },
+
+    /// Create a new invite code (for use with '--invite-code-required'), and print it to stdout
+    CreateInviteCode {
+        /// Number of accounts which can sign up with the code
+        #[structopt(long, default_value = "1")]
+        uses: u64,
+
+        /// Expiry time of the code, as an RFC 3339 timestamp
+        #[structopt(long = "--expires-at")]
+        expires_at: Option<String>,
+    },
 }
 
 fn main() -> Result<()> {
@@ -163,8 +268,13 @@ fn main() -> Result<()> {
             pds_secret_key,
             registration_domain,
             public_url,
-            invite_code,
+            invite_code_required,
+            admin_password,
             homepage_handle,
+            smtp_server,
+            mail_from,
+            plc_host,
+            remote_sync_interval,
         } => {
             let keypair = KeyPair::from_hex(&pds_secret_key)?;
             // clean up config a bit
@@ -182,10 +292,27 @@ fn main() -> Result<()> {
                 listen_host_port: format!("localhost:{port}"),
                 public_url,
                 registration_domain,
-                invite_code,
+                invite_code_required,
+                admin_password,
                 homepage_handle,
+                smtp_server,
+                mail_from,
+                plc_host,
+                remote_sync_interval: match remote_sync_interval {
+                    0 => None,
+                    secs => Some(std::time::Duration::from_secs(secs)),
+                },
             };
-            log::info!("PDS config: {:?}", config);
+            log::info!(
+                "PDS config: {:?}",
+                AtpServiceConfig {
+                    admin_password: config
+                        .admin_password
+                        .as_ref()
+                        .map(|_| "<redacted>".to_string()),
+                    ..config.clone()
+                }
+            );
             let srv = AtpService::new(&opt.blockstore_db_path, &opt.atp_db_path, keypair, config)?;
             srv.run_server()
         }
@@ -216,6 +343,7 @@ fn main() -> Result<()> {
                 password,
                 inviteCode: None,
                 recoveryKey: recovery_key,
+                did: None,
             };
             let config = AtpServiceConfig {
                 public_url: public_url.unwrap_or(format!("https://{handle}")),
@@ -228,5 +356,69 @@ fn main() -> Result<()> {
             println!("{}", json!(sess));
             Ok(())
         }
+        Command::Spider {
+            seeds,
+            concurrency,
+            delay_ms,
+            max_repos,
+            plc_host,
+            retry_errors,
+        } => {
+            let seeds = seeds
+                .iter()
+                .map(|v| Did::from_str(v))
+                .collect::<Result<Vec<Did>>>()?;
+            let config = spider::SpiderConfig {
+                concurrency,
+                delay: std::time::Duration::from_millis(delay_ms),
+                max_repos,
+                plc_host,
+                retry_errors,
+            };
+            // the PDS key isn't used for anything when mirroring, so a throw-away key is fine
+            let srv = AtpService::new(
+                &opt.blockstore_db_path,
+                &opt.atp_db_path,
+                KeyPair::new_random(),
+                Default::default(),
+            )?;
+            spider::run_spider(srv, &seeds, &config)
+        }
+        Command::Reindex { did, all } => {
+            let mut srv = AtpService::new(
+                &opt.blockstore_db_path,
+                &opt.atp_db_path,
+                KeyPair::new_random(),
+                Default::default(),
+            )?;
+            let dids = match (did, all) {
+                (Some(did), false) => vec![Did::from_str(&did)?],
+                (None, true) => srv.atp_db.indexed_repo_dids()?,
+                _ => return Err(anyhow!("pass either a DID, or --all")),
+            };
+            let total = dids.len();
+            let mut failed = 0;
+            for (i, did) in dids.iter().enumerate() {
+                match bsky_reindex(&mut srv, did) {
+                    Ok(count) => println!("[{}/{}] {}: {} records", i + 1, total, did, count),
+                    Err(e) => {
+                        println!("[{}/{}] {}: failed: {}", i + 1, total, did, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(anyhow!("failed to re-index {} of {} repos", failed, total));
+            }
+            Ok(())
+        }
+        Command::ResetPassword { handle, password } => {
+            reset_password(&opt.atp_db_path, &handle, &password)
+        }
+        Command::CreateInviteCode { uses, expires_at } => {
+            let code = create_invite_code(&opt.atp_db_path, uses, expires_at.as_deref())?;
+            println!("{code}");
+            Ok(())
+        }
     }
 }
@@ -1,22 +1,46 @@
 /// ATP database (as distinct from blockstore)
-use crate::{created_at_now, ipld_into_json_value, Did, KeyPair, Tid};
+use crate::{
+    created_at_now, ipld_into_json_value, AtUri, Did, DidOrHost, KeyPair, Nsid, Tid, XrpcError,
+};
 use adenosine::app_bsky;
 use adenosine::com_atproto;
 use anyhow::{anyhow, Result};
 use lazy_static::lazy_static;
 use libipld::cbor::DagCborCodec;
 use libipld::multihash::Code;
-use libipld::{Block, DefaultParams, Ipld};
+use libipld::{Block, Cid, DefaultParams, Ipld};
 use log::debug;
 use rusqlite::{params, Connection, OptionalExtension};
 use rusqlite_migration::{Migrations, M};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::path::PathBuf;
 use std::str::FromStr;
 
 /// Default is 12, but that is quite slow (on my laptop at least)
 const BCRYPT_COST: u32 = 8;
 
+/// How long account deletion confirmation tokens are valid for, as an SQLite date modifier
+const DELETE_TOKEN_EXPIRY: &str = "+15 minutes";
+
+/// Random string for use as a single-use confirmation token
+pub fn random_token() -> String {
+    let random_bytes: [u8; 20] = rand::random();
+    data_encoding::BASE32_NOPAD
+        .encode(&random_bytes)
+        .to_ascii_lowercase()
+}
+
+/// Returns the DID part of an AT URI (if it is a DID, not a handle)
+fn at_uri_did(uri: &str) -> Option<String> {
+    match AtUri::from_str(uri) {
+        Ok(AtUri {
+            repository: DidOrHost::Did(did_type, did_body),
+            ..
+        }) => Some(format!("did:{did_type}:{did_body}")),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -25,11 +49,586 @@ mod tests {
     fn migrations_test() {
         assert!(MIGRATIONS.validate().is_ok());
     }
+
+    #[test]
+    fn update_handle_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let did = Did::from_str("did:plc:dummy").unwrap();
+        db.create_account(
+            &did,
+            "alice.test",
+            "bogus",
+            "alice@bogus.com",
+            "did:key:dummy",
+            None,
+        )
+        .unwrap();
+        db.put_did_doc(
+            &did,
+            "alice.test",
+            &json!({"id": did.to_string(), "alsoKnownAs": ["https://alice.test"]}),
+        )
+        .unwrap();
+        db.update_handle(&did, "alice.example.com").unwrap();
+        assert_eq!(db.resolve_handle("alice.test").unwrap(), None);
+        assert_eq!(
+            db.resolve_handle("alice.example.com").unwrap(),
+            Some(did.clone())
+        );
+        assert_eq!(db.resolve_did(&did).unwrap().unwrap(), "alice.example.com");
+        assert_eq!(
+            db.get_did_doc(&did).unwrap()["alsoKnownAs"][0],
+            "https://alice.example.com"
+        );
+    }
+
+    #[test]
+    fn delete_account_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let did = Did::from_str("did:plc:dummy").unwrap();
+        db.create_account(
+            &did,
+            "alice.test",
+            "bogus",
+            "alice@bogus.com",
+            "did:key:dummy",
+            None,
+        )
+        .unwrap();
+        db.put_did_doc(&did, "alice.test", &json!({"id": did.to_string()}))
+            .unwrap();
+
+        db.set_account_deactivated(&did, true).unwrap();
+        assert!(!db.account_is_active(&did).unwrap());
+        db.set_account_deactivated(&did, false).unwrap();
+        assert!(db.account_is_active(&did).unwrap());
+
+        let token = db.create_delete_token(&did).unwrap();
+        assert!(db.check_delete_account(&did, "wrong", &token).is_err());
+        assert!(db.check_delete_account(&did, "bogus", "wrong").is_err());
+        db.check_delete_account(&did, "bogus", &token).unwrap();
+        assert!(db.delete_account(&did, "wrong", &token, None).is_err());
+        assert!(db.delete_account(&did, "bogus", "wrong", None).is_err());
+        assert!(db.resolve_did(&did).unwrap().is_some());
+
+        db.delete_account(&did, "bogus", &token, None).unwrap();
+        assert_eq!(db.resolve_did(&did).unwrap(), None);
+        assert!(db.get_did_doc(&did).is_err());
+        // tokens are single-use
+        assert!(db.delete_account(&did, "bogus", &token, None).is_err());
+    }
+
+    #[test]
+    fn admin_accounts_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let alice = Did::from_str("did:plc:alice").unwrap();
+        let bob = Did::from_str("did:plc:bob").unwrap();
+        db.create_account(
+            &alice,
+            "alice.test",
+            "bogus",
+            "alice@bogus.com",
+            "did:key:dummy",
+            None,
+        )
+        .unwrap();
+        db.create_account(
+            &bob,
+            "bob_100%.test",
+            "bogus",
+            "bob@bogus.com",
+            "did:key:dummy",
+            None,
+        )
+        .unwrap();
+
+        let all = db.admin_list_accounts(None, 10, None).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].handle, "alice.test");
+        let page = db
+            .admin_list_accounts(None, 10, Some("alice.test"))
+            .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].did, bob.to_string());
+
+        // search terms match handle or email substrings, or exact DID
+        assert_eq!(
+            db.admin_list_accounts(Some("lic"), 10, None).unwrap().len(),
+            1
+        );
+        assert_eq!(
+            db.admin_list_accounts(Some("bob@"), 10, None)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            db.admin_list_accounts(Some("did:plc:alice"), 10, None)
+                .unwrap()
+                .len(),
+            1
+        );
+        // LIKE wildcards are matched literally
+        let found = db.admin_list_accounts(Some("_"), 10, None).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].did, bob.to_string());
+        let found = db.admin_list_accounts(Some("0%."), 10, None).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            db.admin_list_accounts(Some("%"), 10, None).unwrap().len(),
+            1
+        );
+
+        // disabling removes sessions and blocks login, but the account still exists
+        let keypair = KeyPair::new_random();
+        let session = db.create_session("alice.test", "bogus", &keypair).unwrap();
+        assert!(db.account_is_active(&alice).unwrap());
+        db.set_account_disabled(&alice, true).unwrap();
+        assert!(!db.account_is_active(&alice).unwrap());
+        assert_eq!(db.check_auth_token(&session.accessJwt).unwrap(), None);
+        assert!(db.create_session("alice.test", "bogus", &keypair).is_err());
+        assert!(db.admin_get_account(&alice).unwrap().unwrap().disabled);
+        db.set_account_disabled(&alice, false).unwrap();
+        assert!(db.account_is_active(&alice).unwrap());
+        assert!(db.create_session("alice.test", "bogus", &keypair).is_ok());
+
+        assert!(db
+            .set_account_disabled(&Did::from_str("did:plc:unknown").unwrap(), true)
+            .is_err());
+        // non-local DIDs are considered active
+        assert!(db
+            .account_is_active(&Did::from_str("did:plc:unknown").unwrap())
+            .unwrap());
+    }
+
+    #[test]
+    fn account_token_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let did = Did::from_str("did:plc:dummy").unwrap();
+        db.create_account(
+            &did,
+            "alice.test",
+            "bogus",
+            "alice@bogus.com",
+            "did:key:dummy",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            db.resolve_email("Alice@Bogus.com").unwrap(),
+            Some(did.clone())
+        );
+
+        let token = db
+            .create_account_token(&did, "password_reset", "+1 hour")
+            .unwrap();
+        assert_eq!(
+            db.consume_account_token(&token, "email_verify").unwrap(),
+            None
+        );
+        assert_eq!(
+            db.consume_account_token(&token, "password_reset").unwrap(),
+            Some(did.clone())
+        );
+        // single-use
+        assert_eq!(
+            db.consume_account_token(&token, "password_reset").unwrap(),
+            None
+        );
+
+        let expired = db
+            .create_account_token(&did, "password_reset", "-1 minute")
+            .unwrap();
+        assert_eq!(
+            db.consume_account_token(&expired, "password_reset")
+                .unwrap(),
+            None
+        );
+
+        db.set_password(&did, "new-password").unwrap();
+        let keypair = KeyPair::new_random();
+        assert!(db.create_session("alice.test", "bogus", &keypair).is_err());
+        assert!(db
+            .create_session("alice.test", "new-password", &keypair)
+            .is_ok());
+
+        assert_eq!(
+            db.get_account_email(&did).unwrap(),
+            Some(("alice@bogus.com".to_string(), false))
+        );
+        db.set_email_verified(&did).unwrap();
+        assert_eq!(
+            db.get_account_email(&did).unwrap(),
+            Some(("alice@bogus.com".to_string(), true))
+        );
+    }
+
+    #[test]
+    fn invite_code_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let alice_did = Did::from_str("did:plc:alice").unwrap();
+        let bob_did = Did::from_str("did:plc:bob").unwrap();
+        let carol_did = Did::from_str("did:plc:carol").unwrap();
+        let code = db.create_invite_code("admin", 2, None).unwrap();
+
+        assert!(db
+            .create_account(
+                &alice_did,
+                "alice.test",
+                "bogus",
+                "alice@bogus.com",
+                "did:key:dummy",
+                Some("bogus-code"),
+            )
+            .is_err());
+        // failed invite should not have created the account
+        assert_eq!(db.resolve_handle("alice.test").unwrap(), None);
+
+        db.create_account(
+            &alice_did,
+            "alice.test",
+            "bogus",
+            "alice@bogus.com",
+            "did:key:dummy",
+            Some(&code),
+        )
+        .unwrap();
+        db.create_account(
+            &bob_did,
+            "bob.test",
+            "bogus",
+            "bob@bogus.com",
+            "did:key:dummy",
+            Some(&code),
+        )
+        .unwrap();
+        assert!(db
+            .create_account(
+                &carol_did,
+                "carol.test",
+                "bogus",
+                "carol@bogus.com",
+                "did:key:dummy",
+                Some(&code),
+            )
+            .is_err());
+
+        let codes = db.list_invite_codes("admin").unwrap();
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].code, code);
+        assert_eq!(codes[0].available, 2);
+        assert_eq!(codes[0].uses.len(), 2);
+        assert_eq!(codes[0].uses[0].usedBy, alice_did.to_string());
+        assert!(db
+            .list_invite_codes(&alice_did.to_string())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn webhook_outbox_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        // no webhooks configured: nothing gets queued
+        db.enqueue_webhook_payloads(&[json!({"operation": "create"})])
+            .unwrap();
+        assert!(db.due_webhook_deliveries(10).unwrap().is_empty());
+
+        let id = db
+            .create_webhook("https://hooks.example.com/atp", "secret")
+            .unwrap();
+        db.enqueue_webhook_payloads(&[
+            json!({"operation": "create"}),
+            json!({"operation": "delete"}),
+        ])
+        .unwrap();
+        let due = db.due_webhook_deliveries(10).unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].url, "https://hooks.example.com/atp");
+        assert_eq!(due[0].secret, "secret");
+        assert_eq!(due[0].attempts, 0);
+
+        db.complete_webhook_delivery(due[0].id).unwrap();
+        // retry in the future is no longer due
+        db.fail_webhook_delivery(due[1].id, "HTTP 500", Some("+1 hour"))
+            .unwrap();
+        assert!(db.due_webhook_deliveries(10).unwrap().is_empty());
+        let hooks = db.list_webhooks().unwrap();
+        assert_eq!(hooks.len(), 1);
+        assert_eq!(hooks[0].pendingDeliveries, 1);
+
+        // given up deliveries are kept, but never due
+        db.fail_webhook_delivery(due[1].id, "HTTP 500", None)
+            .unwrap();
+        let hooks = db.list_webhooks().unwrap();
+        assert_eq!(hooks[0].pendingDeliveries, 0);
+        assert_eq!(hooks[0].failedDeliveries, 1);
+
+        assert!(db.delete_webhook(id).unwrap());
+        assert!(!db.delete_webhook(id).unwrap());
+        assert!(db.list_webhooks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn spider_frontier_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let alice_did = Did::from_str("did:plc:alice").unwrap();
+        let bob_did = Did::from_str("did:plc:bob").unwrap();
+        let cid =
+            Cid::from_str("bafyreiagt55jzvkenoa4yik77dhomagq2uj26ix4cijj7kd2py2u3s43ve").unwrap();
+
+        assert_eq!(
+            db.spider_add_dids(&[alice_did.clone(), bob_did.clone()])
+                .unwrap(),
+            2
+        );
+        // de-duplicated
+        assert_eq!(
+            db.spider_add_dids(std::slice::from_ref(&alice_did))
+                .unwrap(),
+            0
+        );
+        assert_eq!(db.spider_count("pending").unwrap(), 2);
+
+        let first = db.spider_claim_next().unwrap().unwrap();
+        let second = db.spider_claim_next().unwrap().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(db.spider_claim_next().unwrap(), None);
+        db.spider_finish(&first, Ok(&cid)).unwrap();
+        db.spider_finish(&second, Err("HTTP 404")).unwrap();
+        assert_eq!(db.spider_count("done").unwrap(), 1);
+        assert_eq!(db.spider_count("error").unwrap(), 1);
+
+        // finished DIDs are not re-added
+        assert_eq!(db.spider_add_dids(std::slice::from_ref(&first)).unwrap(), 0);
+        db.spider_reset(false).unwrap();
+        assert_eq!(db.spider_claim_next().unwrap(), None);
+        db.spider_reset(true).unwrap();
+        assert_eq!(db.spider_claim_next().unwrap(), Some(second));
+    }
+
+    #[test]
+    fn record_index_test() {
+        use libipld::ipld;
+
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let did = Did::from_str("did:plc:alice").unwrap();
+        let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+        let other_nsid = Nsid::from_str("com.example.thing").unwrap();
+        let rkeys = ["3jqfcqzm3fo2j", "3jqfcqzm3fp2j", "3jqfcqzm3fq2j"];
+        for rkey in rkeys {
+            db.upsert_record(&did, &post_nsid, rkey, Some(ipld!({"text": rkey})))
+                .unwrap();
+        }
+        db.upsert_record(&did, &other_nsid, rkeys[0], Some(ipld!({"n": 1})))
+            .unwrap();
+        assert_eq!(
+            db.record_collections(&did).unwrap(),
+            vec!["app.bsky.feed.post", "com.example.thing"]
+        );
+
+        let list_rkeys =
+            |rows: Vec<RecordRow>| -> Vec<String> { rows.into_iter().map(|r| r.rkey).collect() };
+        let rows = db
+            .list_records(&did, &post_nsid, 50, None, None, false)
+            .unwrap();
+        assert_eq!(rows[0].value, json!({"text": rkeys[2]}));
+        assert_eq!(list_rkeys(rows), vec![rkeys[2], rkeys[1], rkeys[0]]);
+        let rows = db
+            .list_records(&did, &post_nsid, 2, None, None, true)
+            .unwrap();
+        assert_eq!(list_rkeys(rows), vec![rkeys[0], rkeys[1]]);
+        let rows = db
+            .list_records(&did, &post_nsid, 50, Some(rkeys[2]), None, false)
+            .unwrap();
+        assert_eq!(list_rkeys(rows), vec![rkeys[1], rkeys[0]]);
+        let rows = db
+            .list_records(&did, &post_nsid, 50, Some(rkeys[2]), Some(rkeys[0]), false)
+            .unwrap();
+        assert_eq!(list_rkeys(rows), vec![rkeys[1]]);
+
+        // updates replace, deletes remove
+        db.upsert_record(&did, &post_nsid, rkeys[1], Some(ipld!({"text": "edited"})))
+            .unwrap();
+        db.upsert_record(&did, &post_nsid, rkeys[2], None).unwrap();
+        let rows = db
+            .list_records(&did, &post_nsid, 50, None, None, false)
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].value, json!({"text": "edited"}));
+
+        db.bsky_delete_did(&did).unwrap();
+        assert!(db.record_collections(&did).unwrap().is_empty());
+    }
+
+    #[test]
+    fn remote_repo_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let alice_did = Did::from_str("did:plc:alice").unwrap();
+        let bob_did = Did::from_str("did:plc:bob").unwrap();
+        let cid =
+            Cid::from_str("bafyreiagt55jzvkenoa4yik77dhomagq2uj26ix4cijj7kd2py2u3s43ve").unwrap();
+        db.create_account(
+            &alice_did,
+            "alice.test",
+            "bogus",
+            "alice@bogus.com",
+            "did:key:dummy",
+            None,
+        )
+        .unwrap();
+        for (tid, subject) in [
+            ("3jqfcqzm3fo2j", "did:plc:bob"),
+            ("3jqfcqzm3fp2j", "did:plc:alice"),
+        ] {
+            db.conn
+                .execute(
+                    "INSERT INTO bsky_follow (did, tid, subject_did, created_at) VALUES (?1, ?2, ?3, ?4)",
+                    params!(alice_did.to_string(), tid, subject, "2022-11-01T00:00:00.000Z"),
+                )
+                .unwrap();
+        }
+        // local accounts are not included
+        assert_eq!(db.remote_followed_dids().unwrap(), vec![bob_did.clone()]);
+
+        assert_eq!(db.get_remote_repo(&bob_did).unwrap(), None);
+        db.set_remote_repo_error(&bob_did, "HTTP 500", true)
+            .unwrap();
+        assert_eq!(
+            db.get_remote_repo(&bob_did).unwrap().unwrap().head_cid,
+            None
+        );
+        let remote = RemoteRepo {
+            did: bob_did.clone(),
+            handle: Some("bob.example.com".to_string()),
+            pds_url: Some("https://pds.example.com".to_string()),
+            head_cid: Some(cid),
+        };
+        db.put_remote_repo(&remote).unwrap();
+        assert_eq!(db.get_remote_repo(&bob_did).unwrap(), Some(remote.clone()));
+        db.set_remote_repo_error(&bob_did, "HTTP 500", false)
+            .unwrap();
+        assert_eq!(db.get_remote_repo(&bob_did).unwrap(), Some(remote));
+        db.set_remote_repo_error(&bob_did, "bad commit", true)
+            .unwrap();
+        assert_eq!(
+            db.get_remote_repo(&bob_did).unwrap().unwrap().head_cid,
+            None
+        );
+    }
+
+    #[test]
+    fn repo_seq_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let did = Did::from_str("did:plc:dummy").unwrap();
+        let cid_a =
+            Cid::from_str("bafyreiagt55jzvkenoa4yik77dhomagq2uj26ix4cijj7kd2py2u3s43ve").unwrap();
+        let cid_b =
+            Cid::from_str("bafyreicivoa3p3ttcebdn2zfkdzenkd2uk3gxxlaz43qvueeip6yysvq2m").unwrap();
+        assert_eq!(db.latest_repo_seq().unwrap(), 0);
+        let ops = json!([{"action": "create", "path": "app.bsky.feed.post/3jqfcqzm3fo2j", "cid": cid_a.to_string()}]);
+        let seq1 = db
+            .sequence_commit(&did, &cid_a, None, &ops, b"car-a")
+            .unwrap();
+        let seq2 = db
+            .sequence_commit(&did, &cid_b, Some(&cid_a), &json!([]), b"car-b")
+            .unwrap();
+        assert!(seq2 > seq1);
+        assert_eq!(db.latest_repo_seq().unwrap(), seq2);
+
+        let events = db.repo_events_since(0, 100).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].commit_cid, cid_a);
+        assert_eq!(events[0].prev_cid, None);
+        assert_eq!(events[0].ops, ops);
+        assert_eq!(events[1].prev_cid, Some(cid_a));
+        assert_eq!(events[1].blocks, b"car-b".to_vec());
+
+        let events = db.repo_events_since(seq1, 100).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].seq, seq2);
+        assert!(db.repo_events_since(seq2, 100).unwrap().is_empty());
+    }
+
+    #[test]
+    fn blob_meta_test() {
+        let mut db = AtpDatabase::open_ephemeral().unwrap();
+        let alice = Did::from_str("did:plc:alice").unwrap();
+        let bob = Did::from_str("did:plc:bob").unwrap();
+        let cid =
+            Cid::from_str("bafkreibme22gw2h7y2h7tg2fhqotaqjucnbc24deqo72b6mkl2egezxhvy").unwrap();
+        assert_eq!(db.get_blob_mime_type(&alice, &cid).unwrap(), None);
+        db.put_blob_meta(&alice, &cid, "image/png", 1234).unwrap();
+        assert_eq!(
+            db.get_blob_mime_type(&alice, &cid).unwrap(),
+            Some("image/png".to_string())
+        );
+        // blobs are only served in the context of the account which uploaded them
+        assert_eq!(db.get_blob_mime_type(&bob, &cid).unwrap(), None);
+    }
 }
 
 lazy_static! {
-    static ref MIGRATIONS: Migrations<'static> =
-        Migrations::new(vec![M::up(include_str!("atp_db.sql")),]);
+    static ref MIGRATIONS: Migrations<'static> = Migrations::new(vec![
+        M::up(include_str!("atp_db.sql")),
+        M::up(include_str!("migrations/0002_did_doc_handle.sql")),
+        M::up(include_str!("migrations/0003_invite_code.sql")),
+        M::up(include_str!("migrations/0004_account_disabled.sql")),
+        M::up(include_str!(
+            "migrations/0005_account_deactivate_delete.sql"
+        )),
+        M::up(include_str!("migrations/0006_account_token.sql")),
+        M::up(include_str!("migrations/0007_repo_seq.sql")),
+        M::up(include_str!("migrations/0008_webhook.sql")),
+        M::up(include_str!("migrations/0009_spider_frontier.sql")),
+        M::up(include_str!("migrations/0010_remote_repo.sql")),
+        M::up(include_str!("migrations/0011_record.sql")),
+        M::up(include_str!("migrations/0012_bsky_notification.sql")),
+        M::up(include_str!("migrations/0013_bsky_actor_search.sql")),
+        M::up(include_str!("migrations/0014_bsky_post_search.sql")),
+        M::up(include_str!("migrations/0015_blob.sql")),
+        M::up(include_str!("migrations/0016_record_backfill.sql")),
+    ]);
+}
+
+/// A single sequenced repository commit, as stored in the `repo_seq` table
+#[derive(Debug, Clone)]
+pub struct RepoEvent {
+    pub seq: u64,
+    pub did: Did,
+    pub commit_cid: Cid,
+    pub prev_cid: Option<Cid>,
+    pub ops: Value,
+    pub blocks: Vec<u8>,
+    pub time: String,
+}
+
+/// A pending webhook delivery from the outbox, joined with its webhook configuration
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: u64,
+    pub url: String,
+    pub secret: String,
+    pub payload_json: String,
+    pub attempts: u32,
+}
+
+/// Sync state for a remote repository followed by local accounts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRepo {
+    pub did: Did,
+    pub handle: Option<String>,
+    pub pds_url: Option<String>,
+    pub head_cid: Option<Cid>,
+}
+
+/// Row from the generic `record` index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordRow {
+    pub collection: Nsid,
+    pub rkey: String,
+    pub cid: Cid,
+    pub value: Value,
+    pub indexed_at: String,
 }
 
 #[derive(Debug)]
@@ -77,6 +676,10 @@ impl AtpDatabase {
         Ok(count > 0)
     }
 
+    /// Inserts a new account row.
+    ///
+    /// If an invite code is passed, it is consumed in the same transaction, and the account is not
+    /// created if the code is invalid, expired, or used up.
     pub fn create_account(
         &mut self,
         did: &Did,
@@ -84,22 +687,113 @@ impl AtpDatabase {
         password: &str,
         email: &str,
         recovery_pubkey: &str,
+        invite_code: Option<&str>,
     ) -> Result<()> {
         debug!("bcrypt hashing password (can be slow)...");
         let password_bcrypt = bcrypt::hash(password, BCRYPT_COST)?;
-        let mut stmt = self.conn.prepare_cached(
+        let tx = self.conn.transaction()?;
+        tx.execute(
             "INSERT INTO account (handle, password_bcrypt, email, did, recovery_pubkey) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params!(
+                handle,
+                password_bcrypt,
+                email,
+                did.to_string(),
+                recovery_pubkey,
+            ),
         )?;
-        stmt.execute(params!(
-            handle,
-            password_bcrypt,
-            email,
-            did.to_string(),
-            recovery_pubkey,
-        ))?;
+        if let Some(code) = invite_code {
+            // the conditional UPDATE is what keeps codes from being used more than allowed
+            let count = tx.execute(
+                "UPDATE invite_code SET use_count = use_count + 1 WHERE code = ?1 AND disabled = 0 AND use_count < max_uses AND (expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))",
+                params!(code),
+            )?;
+            if count != 1 {
+                Err(XrpcError::Forbidden(
+                    "invite code is not valid, expired, or used up".to_string(),
+                ))?;
+            }
+            tx.execute(
+                "INSERT INTO invite_code_use (code, used_by_did) VALUES (?1, ?2)",
+                params!(code, did.to_string()),
+            )?;
+        }
+        tx.commit()?;
+        self.bsky_update_actor_search(did)?;
+        Ok(())
+    }
+
+    /// Checks that an invite code could currently be used, without consuming it
+    pub fn check_invite_code(&mut self, code: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT COUNT(*) FROM invite_code WHERE code = ?1 AND disabled = 0 AND use_count < max_uses AND (expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))",
+        )?;
+        let count: u64 = stmt.query_row(params!(code), |row| row.get(0))?;
+        if count != 1 {
+            Err(XrpcError::Forbidden(
+                "invite code is not valid, expired, or used up".to_string(),
+            ))?;
+        }
         Ok(())
     }
 
+    /// Creates a new random invite code, returning the code string.
+    ///
+    /// `creator` is either the DID of the account the code is for, or "admin".
+    pub fn create_invite_code(
+        &mut self,
+        creator: &str,
+        max_uses: u64,
+        expires_at: Option<&str>,
+    ) -> Result<String> {
+        let random_bytes: [u8; 10] = rand::random();
+        let enc = data_encoding::BASE32_NOPAD
+            .encode(&random_bytes)
+            .to_ascii_lowercase();
+        let code = format!("{}-{}", &enc[0..8], &enc[8..16]);
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO invite_code (code, creator, max_uses, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        stmt.execute(params!(code, creator, max_uses, expires_at))?;
+        Ok(code)
+    }
+
+    /// Lists all invite codes created for the given creator (DID or "admin"), newest first,
+    /// including which accounts used them.
+    pub fn list_invite_codes(&mut self, creator: &str) -> Result<Vec<com_atproto::InviteCode>> {
+        let mut codes: Vec<com_atproto::InviteCode> = vec![];
+        {
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT code, max_uses, disabled, expires_at, created_at FROM invite_code WHERE creator = ?1 ORDER BY created_at DESC",
+            )?;
+            let mut sql_rows = stmt.query(params!(creator))?;
+            while let Some(row) = sql_rows.next()? {
+                codes.push(com_atproto::InviteCode {
+                    code: row.get(0)?,
+                    available: row.get(1)?,
+                    disabled: row.get(2)?,
+                    createdBy: creator.to_string(),
+                    expiresAt: row.get(3)?,
+                    createdAt: row.get(4)?,
+                    uses: vec![],
+                });
+            }
+        }
+        for code in codes.iter_mut() {
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT used_by_did, used_at FROM invite_code_use WHERE code = ?1 ORDER BY used_at ASC, rowid ASC",
+            )?;
+            let mut sql_rows = stmt.query(params!(code.code))?;
+            while let Some(row) = sql_rows.next()? {
+                code.uses.push(com_atproto::InviteCodeUse {
+                    usedBy: row.get(0)?,
+                    usedAt: row.get(1)?,
+                });
+            }
+        }
+        Ok(codes)
+    }
+
     /// Returns a JWT session token
     pub fn create_session(
         &mut self,
@@ -107,14 +801,21 @@ impl AtpDatabase {
         password: &str,
         keypair: &KeyPair,
     ) -> Result<com_atproto::Session> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT did, password_bcrypt FROM account WHERE handle = ?1")?;
-        let (did_col, password_bcrypt): (String, String) =
-            stmt.query_row(params!(handle), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT did, password_bcrypt, disabled FROM account WHERE handle = ?1",
+        )?;
+        let (did_col, password_bcrypt, disabled): (String, String, bool) = stmt
+            .query_row(params!(handle), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
         if !bcrypt::verify(password, &password_bcrypt)? {
             return Err(anyhow!("password did not match"));
         }
+        if disabled {
+            Err(XrpcError::Forbidden(
+                "account has been disabled".to_string(),
+            ))?;
+        }
         let did = Did::from_str(&did_col)?;
         let jwt = keypair.ucan(&did)?;
         let mut stmt = self
@@ -129,6 +830,93 @@ impl AtpDatabase {
         })
     }
 
+    /// Looks up local DID associated with an email address
+    pub fn resolve_email(&mut self, email: &str) -> Result<Option<Did>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT did FROM account WHERE lower(email) = lower($1)")?;
+        let did_maybe: Option<String> = stmt
+            .query_row(params!(email), |row| row.get(0))
+            .optional()?;
+        Ok(did_maybe.map(|v| Did::from_str(&v).expect("valid DID in database")))
+    }
+
+    /// Creates a new single-use token for the account. `purpose` is a short string like
+    /// "password_reset", and `expiry` is an SQLite date modifier like "+1 hour".
+    pub fn create_account_token(
+        &mut self,
+        did: &Did,
+        purpose: &str,
+        expiry: &str,
+    ) -> Result<String> {
+        let token = random_token();
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO account_token (token, did, purpose, expires_at) VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?4))",
+        )?;
+        stmt.execute(params!(token, did.to_string(), purpose, expiry))?;
+        Ok(token)
+    }
+
+    /// Consumes a token, returning the DID it was created for. Returns None if the token does not
+    /// exist, has already been used, has expired, or is for a different purpose.
+    pub fn consume_account_token(&mut self, token: &str, purpose: &str) -> Result<Option<Did>> {
+        let mut stmt = self.conn.prepare_cached(
+            "DELETE FROM account_token WHERE token = ?1 AND purpose = ?2 AND expires_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now') RETURNING did",
+        )?;
+        let did_maybe: Option<String> = stmt
+            .query_row(params!(token, purpose), |row| row.get(0))
+            .optional()?;
+        Ok(did_maybe.map(|v| Did::from_str(&v).expect("valid DID in database")))
+    }
+
+    /// Sets a new password for the account, and removes all existing sessions (and any other
+    /// outstanding password reset tokens).
+    pub fn set_password(&mut self, did: &Did, password: &str) -> Result<()> {
+        debug!("bcrypt hashing password (can be slow)...");
+        let password_bcrypt = bcrypt::hash(password, BCRYPT_COST)?;
+        let tx = self.conn.transaction()?;
+        let count = tx.execute(
+            "UPDATE account SET password_bcrypt = ?1 WHERE did = ?2",
+            params!(password_bcrypt, did.to_string()),
+        )?;
+        if count != 1 {
+            Err(XrpcError::NotFound(format!(
+                "no local account for DID: {did}"
+            )))?;
+        }
+        tx.execute(
+            "DELETE FROM session WHERE did = ?1",
+            params!(did.to_string()),
+        )?;
+        tx.execute(
+            "DELETE FROM account_token WHERE did = ?1 AND purpose = 'password_reset'",
+            params!(did.to_string()),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn set_email_verified(&mut self, did: &Did) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("UPDATE account SET email_verified = 1 WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        Ok(())
+    }
+
+    /// Returns the (email, email_verified) for a local account
+    pub fn get_account_email(&mut self, did: &Did) -> Result<Option<(String, bool)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT email, email_verified FROM account WHERE did = ?1")?;
+        let row = stmt
+            .query_row(params!(did.to_string()), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+        Ok(row)
+    }
+
     /// Returns the DID that a token is valid for, or None if session not found
     pub fn check_auth_token(&mut self, jwt: &str) -> Result<Option<Did>> {
         let mut stmt = self
@@ -139,6 +927,206 @@ impl AtpDatabase {
         Ok(did_maybe.map(|v| Did::from_str(&v).expect("valid DID in database")))
     }
 
+    /// Whether the repository for a DID should be served publicly: false if the account has been
+    /// disabled by an admin, or deactivated by the user. True for non-local DIDs.
+    pub fn account_is_active(&mut self, did: &Did) -> Result<bool> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT disabled, deactivated FROM account WHERE did = $1")?;
+        let flags: Option<(bool, bool)> = stmt
+            .query_row(params!(did.to_string()), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+        Ok(match flags {
+            Some((disabled, deactivated)) => !(disabled || deactivated),
+            None => true,
+        })
+    }
+
+    /// Deactivates (or re-activates) a local account. Unlike admin disabling, the user can still
+    /// log in to a deactivated account, so that they are able to re-activate it.
+    pub fn set_account_deactivated(&mut self, did: &Did, deactivated: bool) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("UPDATE account SET deactivated = ?1 WHERE did = ?2")?;
+        let count = stmt.execute(params!(deactivated, did.to_string()))?;
+        if count != 1 {
+            Err(XrpcError::NotFound(format!(
+                "no local account for DID: {did}"
+            )))?;
+        }
+        Ok(())
+    }
+
+    /// Creates a short-lived token which must be passed to `delete_account()`, replacing any
+    /// existing token for the account.
+    pub fn create_delete_token(&mut self, did: &Did) -> Result<String> {
+        let token = random_token();
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO account_delete_token (did, token, expires_at) VALUES (?1, ?2, strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?3))",
+        )?;
+        stmt.execute(params!(did.to_string(), token, DELETE_TOKEN_EXPIRY))?;
+        Ok(token)
+    }
+
+    /// Checks the password and confirmation token for `delete_account()`, without using up the
+    /// token.
+    pub fn check_delete_account(&self, did: &Did, password: &str, token: &str) -> Result<()> {
+        let password_bcrypt: Option<String> = self
+            .conn
+            .prepare_cached("SELECT password_bcrypt FROM account WHERE did = ?1")?
+            .query_row(params!(did.to_string()), |row| row.get(0))
+            .optional()?;
+        let password_bcrypt = match password_bcrypt {
+            Some(v) => v,
+            None => Err(XrpcError::NotFound(format!(
+                "no local account for DID: {did}"
+            )))?,
+        };
+        if !bcrypt::verify(password, &password_bcrypt)? {
+            Err(XrpcError::Forbidden("password did not match".to_string()))?;
+        }
+        let token_valid: bool = self
+            .conn
+            .prepare_cached("SELECT EXISTS (SELECT 1 FROM account_delete_token WHERE did = ?1 AND token = ?2 AND expires_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))")?
+            .query_row(params!(did.to_string(), token), |row| row.get(0))?;
+        if !token_valid {
+            Err(XrpcError::Forbidden(
+                "account deletion token is not valid or has expired".to_string(),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Permanently removes a local account, after checking the password and confirmation token.
+    ///
+    /// Sessions, the DID document, and all bsky index rows for the account are deleted, and a
+    /// tombstone row is recorded for the DID (with the signed did:plc operation, if any). The
+    /// repository blocks are not touched here; see `RepoStore::unlink_repo()`.
+    pub fn delete_account(
+        &mut self,
+        did: &Did,
+        password: &str,
+        token: &str,
+        plc_op: Option<&Value>,
+    ) -> Result<()> {
+        self.check_delete_account(did, password, token)?;
+
+        let tx = self.conn.transaction()?;
+        let count = tx.execute(
+            "DELETE FROM account_delete_token WHERE did = ?1 AND token = ?2 AND expires_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now')",
+            params!(did.to_string(), token),
+        )?;
+        if count != 1 {
+            Err(XrpcError::Forbidden(
+                "account deletion token is not valid or has expired".to_string(),
+            ))?;
+        }
+        for table in [
+            "session",
+            "account",
+            "did_doc",
+            "bsky_post",
+            "bsky_ref",
+            "bsky_follow",
+            "bsky_actor_fts",
+            "bsky_post_fts",
+            "blob",
+        ] {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE did = ?1"),
+                params!(did.to_string()),
+            )?;
+        }
+        tx.execute(
+            "DELETE FROM bsky_notification WHERE user_did = ?1",
+            params!(did.to_string()),
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO did_tombstone (did, plc_op_json) VALUES (?1, ?2)",
+            params!(did.to_string(), plc_op.map(|v| v.to_string())),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Disables (or re-enables) a local account. Disabling also removes any existing sessions.
+    pub fn set_account_disabled(&mut self, did: &Did, disabled: bool) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        let count = tx.execute(
+            "UPDATE account SET disabled = ?1 WHERE did = ?2",
+            params!(disabled, did.to_string()),
+        )?;
+        if count != 1 {
+            Err(XrpcError::NotFound(format!(
+                "no local account for DID: {did}"
+            )))?;
+        }
+        if disabled {
+            tx.execute(
+                "DELETE FROM session WHERE did = ?1",
+                params!(did.to_string()),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Lists local accounts in handle order, optionally filtered by a partial handle, email, or
+    /// exact DID. The cursor is the last handle of the previous page.
+    pub fn admin_list_accounts(
+        &mut self,
+        term: Option<&str>,
+        limit: u64,
+        cursor: Option<&str>,
+    ) -> Result<Vec<com_atproto::admin::AccountView>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT did, handle, email, disabled, created_at FROM account WHERE handle > ?1 AND (?2 IS NULL OR handle LIKE ?2 ESCAPE '\\' OR email LIKE ?2 ESCAPE '\\' OR did = ?3) ORDER BY handle ASC LIMIT ?4",
+        )?;
+        // escape LIKE wildcards, so the term only matches literally
+        let like_term = term.map(|t| {
+            let t = t
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{t}%")
+        });
+        let mut sql_rows = stmt.query(params!(cursor.unwrap_or(""), like_term, term, limit))?;
+        let mut accounts = vec![];
+        while let Some(row) = sql_rows.next()? {
+            accounts.push(com_atproto::admin::AccountView {
+                did: row.get(0)?,
+                handle: row.get(1)?,
+                email: row.get(2)?,
+                disabled: row.get(3)?,
+                createdAt: row.get(4)?,
+            });
+        }
+        Ok(accounts)
+    }
+
+    pub fn admin_get_account(
+        &mut self,
+        did: &Did,
+    ) -> Result<Option<com_atproto::admin::AccountView>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT did, handle, email, disabled, created_at FROM account WHERE did = ?1",
+        )?;
+        let account = stmt
+            .query_row(params!(did.to_string()), |row| {
+                Ok(com_atproto::admin::AccountView {
+                    did: row.get(0)?,
+                    handle: row.get(1)?,
+                    email: row.get(2)?,
+                    disabled: row.get(3)?,
+                    createdAt: row.get(4)?,
+                })
+            })
+            .optional()?;
+        Ok(account)
+    }
+
     /// Looks up local account handle associated with a DID
     ///
     /// TODO: remote lookups, and/or local cache of external DIDs
@@ -173,11 +1161,11 @@ impl AtpDatabase {
         Ok(count >= 1)
     }
 
-    pub fn put_did_doc(&mut self, did: &Did, did_doc: &Value) -> Result<()> {
+    pub fn put_did_doc(&mut self, did: &Did, handle: &str, did_doc: &Value) -> Result<()> {
         let mut stmt = self
             .conn
-            .prepare_cached("INSERT INTO did_doc (did, doc_json) VALUES (?1, ?2)")?;
-        stmt.execute(params!(did.to_string(), did_doc.to_string()))?;
+            .prepare_cached("INSERT INTO did_doc (did, handle, doc_json) VALUES (?1, ?2, ?3)")?;
+        stmt.execute(params!(did.to_string(), handle, did_doc.to_string()))?;
         Ok(())
     }
     pub fn get_did_doc(&mut self, did: &Did) -> Result<Value> {
@@ -188,58 +1176,637 @@ impl AtpDatabase {
         Ok(Value::from_str(&doc_json)?)
     }
 
-    pub fn bsky_upsert_post(&mut self, did: &Did, tid: &Tid, val: Option<Ipld>) -> Result<()> {
+    /// Switches a local account to a new handle.
+    ///
+    /// Updates the account row, and the `alsoKnownAs` field (and handle column) of the stored DID
+    /// document, in a single transaction. Does not check if the new handle is valid or verified.
+    pub fn update_handle(&mut self, did: &Did, handle: &str) -> Result<()> {
+        let mut did_doc = self.get_did_doc(did)?;
+        did_doc["alsoKnownAs"] = json!([format!("https://{handle}")]);
+        let tx = self.conn.transaction()?;
+        let count = tx.execute(
+            "UPDATE account SET handle = ?1 WHERE did = ?2",
+            params!(handle, did.to_string()),
+        )?;
+        if count != 1 {
+            return Err(anyhow!("no local account for DID: {}", did));
+        }
+        tx.execute(
+            "UPDATE did_doc SET handle = ?1, doc_json = ?2 WHERE did = ?3",
+            params!(handle, did_doc.to_string(), did.to_string()),
+        )?;
+        tx.commit()?;
+        self.bsky_update_actor_search(did)?;
+        Ok(())
+    }
+
+    /// Appends a repository commit to the event sequence, returning the new sequence number
+    pub fn sequence_commit(
+        &mut self,
+        did: &Did,
+        commit_cid: &Cid,
+        prev_cid: Option<&Cid>,
+        ops: &Value,
+        blocks: &[u8],
+    ) -> Result<u64> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO repo_seq (did, commit_cid, prev_cid, ops_json, blocks) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        stmt.execute(params!(
+            did.to_string(),
+            commit_cid.to_string(),
+            prev_cid.map(|c| c.to_string()),
+            ops.to_string(),
+            blocks,
+        ))?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    /// Returns sequenced commit events with sequence number strictly greater than `cursor`, in
+    /// order, up to `limit` events.
+    pub fn repo_events_since(&mut self, cursor: u64, limit: u64) -> Result<Vec<RepoEvent>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT seq, did, commit_cid, prev_cid, ops_json, blocks, created_at FROM repo_seq WHERE seq > ?1 ORDER BY seq ASC LIMIT ?2",
+        )?;
+        let mut rows = stmt.query(params!(cursor, limit))?;
+        let mut events = vec![];
+        while let Some(row) = rows.next()? {
+            let prev_cid: Option<String> = row.get(3)?;
+            let ops_json: String = row.get(4)?;
+            events.push(RepoEvent {
+                seq: row.get(0)?,
+                did: Did::from_str(&row.get::<_, String>(1)?)?,
+                commit_cid: Cid::from_str(&row.get::<_, String>(2)?)?,
+                prev_cid: prev_cid.map(|v| Cid::from_str(&v)).transpose()?,
+                ops: serde_json::from_str(&ops_json)?,
+                blocks: row.get(5)?,
+                time: row.get(6)?,
+            });
+        }
+        Ok(events)
+    }
+
+    /// Most recent event sequence number, or zero if there are no events yet
+    pub fn latest_repo_seq(&mut self) -> Result<u64> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT COALESCE(MAX(seq), 0) FROM repo_seq")?;
+        let seq: u64 = stmt.query_row([], |row| row.get(0))?;
+        Ok(seq)
+    }
+
+    /// Creates a new webhook subscription, returning the ID
+    pub fn create_webhook(&mut self, url: &str, secret: &str) -> Result<u64> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("INSERT INTO webhook (url, secret) VALUES (?1, ?2)")?;
+        stmt.execute(params!(url, secret))?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    pub fn list_webhooks(&mut self) -> Result<Vec<com_atproto::admin::WebhookView>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT webhook.id, webhook.url, webhook.created_at, (SELECT COUNT(*) FROM webhook_outbox WHERE webhook_id = webhook.id AND next_attempt_at IS NOT NULL), (SELECT COUNT(*) FROM webhook_outbox WHERE webhook_id = webhook.id AND next_attempt_at IS NULL) FROM webhook ORDER BY webhook.id ASC",
+        )?;
+        let hook_iter = stmt.query_map([], |row| {
+            Ok(com_atproto::admin::WebhookView {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                createdAt: row.get(2)?,
+                pendingDeliveries: row.get(3)?,
+                failedDeliveries: row.get(4)?,
+            })
+        })?;
+        let mut hooks = vec![];
+        for hook in hook_iter {
+            hooks.push(hook?);
+        }
+        Ok(hooks)
+    }
+
+    /// Removes a webhook subscription, along with any undelivered payloads. Returns false if the
+    /// webhook did not exist.
+    pub fn delete_webhook(&mut self, id: u64) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM webhook_outbox WHERE webhook_id = ?1",
+            params!(id),
+        )?;
+        let count = tx.execute("DELETE FROM webhook WHERE id = ?1", params!(id))?;
+        tx.commit()?;
+        Ok(count > 0)
+    }
+
+    /// Queues a copy of each payload for delivery to every configured webhook
+    pub fn enqueue_webhook_payloads(&mut self, payloads: &[Value]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO webhook_outbox (webhook_id, payload_json) SELECT id, ?1 FROM webhook",
+            )?;
+            for payload in payloads {
+                stmt.execute(params!(payload.to_string()))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns outbox deliveries which are due to be attempted, oldest first
+    pub fn due_webhook_deliveries(&mut self, limit: u64) -> Result<Vec<WebhookDelivery>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT webhook_outbox.id, webhook.url, webhook.secret, webhook_outbox.payload_json, webhook_outbox.attempts FROM webhook_outbox JOIN webhook ON webhook.id = webhook_outbox.webhook_id WHERE webhook_outbox.next_attempt_at <= strftime('%Y-%m-%dT%H:%M:%fZ', 'now') ORDER BY webhook_outbox.id ASC LIMIT ?1",
+        )?;
+        let delivery_iter = stmt.query_map(params!(limit), |row| {
+            Ok(WebhookDelivery {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                secret: row.get(2)?,
+                payload_json: row.get(3)?,
+                attempts: row.get(4)?,
+            })
+        })?;
+        let mut deliveries = vec![];
+        for delivery in delivery_iter {
+            deliveries.push(delivery?);
+        }
+        Ok(deliveries)
+    }
+
+    /// Removes a successfully delivered payload from the outbox
+    pub fn complete_webhook_delivery(&mut self, id: u64) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM webhook_outbox WHERE id = ?1")?;
+        stmt.execute(params!(id))?;
+        Ok(())
+    }
+
+    /// Records a failed delivery attempt. `retry_delay` is an SQLite date modifier like "+30
+    /// seconds"; if None, the delivery is given up on (but kept in the outbox for inspection).
+    pub fn fail_webhook_delivery(
+        &mut self,
+        id: u64,
+        error: &str,
+        retry_delay: Option<&str>,
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "UPDATE webhook_outbox SET attempts = attempts + 1, last_error = ?2, next_attempt_at = CASE WHEN ?3 IS NULL THEN NULL ELSE strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?3) END WHERE id = ?1",
+        )?;
+        stmt.execute(params!(id, error, retry_delay))?;
+        Ok(())
+    }
+
+    /// Adds DIDs to the spider frontier, if they are not already in it (in any state). Returns
+    /// the number of newly added DIDs.
+    pub fn spider_add_dids(&mut self, dids: &[Did]) -> Result<u64> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("INSERT OR IGNORE INTO spider_frontier (did) VALUES (?1)")?;
+        let mut count = 0;
+        for did in dids {
+            count += stmt.execute(params!(did.to_string()))? as u64;
+        }
+        Ok(count)
+    }
+
+    /// Puts DIDs which were being fetched when a previous crawl was interrupted back in the
+    /// pending state. Optionally also retries DIDs which failed.
+    pub fn spider_reset(&mut self, retry_errors: bool) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "UPDATE spider_frontier SET status = 'pending' WHERE status = 'in_progress' OR (?1 AND status = 'error')",
+        )?;
+        stmt.execute(params!(retry_errors))?;
+        Ok(())
+    }
+
+    /// Marks the oldest pending DID in the spider frontier as in progress, and returns it
+    pub fn spider_claim_next(&mut self) -> Result<Option<Did>> {
+        let mut stmt = self.conn.prepare_cached(
+            "UPDATE spider_frontier SET status = 'in_progress', updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE did = (SELECT did FROM spider_frontier WHERE status = 'pending' ORDER BY added_at ASC, did ASC LIMIT 1) RETURNING did",
+        )?;
+        let did_maybe: Option<String> = stmt.query_row([], |row| row.get(0)).optional()?;
+        Ok(did_maybe.map(|v| Did::from_str(&v).expect("valid DID in database")))
+    }
+
+    /// Records the outcome of crawling a DID: either the imported commit, or an error message
+    pub fn spider_finish(&mut self, did: &Did, result: Result<&Cid, &str>) -> Result<()> {
+        let (status, commit_cid, error) = match result {
+            Ok(cid) => ("done", Some(cid.to_string()), None),
+            Err(msg) => ("error", None, Some(msg)),
+        };
+        let mut stmt = self.conn.prepare_cached(
+            "UPDATE spider_frontier SET status = ?2, commit_cid = COALESCE(?3, commit_cid), last_error = ?4, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE did = ?1",
+        )?;
+        stmt.execute(params!(did.to_string(), status, commit_cid, error))?;
+        Ok(())
+    }
+
+    /// Number of DIDs in the spider frontier with the given status
+    pub fn spider_count(&mut self, status: &str) -> Result<u64> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT COUNT(*) FROM spider_frontier WHERE status = ?1")?;
+        let count: u64 = stmt.query_row(params!(status), |row| row.get(0))?;
+        Ok(count)
+    }
+
+    /// DIDs which are followed by any local account, but which are not local accounts themselves
+    pub fn remote_followed_dids(&mut self) -> Result<Vec<Did>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT DISTINCT bsky_follow.subject_did FROM bsky_follow JOIN account ON bsky_follow.did = account.did WHERE bsky_follow.subject_did NOT IN (SELECT did FROM account) ORDER BY bsky_follow.subject_did ASC",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut dids = vec![];
+        while let Some(row) = rows.next()? {
+            let did: String = row.get(0)?;
+            match Did::from_str(&did) {
+                Ok(did) => dids.push(did),
+                Err(_) => debug!("skipping invalid followed DID: {}", did),
+            }
+        }
+        Ok(dids)
+    }
+
+    pub fn get_remote_repo(&mut self, did: &Did) -> Result<Option<RemoteRepo>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT handle, pds_url, head_cid FROM remote_repo WHERE did = ?1")?;
+        let row: Option<(Option<String>, Option<String>, Option<String>)> = stmt
+            .query_row(params!(did.to_string()), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()?;
+        Ok(match row {
+            None => None,
+            Some((handle, pds_url, head_cid)) => Some(RemoteRepo {
+                did: did.clone(),
+                handle,
+                pds_url,
+                head_cid: head_cid.map(|v| Cid::from_str(&v)).transpose()?,
+            }),
+        })
+    }
+
+    /// Records a successful sync of a remote repository
+    pub fn put_remote_repo(&mut self, repo: &RemoteRepo) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO remote_repo (did, handle, pds_url, head_cid, last_error, checked_at) VALUES (?1, ?2, ?3, ?4, NULL, strftime('%Y-%m-%dT%H:%M:%fZ', 'now')) ON CONFLICT (did) DO UPDATE SET handle = ?2, pds_url = ?3, head_cid = ?4, last_error = NULL, checked_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')",
+        )?;
+        stmt.execute(params!(
+            repo.did.to_string(),
+            repo.handle,
+            repo.pds_url,
+            repo.head_cid.map(|c| c.to_string()),
+        ))?;
+        Ok(())
+    }
+
+    /// Records a failed sync of a remote repository. If `reset_head` is set, the next sync will
+    /// re-fetch and re-index the full repository.
+    pub fn set_remote_repo_error(
+        &mut self,
+        did: &Did,
+        error: &str,
+        reset_head: bool,
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO remote_repo (did, last_error, checked_at) VALUES (?1, ?2, strftime('%Y-%m-%dT%H:%M:%fZ', 'now')) ON CONFLICT (did) DO UPDATE SET last_error = ?2, checked_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), head_cid = CASE WHEN ?3 THEN NULL ELSE head_cid END",
+        )?;
+        stmt.execute(params!(did.to_string(), error, reset_head))?;
+        Ok(())
+    }
+
+    /// Records an uploaded blob (already written to the blockstore) as belonging to an account
+    pub fn put_blob_meta(
+        &mut self,
+        did: &Did,
+        cid: &Cid,
+        mime_type: &str,
+        size: u64,
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO blob (did, cid, mime_type, size) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        stmt.execute(params!(did.to_string(), cid.to_string(), mime_type, size))?;
+        Ok(())
+    }
+
+    /// MIME type of a blob uploaded by the given account, if there is one
+    pub fn get_blob_mime_type(&mut self, did: &Did, cid: &Cid) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT mime_type FROM blob WHERE did = ?1 AND cid = ?2")?;
+        Ok(stmt
+            .query_row(params!(did.to_string(), cid.to_string()), |row| row.get(0))
+            .optional()?)
+    }
+
+    /// All DIDs with a repository in the blockstore that gets indexed: local accounts, plus remote
+    /// repositories which have been synced or mirrored
+    pub fn indexed_repo_dids(&mut self) -> Result<Vec<Did>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT did FROM indexed_repo ORDER BY did ASC")?;
+        let mut rows = stmt.query([])?;
+        let mut dids = vec![];
+        while let Some(row) = rows.next()? {
+            let did: String = row.get(0)?;
+            dids.push(Did::from_str(&did)?);
+        }
+        Ok(dids)
+    }
+
+    /// Indexed repositories (as in `indexed_repo_dids()`) which were indexed before the generic
+    /// record index existed, and haven't been backfilled yet
+    pub fn record_backfill_dids(&mut self) -> Result<Vec<Did>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT did FROM record_backfill ORDER BY did ASC")?;
+        let mut rows = stmt.query([])?;
+        let mut dids = vec![];
+        while let Some(row) = rows.next()? {
+            let did: String = row.get(0)?;
+            dids.push(Did::from_str(&did)?);
+        }
+        Ok(dids)
+    }
+
+    /// Marks a repository as backfilled in the generic record index
+    pub fn mark_record_backfilled(&mut self, did: &Did) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM record_backfill WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        Ok(())
+    }
+
+    /// Removes all index rows (generic and bsky) for a DID
+    pub fn bsky_delete_did(&mut self, did: &Did) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM record WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM bsky_post WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM bsky_post_fts WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM bsky_ref WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM bsky_follow WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        Ok(())
+    }
+
+    /// The (collection, record key) pairs of all records of a DID which have rows in the generic
+    /// record index or any of the bsky tables
+    pub fn bsky_indexed_keys(&self, did: &Did) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare_cached("SELECT collection, rkey FROM record WHERE did = ?1 UNION SELECT 'app.bsky.feed.post', tid FROM bsky_post WHERE did = ?1 UNION SELECT 'app.bsky.feed.' || ref_type, tid FROM bsky_ref WHERE did = ?1 UNION SELECT 'app.bsky.graph.follow', tid FROM bsky_follow WHERE did = ?1")?;
+        let keys = stmt
+            .query_map(params!(did.to_string()), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        Ok(keys)
+    }
+
+    /// Re-writes the actor search index row for a DID, from the account (or remote repo) handle
+    /// and the most recent profile record in the generic index. DIDs which are neither local
+    /// accounts nor have a profile record are left out of the index.
+    pub fn bsky_update_actor_search(&self, did: &Did) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM bsky_actor_fts WHERE did = ?1")?;
+        stmt.execute(params!(did.to_string()))?;
+        let mut stmt = self.conn.prepare_cached("INSERT INTO bsky_actor_fts (did, handle, display_name, description) SELECT ?1, COALESCE((SELECT handle FROM account WHERE did = ?1), (SELECT handle FROM remote_repo WHERE did = ?1), ''), json_extract(profile.record_json, '$.displayName'), json_extract(profile.record_json, '$.description') FROM (SELECT (SELECT record_json FROM record WHERE did = ?1 AND collection = 'app.bsky.actor.profile' ORDER BY rkey DESC LIMIT 1) AS record_json) AS profile WHERE profile.record_json IS NOT NULL OR EXISTS (SELECT 1 FROM account WHERE did = ?1)")?;
+        stmt.execute(params!(did.to_string()))?;
+        Ok(())
+    }
+
+    /// Updates the generic record index for a single record; `None` means the record was deleted
+    pub fn upsert_record(
+        &self,
+        did: &Did,
+        collection: &Nsid,
+        rkey: &str,
+        val: Option<Ipld>,
+    ) -> Result<()> {
         if let Some(val) = val {
-            // need to re-compute the CID from DagCbor re-encoding, I guess. bleh.
             let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &val)?;
             let cid = *block.cid();
-            let post: app_bsky::Post = serde_json::from_value(ipld_into_json_value(val))?;
-            let (reply_to_parent_uri, reply_to_root_uri) = match post.reply {
-                Some(ref reply) => (Some(reply.parent.uri.clone()), Some(reply.root.uri.clone())),
-                None => (None, None),
-            };
             let mut stmt = self
                 .conn
-                .prepare_cached("INSERT INTO bsky_post (did, tid, cid, reply_to_parent_uri, reply_to_root_uri, record_json, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+                .prepare_cached("INSERT INTO record (did, collection, rkey, record_cid, record_json) VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT (did, collection, rkey) DO UPDATE SET record_cid = excluded.record_cid, record_json = excluded.record_json")?;
             stmt.execute(params!(
                 did.to_string(),
-                tid.to_string(),
+                collection.to_string(),
+                rkey,
                 cid.to_string(),
-                reply_to_parent_uri,
-                reply_to_root_uri,
-                serde_json::to_string(&post)?,
-                post.createdAt.unwrap_or_else(created_at_now)
+                serde_json::to_string(&ipld_into_json_value(val))?
             ))?;
         } else {
-            let mut stmt = self
-                .conn
-                .prepare_cached("DELETE FROM bsky_post WHERE did = ?1 AND tid = ?2")?;
-            stmt.execute(params!(did.to_string(), tid.to_string()))?;
+            let mut stmt = self.conn.prepare_cached(
+                "DELETE FROM record WHERE did = ?1 AND collection = ?2 AND rkey = ?3",
+            )?;
+            stmt.execute(params!(did.to_string(), collection.to_string(), rkey))?;
         }
         Ok(())
     }
 
-    pub fn bsky_upsert_ref(
+    /// Lists records in a collection from the generic record index.
+    ///
+    /// Records are sorted by record key, descending (newest first for TIDs) unless `reverse` is
+    /// set. `before` and `after` are exclusive bounds on the record key.
+    pub fn list_records(
         &mut self,
+        did: &Did,
+        collection: &Nsid,
+        limit: u64,
+        before: Option<&str>,
+        after: Option<&str>,
+        reverse: bool,
+    ) -> Result<Vec<RecordRow>> {
+        let mut stmt = if reverse {
+            self.conn.prepare_cached("SELECT collection, rkey, record_cid, record_json, indexed_at FROM record WHERE did = ?1 AND collection = ?2 AND (?3 IS NULL OR rkey < ?3) AND (?4 IS NULL OR rkey > ?4) ORDER BY rkey ASC LIMIT ?5")?
+        } else {
+            self.conn.prepare_cached("SELECT collection, rkey, record_cid, record_json, indexed_at FROM record WHERE did = ?1 AND collection = ?2 AND (?3 IS NULL OR rkey < ?3) AND (?4 IS NULL OR rkey > ?4) ORDER BY rkey DESC LIMIT ?5")?
+        };
+        let mut sql_rows = stmt.query(params!(
+            did.to_string(),
+            collection.to_string(),
+            before,
+            after,
+            limit
+        ))?;
+        let mut rows: Vec<RecordRow> = vec![];
+        while let Some(row) = sql_rows.next()? {
+            let collection: String = row.get(0)?;
+            let cid: String = row.get(2)?;
+            let record_json: String = row.get(3)?;
+            rows.push(RecordRow {
+                collection: Nsid::from_str(&collection)?,
+                rkey: row.get(1)?,
+                cid: Cid::from_str(&cid)?,
+                value: serde_json::from_str(&record_json)?,
+                indexed_at: row.get(4)?,
+            });
+        }
+        Ok(rows)
+    }
+
+    /// Distinct collections with at least one record in a repository, from the generic index
+    pub fn record_collections(&mut self, did: &Did) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT DISTINCT collection FROM record WHERE did = ?1 ORDER BY collection ASC",
+        )?;
+        let collections = stmt
+            .query_map(params!(did.to_string()), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(collections)
+    }
+
+    /// Creates a notification for a local account about a record created by another account.
+    /// Notifications which already exist (eg, when re-indexing) are left as-is, including their
+    /// seen status.
+    fn bsky_notify(
+        &self,
+        user_did: &str,
+        author_did: &Did,
+        subject_uri: &str,
+        subject_cid: &Cid,
+        reason: &str,
+        reason_subject: Option<&str>,
+    ) -> Result<()> {
+        if user_did == author_did.to_string() {
+            return Ok(());
+        }
+        let mut stmt = self
+            .conn
+            .prepare_cached("INSERT INTO bsky_notification (user_did, subject_uri, subject_cid, reason, author_did, reason_subject) SELECT ?1, ?2, ?3, ?4, ?5, ?6 WHERE EXISTS (SELECT 1 FROM account WHERE did = ?1) ON CONFLICT DO NOTHING")?;
+        stmt.execute(params!(
+            user_did,
+            subject_uri,
+            subject_cid.to_string(),
+            reason,
+            author_did.to_string(),
+            reason_subject
+        ))?;
+        Ok(())
+    }
+
+    /// Removes all notifications caused by a record (eg, after it was deleted)
+    fn bsky_delete_notifications(&self, subject_uri: &str) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM bsky_notification WHERE subject_uri = ?1")?;
+        stmt.execute(params!(subject_uri))?;
+        Ok(())
+    }
+
+    pub fn bsky_upsert_post(&self, did: &Did, tid: &Tid, val: Option<Ipld>) -> Result<()> {
+        if let Some(val) = val {
+            // need to re-compute the CID from DagCbor re-encoding, I guess. bleh.
+            let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &val)?;
+            let cid = *block.cid();
+            let post: app_bsky::Post = serde_json::from_value(ipld_into_json_value(val))?;
+            let (reply_to_parent_uri, reply_to_root_uri) = match post.reply {
+                Some(ref reply) => (Some(reply.parent.uri.clone()), Some(reply.root.uri.clone())),
+                None => (None, None),
+            };
+            {
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("INSERT INTO bsky_post (did, tid, cid, reply_to_parent_uri, reply_to_root_uri, record_json, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) ON CONFLICT (did, tid) DO UPDATE SET cid = excluded.cid, reply_to_parent_uri = excluded.reply_to_parent_uri, reply_to_root_uri = excluded.reply_to_root_uri, record_json = excluded.record_json, created_at = excluded.created_at")?;
+                stmt.execute(params!(
+                    did.to_string(),
+                    tid.to_string(),
+                    cid.to_string(),
+                    reply_to_parent_uri,
+                    reply_to_root_uri,
+                    serde_json::to_string(&post)?,
+                    post.createdAt.clone().unwrap_or_else(created_at_now)
+                ))?;
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("DELETE FROM bsky_post_fts WHERE did = ?1 AND tid = ?2")?;
+                stmt.execute(params!(did.to_string(), tid.to_string()))?;
+                let mut stmt = self.conn.prepare_cached(
+                    "INSERT INTO bsky_post_fts (did, tid, text) VALUES (?1, ?2, ?3)",
+                )?;
+                stmt.execute(params!(did.to_string(), tid.to_string(), post.text))?;
+            }
+            let uri = format!("at://{did}/app.bsky.feed.post/{tid}");
+            if let Some(ref parent_uri) = reply_to_parent_uri {
+                if let Some(parent_did) = at_uri_did(parent_uri) {
+                    self.bsky_notify(&parent_did, did, &uri, &cid, "reply", Some(parent_uri))?;
+                }
+            }
+            for entity in post.entities.unwrap_or_default() {
+                if entity.r#type == "mention" && Did::from_str(&entity.value).is_ok() {
+                    self.bsky_notify(&entity.value, did, &uri, &cid, "mention", None)?;
+                }
+            }
+        } else {
+            {
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("DELETE FROM bsky_post WHERE did = ?1 AND tid = ?2")?;
+                stmt.execute(params!(did.to_string(), tid.to_string()))?;
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("DELETE FROM bsky_post_fts WHERE did = ?1 AND tid = ?2")?;
+                stmt.execute(params!(did.to_string(), tid.to_string()))?;
+            }
+            self.bsky_delete_notifications(&format!("at://{did}/app.bsky.feed.post/{tid}"))?;
+        }
+        Ok(())
+    }
+
+    pub fn bsky_upsert_ref(
+        &self,
         ref_type: &str,
         did: &Did,
         tid: &Tid,
         val: Option<Ipld>,
     ) -> Result<()> {
+        let uri = format!("at://{did}/app.bsky.feed.{ref_type}/{tid}");
         if let Some(val) = val {
+            let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &val)?;
+            let cid = *block.cid();
             let ref_obj: app_bsky::RefRecord = serde_json::from_value(ipld_into_json_value(val))?;
-            let mut stmt = self
-                .conn
-                .prepare_cached("INSERT INTO bsky_ref (ref_type, did, tid, subject_uri, subject_cid, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
-            stmt.execute(params!(
-                ref_type.to_string(),
-                did.to_string(),
-                tid.to_string(),
-                ref_obj.subject.uri,
-                ref_obj.subject.cid,
-                ref_obj.createdAt
-            ))?;
+            {
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("INSERT INTO bsky_ref (ref_type, did, tid, subject_uri, subject_cid, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6) ON CONFLICT (ref_type, did, tid) DO UPDATE SET subject_uri = excluded.subject_uri, subject_cid = excluded.subject_cid, created_at = excluded.created_at")?;
+                stmt.execute(params!(
+                    ref_type.to_string(),
+                    did.to_string(),
+                    tid.to_string(),
+                    ref_obj.subject.uri,
+                    ref_obj.subject.cid,
+                    ref_obj.createdAt
+                ))?;
+            }
+            if let Some(subject_did) = at_uri_did(&ref_obj.subject.uri) {
+                self.bsky_notify(
+                    &subject_did,
+                    did,
+                    &uri,
+                    &cid,
+                    ref_type,
+                    Some(&ref_obj.subject.uri),
+                )?;
+            }
         } else {
+            self.bsky_delete_notifications(&uri)?;
             let mut stmt = self.conn.prepare_cached(
                 "DELETE FROM bsky_ref WHERE ref_type = ?1 AND did = ?2 AND tid = ?3",
             )?;
@@ -252,23 +1819,32 @@ impl AtpDatabase {
         Ok(())
     }
 
-    pub fn bsky_upsert_follow(&mut self, did: &Did, tid: &Tid, val: Option<Ipld>) -> Result<()> {
+    pub fn bsky_upsert_follow(&self, did: &Did, tid: &Tid, val: Option<Ipld>) -> Result<()> {
+        let uri = format!("at://{did}/app.bsky.graph.follow/{tid}");
         if let Some(val) = val {
+            let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &val)?;
+            let cid = *block.cid();
             let follow: app_bsky::FollowRecord = serde_json::from_value(ipld_into_json_value(val))?;
-            let mut stmt = self
-                .conn
-                .prepare_cached("INSERT INTO bsky_follow (did, tid, subject_did, created_at) VALUES (?1, ?2, ?3, ?4)")?;
-            stmt.execute(params!(
-                did.to_string(),
-                tid.to_string(),
-                follow.subject.did,
-                follow.createdAt
-            ))?;
+            {
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("INSERT INTO bsky_follow (did, tid, subject_did, created_at) VALUES (?1, ?2, ?3, ?4) ON CONFLICT (did, tid) DO UPDATE SET subject_did = excluded.subject_did, created_at = excluded.created_at")?;
+                stmt.execute(params!(
+                    did.to_string(),
+                    tid.to_string(),
+                    follow.subject.did,
+                    follow.createdAt
+                ))?;
+            }
+            self.bsky_notify(&follow.subject.did, did, &uri, &cid, "follow", None)?;
         } else {
-            let mut stmt = self
-                .conn
-                .prepare_cached("DELETE FROM bsky_follow WHERE did = ?2 AND tid = ?3")?;
-            stmt.execute(params!(did.to_string(), tid.to_string()))?;
+            {
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("DELETE FROM bsky_follow WHERE did = ?1 AND tid = ?2")?;
+                stmt.execute(params!(did.to_string(), tid.to_string()))?;
+            }
+            self.bsky_delete_notifications(&uri)?;
         }
         Ok(())
     }
@@ -1,24 +1,44 @@
 /// Helper functions for doing database and repo operations relating to bluesky endpoints and
 /// records
-use crate::{AtpDatabase, AtpService, Result, XrpcError};
+use crate::{commit_mutations, AtpDatabase, AtpService, Result, XrpcError};
 use adenosine::app_bsky;
 use adenosine::identifiers::{AtUri, Did, DidOrHost, Nsid, Tid};
 use adenosine::ipld::{ipld_into_json_value, json_value_into_ipld};
+use adenosine::normalize_timestamp;
 use adenosine::repo::Mutation;
 use anyhow::anyhow;
 use libipld::Cid;
-use rusqlite::params;
-use serde_json::json;
+use rusqlite::{params, OptionalExtension};
+use serde_json::{json, Value};
+use std::collections::HashSet;
 use std::str::FromStr;
 
-/// Handles updating the database with creation, update, deletion of arbitrary records
-pub fn bsky_mutate_db(db: &mut AtpDatabase, did: &Did, mutations: Vec<Mutation>) -> Result<()> {
+/// Handles updating the database with creation, update, deletion of arbitrary records.
+///
+/// Every record goes in the generic `record` index; bsky records are also indexed in their own
+/// tables.
+pub fn bsky_mutate_db(db: &AtpDatabase, did: &Did, mutations: Vec<Mutation>) -> Result<()> {
     // TODO: this function could probably be refactored
     let bsky_post: Nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
     let bsky_repost: Nsid = Nsid::from_str("app.bsky.feed.repost").unwrap();
     let bsky_like: Nsid = Nsid::from_str("app.bsky.feed.like").unwrap();
     let bsky_follow: Nsid = Nsid::from_str("app.bsky.graph.follow").unwrap();
+    let bsky_profile: Nsid = Nsid::from_str("app.bsky.actor.profile").unwrap();
+    let mut profile_changed = false;
     for m in mutations.into_iter() {
+        let (collection, rkey) = m.record_key();
+        profile_changed |= collection == &bsky_profile;
+        match m {
+            Mutation::Create(_, _, ref val)
+            | Mutation::Update(_, _, ref val)
+            | Mutation::CreateKeyed(_, _, ref val)
+            | Mutation::UpdateKeyed(_, _, ref val) => {
+                db.upsert_record(did, collection, &rkey, Some(val.clone()))?
+            }
+            Mutation::Delete(_, _) | Mutation::DeleteKeyed(_, _) => {
+                db.upsert_record(did, collection, &rkey, None)?
+            }
+        }
         match m {
             Mutation::Create(ref_type, tid, val) | Mutation::Update(ref_type, tid, val)
                 if ref_type == bsky_post =>
@@ -55,33 +75,83 @@ pub fn bsky_mutate_db(db: &mut AtpDatabase, did: &Did, mutations: Vec<Mutation>)
             _ => (),
         }
     }
+    // the search index row is built from the latest profile in the generic record index
+    if profile_changed {
+        db.bsky_update_actor_search(did)?;
+    }
     Ok(())
 }
 
-// TODO: should probably return Result<Option<Profile>>?
-pub fn bsky_get_profile(srv: &mut AtpService, did: &Did) -> Result<app_bsky::ProfileView> {
-    // first get the profile record
-    let mut profile_cid: Option<Cid> = None;
+/// Re-generates all the index rows (generic and bsky) for a DID from the current repo contents, in
+/// a single database transaction.
+///
+/// Rows for records which are no longer in the repo are removed; all other records are upserted,
+/// which keeps their original `indexed_at` timestamps. Returns the number of records that were
+/// passed through for indexing.
+pub fn bsky_reindex(srv: &mut AtpService, did: &Did) -> Result<u64> {
     let commit_cid = match srv.repo.lookup_commit(did)? {
         Some(cid) => cid,
         None => Err(anyhow!("repository not found: {}", did))?,
     };
     let last_commit = srv.repo.get_commit(&commit_cid)?;
-    let full_map = srv.repo.mst_to_map(&last_commit.mst_cid)?;
-    let prefix = "app.bsky.actor.profile/";
-    for (mst_key, cid) in full_map.iter() {
-        if mst_key.starts_with(prefix) {
-            profile_cid = Some(*cid);
+    let mut mutations = srv.repo.mst_diff(None, &last_commit.mst_cid)?;
+    let count = mutations.len() as u64;
+    let repo_keys: HashSet<(String, String)> = mutations
+        .iter()
+        .map(|m| {
+            let (collection, rkey) = m.record_key();
+            (collection.to_string(), rkey)
+        })
+        .collect();
+
+    let tx = srv.atp_db.conn.unchecked_transaction()?;
+    for (collection, rkey) in srv.atp_db.bsky_indexed_keys(did)? {
+        if repo_keys.contains(&(collection.clone(), rkey.clone())) {
+            continue;
         }
+        let collection = Nsid::from_str(&collection)?;
+        mutations.push(match Tid::from_str(&rkey) {
+            Ok(tid) => Mutation::Delete(collection, tid),
+            Err(_) => Mutation::DeleteKeyed(collection, rkey),
+        });
     }
-    let (display_name, description): (Option<String>, Option<String>) =
-        if let Some(cid) = profile_cid {
+    bsky_mutate_db(&srv.atp_db, did, mutations)?;
+    tx.commit()?;
+    Ok(count)
+}
+
+/// The current profile record of an account, and its record key, if there is one. If there are
+/// several, the one with the greatest record key is used (as with the generic record index).
+fn bsky_get_profile_record(
+    srv: &mut AtpService,
+    did: &Did,
+) -> Result<Option<(String, app_bsky::ProfileRecord)>> {
+    let commit_cid = match srv.repo.lookup_commit(did)? {
+        Some(cid) => cid,
+        None => Err(anyhow!("repository not found: {}", did))?,
+    };
+    let last_commit = srv.repo.get_commit(&commit_cid)?;
+    let full_map = srv.repo.mst_to_map(&last_commit.mst_cid)?;
+    let prefix = "app.bsky.actor.profile/";
+    let profile = full_map
+        .iter()
+        .rev()
+        .find_map(|(mst_key, cid)| Some((mst_key.strip_prefix(prefix)?, *cid)));
+    match profile {
+        Some((rkey, cid)) => {
             let record: app_bsky::ProfileRecord =
                 serde_json::from_value(ipld_into_json_value(srv.repo.get_ipld(&cid)?))?;
-            (Some(record.displayName), record.description)
-        } else {
-            (None, None)
-        };
+            Ok(Some((rkey.to_string(), record)))
+        }
+        None => Ok(None),
+    }
+}
+
+// TODO: should probably return Result<Option<Profile>>?
+pub fn bsky_get_profile(srv: &mut AtpService, did: &Did) -> Result<app_bsky::ProfileView> {
+    let record = bsky_get_profile_record(srv, did)?.map(|(_, record)| record);
+    let follows_count = bsky_count_follows(&mut srv.atp_db, did)?;
+    let followers_count = bsky_count_followers(&mut srv.atp_db, did)?;
     let mut stmt = srv
         .atp_db
         .conn
@@ -92,26 +162,27 @@ pub fn bsky_get_profile(srv: &mut AtpService, did: &Did) -> Result<app_bsky::Pro
         .conn
         .prepare_cached("SELECT COUNT(*) FROM bsky_post WHERE did = $1")?;
     let post_count: u64 = stmt.query_row(params!(did.to_string()), |row| row.get(0))?;
-    let mut stmt = srv
-        .atp_db
-        .conn
-        .prepare_cached("SELECT COUNT(*) FROM bsky_follow WHERE did = $1")?;
-    let follows_count: u64 = stmt.query_row(params!(did.to_string()), |row| row.get(0))?;
-    let mut stmt = srv
-        .atp_db
-        .conn
-        .prepare_cached("SELECT COUNT(*) FROM bsky_follow WHERE subject_did = $1")?;
-    let followers_count: u64 = stmt.query_row(params!(did.to_string()), |row| row.get(0))?;
     let decl = app_bsky::DeclRef {
         actorType: "app.bsky.system.actorUser".to_string(),
         cid: "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u".to_string(),
     };
+    let (display_name, description, avatar, banner) = match record {
+        Some(record) => (
+            Some(record.displayName),
+            record.description,
+            record.avatar.map(|b| blob_url(srv, did, &b.cid)),
+            record.banner.map(|b| blob_url(srv, did, &b.cid)),
+        ),
+        None => (None, None, None, None),
+    };
     Ok(app_bsky::ProfileView {
         did: did.to_string(),
         handle,
         creator: did.to_string(),
         displayName: display_name,
         description,
+        avatar,
+        banner,
         declaration: decl,
         followersCount: followers_count,
         followsCount: follows_count,
@@ -121,36 +192,241 @@ pub fn bsky_get_profile(srv: &mut AtpService, did: &Did) -> Result<app_bsky::Pro
     })
 }
 
+/// Checks that an image blob was uploaded by the account, and uses the MIME type it was uploaded
+/// with
+fn verify_profile_blob(
+    srv: &mut AtpService,
+    did: &Did,
+    blob: app_bsky::Blob,
+) -> Result<app_bsky::Blob> {
+    let cid = Cid::from_str(&blob.cid)
+        .map_err(|_| XrpcError::BadRequest(format!("invalid blob CID: {}", blob.cid)))?;
+    match srv.atp_db.get_blob_mime_type(did, &cid)? {
+        Some(mime_type) => Ok(app_bsky::Blob {
+            cid: blob.cid,
+            mimeType: mime_type,
+        }),
+        None => Err(XrpcError::BadRequest(format!(
+            "blob has not been uploaded: {}",
+            blob.cid
+        )))?,
+    }
+}
+
+/// Applies changes to the account's profile record, creating it if needed (in which case a
+/// display name is required)
 pub fn bsky_update_profile(
     srv: &mut AtpService,
     did: &Did,
-    profile: app_bsky::ProfileRecord,
+    update: app_bsky::UpdateProfile,
 ) -> Result<()> {
-    // get the profile record
-    let mut profile_tid: Option<Tid> = None;
-    let commit_cid = match srv.repo.lookup_commit(did)? {
-        Some(cid) => cid,
-        None => Err(anyhow!("repository not found: {}", did))?,
+    let avatar = match update.avatar {
+        Some(blob) => Some(verify_profile_blob(srv, did, blob)?),
+        None => None,
     };
-    let last_commit = srv.repo.get_commit(&commit_cid)?;
-    let full_map = srv.repo.mst_to_map(&last_commit.mst_cid)?;
-    let prefix = "app.bsky.actor.profile/";
-    for (mst_key, _cid) in full_map.iter() {
-        if mst_key.starts_with(prefix) {
-            profile_tid = Some(Tid::from_str(mst_key.split('/').nth(1).unwrap())?);
+    let banner = match update.banner {
+        Some(blob) => Some(verify_profile_blob(srv, did, blob)?),
+        None => None,
+    };
+    // an empty description clears it
+    let description = |existing: Option<String>| match update.description.as_deref() {
+        Some("") => None,
+        Some(v) => Some(v.to_string()),
+        None => existing,
+    };
+    let mutation = match bsky_get_profile_record(srv, did)? {
+        Some((rkey, existing)) => {
+            let record = json_value_into_ipld(serde_json::to_value(app_bsky::ProfileRecord {
+                displayName: update.displayName.unwrap_or(existing.displayName),
+                description: description(existing.description),
+                avatar: avatar.or(existing.avatar),
+                banner: banner.or(existing.banner),
+            })?);
+            let collection = Nsid::from_str("app.bsky.actor.profile")?;
+            match Tid::from_str(&rkey) {
+                Ok(tid) => Mutation::Update(collection, tid, record),
+                // eg, "self" records written elsewhere are updated in place
+                Err(_) => Mutation::UpdateKeyed(collection, rkey, record),
+            }
         }
-    }
-    let profile_tid: Tid = profile_tid.unwrap_or(srv.tid_gen.next_tid());
-    let mutations: Vec<Mutation> = vec![Mutation::Update(
-        Nsid::from_str("app.bsky.actor.profile")?,
-        profile_tid,
-        json_value_into_ipld(serde_json::to_value(profile)?),
-    )];
-    let keypair = srv.pds_keypair.clone();
-    srv.repo.mutate_repo(did, &mutations, &keypair)?;
+        None => {
+            let display_name = match update.displayName {
+                Some(v) => v,
+                None => Err(XrpcError::BadRequest(
+                    "displayName is required for a new profile".to_string(),
+                ))?,
+            };
+            Mutation::Create(
+                Nsid::from_str("app.bsky.actor.profile")?,
+                srv.tid_gen.next_tid(),
+                json_value_into_ipld(serde_json::to_value(app_bsky::ProfileRecord {
+                    displayName: display_name,
+                    description: description(None),
+                    avatar,
+                    banner,
+                })?),
+            )
+        }
+    };
+    commit_mutations(srv, did, vec![mutation])?;
     Ok(())
 }
 
+/// Converts free-form search input in to an FTS5 query: every space-separated term is matched as a
+/// prefix (so partial handles and names match), and all terms must match. Terms with punctuation
+/// (like "alice.test") are matched as a phrase, so their words must be adjacent. Returns None if
+/// there are no words.
+fn fts_prefix_query(term: &str) -> Option<String> {
+    let words: Vec<String> = term
+        .split_whitespace()
+        .map(|chunk| {
+            chunk
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+        .filter(|w| !w.is_empty())
+        .map(|w| format!("\"{w}\"*"))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+struct ActorSearchRow {
+    did: String,
+    handle: String,
+    display_name: Option<String>,
+    description: Option<String>,
+}
+
+/// Best matches first: handle matches are weighted above display name, then description.
+/// Disabled and deactivated local accounts are not returned.
+fn query_actor_search(
+    db: &mut AtpDatabase,
+    term: &str,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<ActorSearchRow>> {
+    let fts_query = match fts_prefix_query(term) {
+        Some(q) => q,
+        None => return Ok(vec![]),
+    };
+    let mut stmt = db.conn.prepare_cached("SELECT bsky_actor_fts.did, COALESCE(account.handle, remote_repo.handle, bsky_actor_fts.did), bsky_actor_fts.display_name, bsky_actor_fts.description FROM bsky_actor_fts LEFT JOIN account ON account.did = bsky_actor_fts.did LEFT JOIN remote_repo ON remote_repo.did = bsky_actor_fts.did WHERE bsky_actor_fts MATCH ?1 AND (account.did IS NULL OR (account.disabled = 0 AND account.deactivated = 0)) ORDER BY bm25(bsky_actor_fts, 0.0, 10.0, 5.0, 1.0), bsky_actor_fts.did LIMIT ?2 OFFSET ?3")?;
+    let mut sql_rows = stmt.query(params!(fts_query, limit, offset))?;
+    let mut rows: Vec<ActorSearchRow> = vec![];
+    while let Some(row) = sql_rows.next()? {
+        rows.push(ActorSearchRow {
+            did: row.get(0)?,
+            handle: row.get(1)?,
+            display_name: row.get(2)?,
+            description: row.get(3)?,
+        });
+    }
+    Ok(rows)
+}
+
+/// Ranked search over handles, display names and descriptions. The `cursor` is an offset in to
+/// the ranked results.
+pub fn bsky_search_actors(
+    srv: &mut AtpService,
+    term: &str,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::ActorSearchResults> {
+    let offset = parse_offset_cursor(cursor)?;
+    let rows = query_actor_search(&mut srv.atp_db, term, limit, offset)?;
+    let cursor = if rows.len() as u64 >= limit {
+        Some((offset + rows.len() as u64).to_string())
+    } else {
+        None
+    };
+    let users = rows
+        .into_iter()
+        .map(|row| app_bsky::ActorSearchView {
+            did: row.did,
+            handle: row.handle,
+            declaration: app_bsky::DeclRef {
+                actorType: "app.bsky.system.actorUser".to_string(),
+                cid: "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u".to_string(),
+            },
+            displayName: row.display_name,
+            description: row.description,
+            avatar: None,
+            indexedAt: None,
+        })
+        .collect();
+    Ok(app_bsky::ActorSearchResults { users, cursor })
+}
+
+/// Like `bsky_search_actors()`, but a single page of compact results, for autocompletion
+pub fn bsky_search_typeahead(
+    srv: &mut AtpService,
+    term: &str,
+    limit: u64,
+) -> Result<app_bsky::ActorTypeahead> {
+    let rows = query_actor_search(&mut srv.atp_db, term, limit, 0)?;
+    let users = rows
+        .into_iter()
+        .map(|row| app_bsky::UserView {
+            did: row.did,
+            handle: row.handle,
+            declaration: app_bsky::DeclRef {
+                actorType: "app.bsky.system.actorUser".to_string(),
+                cid: "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u".to_string(),
+            },
+            displayName: row.display_name,
+            avatar: None,
+            viewer: None,
+        })
+        .collect();
+    Ok(app_bsky::ActorTypeahead { users })
+}
+
+/// Parses an offset cursor (for result lists which are ranked, not sorted by TID)
+fn parse_offset_cursor(cursor: Option<&str>) -> Result<u64> {
+    match cursor {
+        None => Ok(0),
+        Some(c) => Ok(c
+            .parse()
+            .map_err(|_| XrpcError::BadRequest(format!("invalid cursor: {c}")))?),
+    }
+}
+
+/// Accounts to suggest following, best first: accounts followed by the accounts `viewer` follows
+/// (ranked by how many of them do), then the most-followed local accounts. The viewer and any
+/// accounts they already follow are excluded. The `cursor` is an offset in to the ranking.
+pub fn bsky_get_suggestions(
+    srv: &mut AtpService,
+    viewer: Option<&Did>,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::Suggestions> {
+    let offset = parse_offset_cursor(cursor)?;
+    let mut dids: Vec<Did> = vec![];
+    {
+        let mut stmt = srv.atp_db.conn.prepare_cached("SELECT candidate.did, SUM(candidate.mutuals) AS mutuals, (SELECT COUNT(*) FROM bsky_follow WHERE bsky_follow.subject_did = candidate.did) AS followers FROM (SELECT fof.subject_did AS did, COUNT(DISTINCT fof.did) AS mutuals FROM bsky_follow AS follow JOIN bsky_follow AS fof ON fof.did = follow.subject_did WHERE follow.did = ?1 GROUP BY fof.subject_did UNION ALL SELECT did, 0 AS mutuals FROM account) AS candidate WHERE (?1 IS NULL OR candidate.did != ?1) AND candidate.did NOT IN (SELECT subject_did FROM bsky_follow WHERE did = ?1) AND NOT EXISTS (SELECT 1 FROM account WHERE account.did = candidate.did AND (account.disabled = 1 OR account.deactivated = 1)) GROUP BY candidate.did ORDER BY mutuals DESC, followers DESC, candidate.did ASC LIMIT ?2 OFFSET ?3")?;
+        let mut sql_rows = stmt.query(params!(viewer.map(|did| did.to_string()), limit, offset))?;
+        while let Some(row) = sql_rows.next()? {
+            let did: String = row.get(0)?;
+            dids.push(Did::from_str(&did)?);
+        }
+    }
+    let cursor = if dids.len() as u64 >= limit {
+        Some((offset + dids.len() as u64).to_string())
+    } else {
+        None
+    };
+    let mut actors = vec![];
+    for did in dids.iter() {
+        actors.push(bsky_actor_view(srv, did)?);
+    }
+    Ok(app_bsky::Suggestions { actors, cursor })
+}
+
 struct FeedRow {
     pub item_did: Did,
     pub item_handle: String,
@@ -177,7 +453,77 @@ fn feed_row(row: &rusqlite::Row) -> Result<FeedRow> {
     })
 }
 
-fn feed_row_to_item(srv: &mut AtpService, row: FeedRow) -> Result<app_bsky::FeedPostView> {
+/// URIs of the viewer's like and repost records for a post, if any
+fn post_viewer_state(
+    db: &mut AtpDatabase,
+    viewer: &Did,
+    uri: &str,
+) -> Result<app_bsky::PostViewerState> {
+    let mut state = app_bsky::PostViewerState::default();
+    let mut stmt = db.conn.prepare_cached(
+        "SELECT ref_type, tid FROM bsky_ref WHERE did = ?1 AND subject_uri = ?2 ORDER BY tid ASC",
+    )?;
+    let mut sql_rows = stmt.query(params!(viewer.to_string(), uri))?;
+    while let Some(row) = sql_rows.next()? {
+        let ref_type: String = row.get(0)?;
+        let tid: String = row.get(1)?;
+        let ref_uri = format!("at://{viewer}/app.bsky.feed.{ref_type}/{tid}");
+        match ref_type.as_str() {
+            "like" => state.like = Some(ref_uri),
+            "repost" => state.repost = Some(ref_uri),
+            _ => (),
+        }
+    }
+    Ok(state)
+}
+
+/// URL at which this PDS serves a blob uploaded by `did`
+pub fn blob_url(srv: &AtpService, did: &Did, cid: &str) -> String {
+    format!(
+        "{}/xrpc/com.atproto.sync.getBlob?did={}&cid={}",
+        srv.config.public_url, did, cid
+    )
+}
+
+/// Hydrates a post record's embed, with URLs for any blobs. Blobs of remote (synced) posts are not
+/// mirrored, so those URLs will not resolve.
+fn post_embed_view(
+    srv: &AtpService,
+    did: &Did,
+    embed: &app_bsky::PostEmbed,
+) -> app_bsky::PostEmbedView {
+    app_bsky::PostEmbedView {
+        external: embed
+            .external
+            .as_ref()
+            .map(|ext| app_bsky::EmbedExternalView {
+                uri: ext.uri.clone(),
+                title: ext.title.clone(),
+                description: ext.description.clone(),
+                thumb: ext.thumb.as_ref().map(|b| blob_url(srv, did, &b.cid)),
+            }),
+        images: embed.images.as_ref().map(|images| {
+            images
+                .iter()
+                .map(|img| {
+                    // no resizing (yet), so thumbnails are the full image
+                    let url = blob_url(srv, did, &img.image.cid);
+                    app_bsky::EmbedImageView {
+                        thumb: url.clone(),
+                        fullsize: url,
+                        alt: img.alt.clone(),
+                    }
+                })
+                .collect()
+        }),
+    }
+}
+
+fn feed_row_to_item(
+    srv: &mut AtpService,
+    row: FeedRow,
+    viewer: Option<&Did>,
+) -> Result<app_bsky::FeedPostView> {
     let record_ipld = srv.repo.get_ipld(&row.item_post_cid)?;
     let post_record: app_bsky::Post = serde_json::from_value(ipld_into_json_value(record_ipld))?;
     let uri = format!(
@@ -185,6 +531,11 @@ fn feed_row_to_item(srv: &mut AtpService, row: FeedRow) -> Result<app_bsky::Feed
         row.item_did, "app.bsky.feed.post", row.item_post_tid
     );
 
+    let viewer_state = match viewer {
+        Some(viewer) => Some(post_viewer_state(&mut srv.atp_db, viewer, &uri)?),
+        None => None,
+    };
+
     let mut stmt = srv.atp_db.conn.prepare_cached(
         "SELECT COUNT(*) FROM bsky_ref WHERE ref_type = 'like' AND subject_uri = $1",
     )?;
@@ -201,6 +552,16 @@ fn feed_row_to_item(srv: &mut AtpService, row: FeedRow) -> Result<app_bsky::Feed
         .prepare_cached("SELECT COUNT(*) FROM bsky_post WHERE reply_to_parent_uri = $1")?;
     let reply_count: u64 = stmt.query_row(params!(uri), |row| row.get(0))?;
 
+    let mut stmt = srv.atp_db.conn.prepare_cached("SELECT record_json FROM record WHERE did = ?1 AND collection = 'app.bsky.actor.profile' ORDER BY rkey DESC LIMIT 1")?;
+    let profile_json: Option<String> = stmt
+        .query_row(params!(row.item_did.to_string()), |row| row.get(0))
+        .optional()?;
+
+    let embed = post_record
+        .embed
+        .as_ref()
+        .map(|embed| post_embed_view(srv, &row.item_did, embed));
+
     let decl = app_bsky::DeclRef {
         actorType: "app.bsky.system.actorUser".to_string(),
         cid: "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u".to_string(),
@@ -213,19 +574,18 @@ fn feed_row_to_item(srv: &mut AtpService, row: FeedRow) -> Result<app_bsky::Feed
                 did: row.item_did.to_string(),
                 handle: row.item_handle,
                 declaration: decl,
-                // TODO:
-                displayName: None,
-                avatar: None,
+                displayName: profile_display_name(profile_json.as_deref()),
+                avatar: profile_avatar_url(srv, &row.item_did, profile_json.as_deref()),
                 viewer: None,
             },
             record: post_record,
-            embed: None,
+            embed,
             replyCount: reply_count,
             repostCount: repost_count,
             upvoteCount: like_count,
             downvoteCount: 0,
             indexedAt: row.indexed_at,
-            viewer: None,
+            viewer: viewer_state,
         },
         // TODO:
         reason: None,
@@ -234,14 +594,54 @@ fn feed_row_to_item(srv: &mut AtpService, row: FeedRow) -> Result<app_bsky::Feed
     Ok(feed_item)
 }
 
-pub fn bsky_get_timeline(srv: &mut AtpService, did: &Did) -> Result<app_bsky::GenericFeed> {
+/// Encodes an opaque (and URL-safe) pagination cursor from the TID and author DID of the last
+/// record (eg, post) in a page. Records are sorted by TID, with DID as a tie-breaker.
+fn encode_tid_cursor(tid: &Tid, did: &Did) -> String {
+    data_encoding::BASE64URL_NOPAD.encode(format!("{tid}::{did}").as_bytes())
+}
+
+/// Parses an optional cursor (from `encode_tid_cursor`) into TID and DID strings
+fn parse_tid_cursor(cursor: Option<&str>) -> Result<(Option<String>, Option<String>)> {
+    let cursor = match cursor {
+        None => return Ok((None, None)),
+        Some(cursor) => cursor,
+    };
+    let decoded = data_encoding::BASE64URL_NOPAD
+        .decode(cursor.as_bytes())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok());
+    match decoded.as_deref().and_then(|v| v.split_once("::")) {
+        Some((tid, did)) if Tid::from_str(tid).is_ok() && Did::from_str(did).is_ok() => {
+            Ok((Some(tid.to_string()), Some(did.to_string())))
+        }
+        _ => Err(XrpcError::BadRequest(format!("invalid cursor: {cursor}")))?,
+    }
+}
+
+/// Cursor for the page following `rows`, if the page was full
+fn next_feed_cursor(rows: &[FeedRow], limit: u64) -> Option<String> {
+    match rows.last() {
+        Some(row) if rows.len() as u64 >= limit => {
+            Some(encode_tid_cursor(&row.item_post_tid, &row.item_did))
+        }
+        _ => None,
+    }
+}
+
+pub fn bsky_get_timeline(
+    srv: &mut AtpService,
+    did: &Did,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::GenericFeed> {
     let mut feed: Vec<app_bsky::FeedPostView> = vec![];
+    let (cursor_tid, cursor_did) = parse_tid_cursor(cursor)?;
     // TODO: also handle reposts
     let rows = {
         let mut stmt = srv.atp_db
             .conn
-            .prepare_cached("SELECT account.did, account.handle, bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did LEFT JOIN bsky_follow ON bsky_post.did = bsky_follow.subject_did WHERE bsky_follow.did = ?1 AND account.did IS NOT NULL ORDER BY bsky_post.tid DESC LIMIT 20")?;
-        let mut sql_rows = stmt.query(params!(did.to_string()))?;
+            .prepare_cached("SELECT bsky_post.did, COALESCE(account.handle, remote_repo.handle, bsky_post.did), bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did LEFT JOIN remote_repo ON bsky_post.did = remote_repo.did LEFT JOIN bsky_follow ON bsky_post.did = bsky_follow.subject_did WHERE bsky_follow.did = ?1 AND (account.did IS NULL OR (account.disabled = 0 AND account.deactivated = 0)) AND (?2 IS NULL OR bsky_post.tid < ?2 OR (bsky_post.tid = ?2 AND bsky_post.did < ?3)) ORDER BY bsky_post.tid DESC, bsky_post.did DESC LIMIT ?4")?;
+        let mut sql_rows = stmt.query(params!(did.to_string(), cursor_tid, cursor_did, limit))?;
         let mut rows: Vec<FeedRow> = vec![];
         while let Some(sql_row) = sql_rows.next()? {
             let row = feed_row(sql_row)?;
@@ -249,20 +649,31 @@ pub fn bsky_get_timeline(srv: &mut AtpService, did: &Did) -> Result<app_bsky::Ge
         }
         rows
     };
+    let cursor = next_feed_cursor(&rows, limit);
     for row in rows {
-        feed.push(feed_row_to_item(srv, row)?);
+        feed.push(feed_row_to_item(srv, row, Some(did))?);
     }
-    Ok(app_bsky::GenericFeed { feed })
+    Ok(app_bsky::GenericFeed { feed, cursor })
 }
 
-pub fn bsky_get_author_feed(srv: &mut AtpService, did: &Did) -> Result<app_bsky::GenericFeed> {
+/// `viewer` is the authenticated account (if any), for post viewer state. The feed is empty if the
+/// account has been disabled or deactivated.
+pub fn bsky_get_author_feed(
+    srv: &mut AtpService,
+    did: &Did,
+    viewer: Option<&Did>,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::GenericFeed> {
     let mut feed: Vec<app_bsky::FeedPostView> = vec![];
+    // all posts have the same DID, so only the TID part of the cursor matters
+    let (cursor_tid, _) = parse_tid_cursor(cursor)?;
     // TODO: also handle reposts
     let rows = {
         let mut stmt = srv.atp_db
             .conn
-            .prepare_cached("SELECT account.did, account.handle, bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did WHERE bsky_post.did = ?1 ORDER BY bsky_post.tid DESC LIMIT 20")?;
-        let mut sql_rows = stmt.query(params!(did.to_string()))?;
+            .prepare_cached("SELECT bsky_post.did, COALESCE(account.handle, remote_repo.handle, bsky_post.did), bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did LEFT JOIN remote_repo ON bsky_post.did = remote_repo.did WHERE bsky_post.did = ?1 AND (account.did IS NULL OR (account.disabled = 0 AND account.deactivated = 0)) AND (?2 IS NULL OR bsky_post.tid < ?2) ORDER BY bsky_post.tid DESC LIMIT ?3")?;
+        let mut sql_rows = stmt.query(params!(did.to_string(), cursor_tid, limit))?;
         let mut rows: Vec<FeedRow> = vec![];
         while let Some(sql_row) = sql_rows.next()? {
             let row = feed_row(sql_row)?;
@@ -270,121 +681,759 @@ pub fn bsky_get_author_feed(srv: &mut AtpService, did: &Did) -> Result<app_bsky:
         }
         rows
     };
+    let cursor = next_feed_cursor(&rows, limit);
     for row in rows {
-        feed.push(feed_row_to_item(srv, row)?);
+        feed.push(feed_row_to_item(srv, row, viewer)?);
     }
-    Ok(app_bsky::GenericFeed { feed })
+    Ok(app_bsky::GenericFeed { feed, cursor })
 }
 
-// TODO: this is a partial implementation
-// TODO: should maybe have this take a did and tid instead of a aturi?
-pub fn bsky_get_thread(
+struct NotificationRow {
+    pk: i64,
+    uri: String,
+    cid: Option<String>,
+    author_did: Option<String>,
+    author_handle: Option<String>,
+    reason: String,
+    reason_subject: Option<String>,
+    is_read: bool,
+    indexed_at: String,
+}
+
+/// Lists notifications for a local account, newest first. The cursor is the (opaque) row key of
+/// the last notification in the previous page.
+pub fn bsky_list_notifications(
     srv: &mut AtpService,
-    uri: &AtUri,
-    _depth: Option<u64>,
-) -> Result<app_bsky::PostThread> {
-    // parse the URI
-    let did = match uri.repository {
-        DidOrHost::Did(ref did_type, ref did_body) => {
-            Did::from_str(&format!("did:{did_type}:{did_body}"))?
-        }
-        _ => Err(anyhow!("expected a DID, not handle, in uri: {}", uri))?,
-    };
-    if uri.collection != Some("app.bsky.feed.post".to_string()) {
-        Err(anyhow!("expected a post collection in uri: {}", uri))?;
-    };
-    let tid = match uri.record {
-        Some(ref tid) => Tid::from_str(tid)?,
-        _ => Err(anyhow!("expected a record in uri: {}", uri))?,
+    did: &Did,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::NotificationList> {
+    let before_pk: Option<i64> = match cursor {
+        None => None,
+        Some(cursor) => Some(
+            cursor
+                .parse()
+                .map_err(|_| XrpcError::BadRequest(format!("invalid cursor: {cursor}")))?,
+        ),
     };
-
-    // post itself, as a app_bsky::FeedPostView
-    let post_items = {
+    let rows = {
         let mut stmt = srv.atp_db
             .conn
-            .prepare_cached("SELECT account.did, account.handle, bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did WHERE bsky_post.did = ?1 AND bsky_post.tid = ?2")?;
-        let mut sql_rows = stmt.query(params!(did.to_string(), tid.to_string()))?;
-        let mut rows: Vec<FeedRow> = vec![];
-        while let Some(sql_row) = sql_rows.next()? {
-            let row = feed_row(sql_row)?;
-            rows.push(row);
+            .prepare_cached("SELECT bsky_notification.pk, bsky_notification.subject_uri, bsky_notification.subject_cid, bsky_notification.author_did, COALESCE(account.handle, remote_repo.handle, bsky_notification.author_did), bsky_notification.reason, bsky_notification.reason_subject, bsky_notification.seen_at IS NOT NULL, bsky_notification.indexed_at FROM bsky_notification LEFT JOIN account ON bsky_notification.author_did = account.did LEFT JOIN remote_repo ON bsky_notification.author_did = remote_repo.did WHERE bsky_notification.user_did = ?1 AND (?2 IS NULL OR bsky_notification.pk < ?2) ORDER BY bsky_notification.pk DESC LIMIT ?3")?;
+        let mut sql_rows = stmt.query(params!(did.to_string(), before_pk, limit))?;
+        let mut rows: Vec<NotificationRow> = vec![];
+        while let Some(row) = sql_rows.next()? {
+            rows.push(NotificationRow {
+                pk: row.get(0)?,
+                uri: row.get(1)?,
+                cid: row.get(2)?,
+                author_did: row.get(3)?,
+                author_handle: row.get(4)?,
+                reason: row.get(5)?,
+                reason_subject: row.get(6)?,
+                is_read: row.get(7)?,
+                indexed_at: row.get(8)?,
+            });
         }
         rows
     };
-    if post_items.is_empty() {
-        Err(XrpcError::NotFound("post not found".to_string()))?;
+    let cursor = match rows.last() {
+        Some(row) if rows.len() as u64 >= limit => Some(row.pk.to_string()),
+        _ => None,
+    };
+    let mut notifications = vec![];
+    for row in rows {
+        // the record may not be available if the notifying repository was since removed
+        let record = match row.cid.as_deref().map(Cid::from_str) {
+            Some(Ok(cid)) => srv
+                .repo
+                .get_ipld(&cid)
+                .map(ipld_into_json_value)
+                .unwrap_or(Value::Null),
+            _ => Value::Null,
+        };
+        let decl = app_bsky::DeclRef {
+            actorType: "app.bsky.system.actorUser".to_string(),
+            cid: "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u".to_string(),
+        };
+        notifications.push(app_bsky::Notification {
+            uri: row.uri,
+            cid: row.cid.unwrap_or_default(),
+            author: app_bsky::UserView {
+                did: row.author_did.unwrap_or_default(),
+                handle: row.author_handle.unwrap_or_default(),
+                declaration: decl,
+                // TODO:
+                displayName: None,
+                avatar: None,
+                viewer: None,
+            },
+            reason: row.reason,
+            reasonSubject: row.reason_subject,
+            record,
+            isRead: row.is_read,
+            indexedAt: row.indexed_at,
+        });
+    }
+    Ok(app_bsky::NotificationList {
+        notifications,
+        cursor,
+    })
+}
+
+struct FollowRow {
+    follow_did: Did,
+    follow_tid: Tid,
+    follow_cid: Option<String>,
+    user_did: String,
+    user_handle: String,
+    profile_json: Option<String>,
+    created_at: String,
+    indexed_at: String,
+}
+
+fn follow_row_to_target(row: FollowRow) -> app_bsky::FollowTarget {
+    app_bsky::FollowTarget {
+        subject: app_bsky::Subject {
+            uri: format!(
+                "at://{}/app.bsky.graph.follow/{}",
+                row.follow_did, row.follow_tid
+            ),
+            cid: row.follow_cid,
+        },
+        did: row.user_did,
+        handle: row.user_handle,
+        displayName: profile_display_name(row.profile_json.as_deref()),
+        createdAt: Some(row.created_at),
+        indexedAt: row.indexed_at,
+    }
+}
+
+/// Extracts the display name from a profile record in the generic record index
+fn profile_display_name(profile_json: Option<&str>) -> Option<String> {
+    let profile: Value = serde_json::from_str(profile_json?).ok()?;
+    profile["displayName"].as_str().map(|v| v.to_string())
+}
+
+/// URL of the avatar image in a profile record in the generic record index
+fn profile_avatar_url(srv: &AtpService, did: &Did, profile_json: Option<&str>) -> Option<String> {
+    let profile: Value = serde_json::from_str(profile_json?).ok()?;
+    profile["avatar"]["cid"]
+        .as_str()
+        .map(|cid| blob_url(srv, did, cid))
+}
+
+/// Runs one of the followers/follows queries; parameters are the DID, the cursor TID and DID,
+/// and the limit
+fn query_follow_rows(
+    db: &mut AtpDatabase,
+    sql: &str,
+    did: &Did,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<Vec<FollowRow>> {
+    let (cursor_tid, cursor_did) = parse_tid_cursor(cursor)?;
+    let mut stmt = db.conn.prepare_cached(sql)?;
+    let mut sql_rows = stmt.query(params!(did.to_string(), cursor_tid, cursor_did, limit))?;
+    let mut rows: Vec<FollowRow> = vec![];
+    while let Some(row) = sql_rows.next()? {
+        let follow_did: String = row.get(0)?;
+        let follow_tid: String = row.get(1)?;
+        rows.push(FollowRow {
+            follow_did: Did::from_str(&follow_did)?,
+            follow_tid: Tid::from_str(&follow_tid)?,
+            follow_cid: row.get(2)?,
+            user_did: row.get(3)?,
+            user_handle: row.get(4)?,
+            profile_json: row.get(5)?,
+            created_at: row.get(6)?,
+            indexed_at: row.get(7)?,
+        });
+    }
+    Ok(rows)
+}
+
+fn next_follow_cursor(rows: &[FollowRow], limit: u64) -> Option<String> {
+    match rows.last() {
+        Some(row) if rows.len() as u64 >= limit => {
+            Some(encode_tid_cursor(&row.follow_tid, &row.follow_did))
+        }
+        _ => None,
+    }
+}
+
+/// Like `bsky_user_view()`, but including the profile description
+fn bsky_actor_view(srv: &mut AtpService, did: &Did) -> Result<app_bsky::ActorSearchView> {
+    let user = bsky_user_view(srv, did)?;
+    let mut stmt = srv.atp_db.conn.prepare_cached("SELECT record_json FROM record WHERE did = ?1 AND collection = 'app.bsky.actor.profile' ORDER BY rkey DESC LIMIT 1")?;
+    let profile_json: Option<String> = stmt
+        .query_row(params!(did.to_string()), |row| row.get(0))
+        .optional()?;
+    let description = profile_json
+        .and_then(|v| serde_json::from_str::<Value>(&v).ok())
+        .and_then(|v| v["description"].as_str().map(|v| v.to_string()));
+    Ok(app_bsky::ActorSearchView {
+        did: user.did,
+        handle: user.handle,
+        declaration: user.declaration,
+        displayName: user.displayName,
+        description,
+        avatar: user.avatar,
+        indexedAt: None,
+    })
+}
+
+/// Basic view of any account (local or remote) in the index, for the subject of lists
+fn bsky_user_view(srv: &mut AtpService, did: &Did) -> Result<app_bsky::UserView> {
+    let mut stmt = srv.atp_db.conn.prepare_cached("SELECT COALESCE((SELECT handle FROM account WHERE did = ?1), (SELECT handle FROM remote_repo WHERE did = ?1), ?1), (SELECT record_json FROM record WHERE did = ?1 AND collection = 'app.bsky.actor.profile' ORDER BY rkey DESC LIMIT 1)")?;
+    let (handle, profile_json): (String, Option<String>) = stmt
+        .query_row(params!(did.to_string()), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+    Ok(app_bsky::UserView {
+        did: did.to_string(),
+        handle,
+        declaration: app_bsky::DeclRef {
+            actorType: "app.bsky.system.actorUser".to_string(),
+            cid: "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u".to_string(),
+        },
+        displayName: profile_display_name(profile_json.as_deref()),
+        avatar: profile_avatar_url(srv, did, profile_json.as_deref()),
+        viewer: None,
+    })
+}
+
+/// Number of follow records with the account as subject; matches `bsky_get_followers`
+pub fn bsky_count_followers(db: &mut AtpDatabase, did: &Did) -> Result<u64> {
+    let mut stmt = db
+        .conn
+        .prepare_cached("SELECT COUNT(*) FROM bsky_follow WHERE subject_did = ?1")?;
+    Ok(stmt.query_row(params!(did.to_string()), |row| row.get(0))?)
+}
+
+/// Number of follow records created by the account; matches `bsky_get_follows`
+pub fn bsky_count_follows(db: &mut AtpDatabase, did: &Did) -> Result<u64> {
+    let mut stmt = db
+        .conn
+        .prepare_cached("SELECT COUNT(*) FROM bsky_follow WHERE did = ?1")?;
+    Ok(stmt.query_row(params!(did.to_string()), |row| row.get(0))?)
+}
+
+/// Accounts following `did`, most recent follows first
+pub fn bsky_get_followers(
+    srv: &mut AtpService,
+    did: &Did,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::FollowersList> {
+    let rows = query_follow_rows(
+        &mut srv.atp_db,
+        "SELECT bsky_follow.did, bsky_follow.tid, follow_record.record_cid, bsky_follow.did, COALESCE(account.handle, remote_repo.handle, bsky_follow.did), (SELECT record_json FROM record WHERE record.did = bsky_follow.did AND record.collection = 'app.bsky.actor.profile' ORDER BY record.rkey DESC LIMIT 1), bsky_follow.created_at, bsky_follow.indexed_at FROM bsky_follow LEFT JOIN record AS follow_record ON follow_record.did = bsky_follow.did AND follow_record.collection = 'app.bsky.graph.follow' AND follow_record.rkey = bsky_follow.tid LEFT JOIN account ON bsky_follow.did = account.did LEFT JOIN remote_repo ON bsky_follow.did = remote_repo.did WHERE bsky_follow.subject_did = ?1 AND (?2 IS NULL OR bsky_follow.tid < ?2 OR (bsky_follow.tid = ?2 AND bsky_follow.did < ?3)) ORDER BY bsky_follow.tid DESC, bsky_follow.did DESC LIMIT ?4",
+        did,
+        limit,
+        cursor,
+    )?;
+    let cursor = next_follow_cursor(&rows, limit);
+    Ok(app_bsky::FollowersList {
+        subject: bsky_user_view(srv, did)?,
+        followers: rows.into_iter().map(follow_row_to_target).collect(),
+        cursor,
+    })
+}
+
+/// Accounts followed by `did`, most recent follows first
+pub fn bsky_get_follows(
+    srv: &mut AtpService,
+    did: &Did,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::FollowsList> {
+    let rows = query_follow_rows(
+        &mut srv.atp_db,
+        "SELECT bsky_follow.did, bsky_follow.tid, follow_record.record_cid, bsky_follow.subject_did, COALESCE(account.handle, remote_repo.handle, bsky_follow.subject_did), (SELECT record_json FROM record WHERE record.did = bsky_follow.subject_did AND record.collection = 'app.bsky.actor.profile' ORDER BY record.rkey DESC LIMIT 1), bsky_follow.created_at, bsky_follow.indexed_at FROM bsky_follow LEFT JOIN record AS follow_record ON follow_record.did = bsky_follow.did AND follow_record.collection = 'app.bsky.graph.follow' AND follow_record.rkey = bsky_follow.tid LEFT JOIN account ON bsky_follow.subject_did = account.did LEFT JOIN remote_repo ON bsky_follow.subject_did = remote_repo.did WHERE bsky_follow.did = ?1 AND (?2 IS NULL OR bsky_follow.tid < ?2 OR (bsky_follow.tid = ?2 AND bsky_follow.did < ?3)) ORDER BY bsky_follow.tid DESC, bsky_follow.did DESC LIMIT ?4",
+        did,
+        limit,
+        cursor,
+    )?;
+    let cursor = next_follow_cursor(&rows, limit);
+    Ok(app_bsky::FollowsList {
+        subject: bsky_user_view(srv, did)?,
+        follows: rows.into_iter().map(follow_row_to_target).collect(),
+        cursor,
+    })
+}
+
+/// Accounts with a like (or repost) record referencing `uri`, most recent first. Returns the
+/// actor views and the cursor for the next page.
+fn query_ref_actors(
+    srv: &mut AtpService,
+    ref_type: &str,
+    uri: &str,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<(Vec<app_bsky::RefActorView>, Option<String>)> {
+    let (cursor_tid, cursor_did) = parse_tid_cursor(cursor)?;
+    let mut rows: Vec<(Did, Tid, String, String)> = vec![];
+    {
+        let mut stmt = srv.atp_db.conn.prepare_cached("SELECT did, tid, created_at, indexed_at FROM bsky_ref WHERE ref_type = ?1 AND subject_uri = ?2 AND (?3 IS NULL OR tid < ?3 OR (tid = ?3 AND did < ?4)) ORDER BY tid DESC, did DESC LIMIT ?5")?;
+        let mut sql_rows = stmt.query(params!(ref_type, uri, cursor_tid, cursor_did, limit))?;
+        while let Some(row) = sql_rows.next()? {
+            let did: String = row.get(0)?;
+            let tid: String = row.get(1)?;
+            rows.push((
+                Did::from_str(&did)?,
+                Tid::from_str(&tid)?,
+                row.get(2)?,
+                row.get(3)?,
+            ));
+        }
+    }
+    let cursor = match rows.last() {
+        Some((did, tid, _, _)) if rows.len() as u64 >= limit => Some(encode_tid_cursor(tid, did)),
+        _ => None,
     };
-    let post_item = feed_row_to_item(srv, post_items.into_iter().next().unwrap())?;
+    let mut actors = vec![];
+    for (did, _, created_at, indexed_at) in rows {
+        actors.push(app_bsky::RefActorView {
+            actor: bsky_user_view(srv, &did)?,
+            createdAt: created_at,
+            indexedAt: indexed_at,
+        });
+    }
+    Ok((actors, cursor))
+}
+
+pub fn bsky_get_liked_by(
+    srv: &mut AtpService,
+    uri: &str,
+    cid: Option<String>,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::LikedBy> {
+    let (actors, cursor) = query_ref_actors(srv, "like", uri, limit, cursor)?;
+    Ok(app_bsky::LikedBy {
+        uri: uri.to_string(),
+        cid,
+        likedBy: actors,
+        cursor,
+    })
+}
+
+pub fn bsky_get_reposted_by(
+    srv: &mut AtpService,
+    uri: &str,
+    cid: Option<String>,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::RepostedBy> {
+    let (actors, cursor) = query_ref_actors(srv, "repost", uri, limit, cursor)?;
+    Ok(app_bsky::RepostedBy {
+        uri: uri.to_string(),
+        cid,
+        repostedBy: actors,
+        cursor,
+    })
+}
 
-    // TODO: any parent
-    let parent = None;
+/// Query and filters for `bsky_search_posts()`
+#[derive(Debug, Clone, Default)]
+pub struct PostSearch {
+    /// Free-form search text; every word must match (as a prefix)
+    pub text: String,
+    pub author: Option<Did>,
+    /// Inclusive lower bound on post `createdAt`, eg "2022-11-01" or a full timestamp
+    pub since: Option<String>,
+    /// Exclusive upper bound on post `createdAt`
+    pub until: Option<String>,
+}
 
-    // any children
-    let mut children = vec![];
+/// Full-text search of post text, most recent posts first. Posts by disabled or deactivated
+/// local accounts are not returned.
+pub fn bsky_search_posts(
+    srv: &mut AtpService,
+    search: &PostSearch,
+    viewer: Option<&Did>,
+    limit: u64,
+    cursor: Option<&str>,
+) -> Result<app_bsky::PostSearchResults> {
+    let (cursor_tid, cursor_did) = parse_tid_cursor(cursor)?;
+    let fts_query = match fts_prefix_query(&search.text) {
+        Some(q) => q,
+        None => {
+            return Ok(app_bsky::PostSearchResults {
+                posts: vec![],
+                cursor: None,
+            })
+        }
+    };
     let rows = {
-        let mut stmt = srv.atp_db
-            .conn
-            .prepare_cached("SELECT account.did, account.handle, bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did WHERE bsky_post.reply_to_parent_uri = ?1 ORDER BY bsky_post.tid DESC LIMIT 20")?;
-        let mut sql_rows = stmt.query(params!(uri.to_string()))?;
+        let mut stmt = srv.atp_db.conn.prepare_cached("SELECT bsky_post.did, COALESCE(account.handle, remote_repo.handle, bsky_post.did), bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post_fts JOIN bsky_post ON bsky_post.did = bsky_post_fts.did AND bsky_post.tid = bsky_post_fts.tid LEFT JOIN account ON bsky_post.did = account.did LEFT JOIN remote_repo ON bsky_post.did = remote_repo.did WHERE bsky_post_fts MATCH ?1 AND (?2 IS NULL OR bsky_post.did = ?2) AND (?3 IS NULL OR bsky_post.created_at >= ?3) AND (?4 IS NULL OR bsky_post.created_at < ?4) AND (account.did IS NULL OR (account.disabled = 0 AND account.deactivated = 0)) AND (?5 IS NULL OR bsky_post.tid < ?5 OR (bsky_post.tid = ?5 AND bsky_post.did < ?6)) ORDER BY bsky_post.tid DESC, bsky_post.did DESC LIMIT ?7")?;
+        let mut sql_rows = stmt.query(params!(
+            fts_query,
+            search.author.as_ref().map(|did| did.to_string()),
+            search.since,
+            search.until,
+            cursor_tid,
+            cursor_did,
+            limit
+        ))?;
         let mut rows: Vec<FeedRow> = vec![];
         while let Some(sql_row) = sql_rows.next()? {
-            let row = feed_row(sql_row)?;
-            rows.push(row);
+            rows.push(feed_row(sql_row)?);
         }
         rows
     };
+    let cursor = next_feed_cursor(&rows, limit);
+    let mut posts = vec![];
     for row in rows {
-        let item = feed_row_to_item(srv, row)?.post;
-        children.push(app_bsky::ThreadPostView {
-            post: Some(app_bsky::PostView {
-                uri: item.uri,
-                cid: item.cid,
-                author: item.author,
-                record: item.record,
-                embed: item.embed,
-                replyCount: item.replyCount,
-                upvoteCount: item.upvoteCount,
-                downvoteCount: 0,
-                repostCount: item.repostCount,
-                indexedAt: item.indexedAt,
-                viewer: None,
-            }),
-            // don't want a loop here
-            parent: None,
-            // only going to depth of one here
-            replies: None,
-            // for "notfound"
-            uri: None,
-            notFound: None,
-        });
+        posts.push(feed_row_to_item(srv, row, viewer)?.post);
     }
+    Ok(app_bsky::PostSearchResults { posts, cursor })
+}
 
-    let pip = post_item.post;
-    let post = app_bsky::ThreadPostView {
-        post: Some(app_bsky::PostView {
-            uri: pip.uri,
-            cid: pip.cid,
-            author: pip.author,
-            record: pip.record,
-            embed: pip.embed,
-            replyCount: pip.replyCount,
-            upvoteCount: pip.upvoteCount,
-            downvoteCount: 0,
-            repostCount: pip.repostCount,
-            indexedAt: pip.indexedAt,
-            viewer: None,
-        }),
-        parent,
-        replies: Some(children),
-        // for "notfound" variant
-        uri: None,
-        notFound: None,
-    };
-    Ok(app_bsky::PostThread { thread: post })
+/// Number of unseen notifications for a local account
+pub fn bsky_notification_count(srv: &mut AtpService, did: &Did) -> Result<u64> {
+    let mut stmt = srv.atp_db.conn.prepare_cached(
+        "SELECT COUNT(*) FROM bsky_notification WHERE user_did = ?1 AND seen_at IS NULL",
+    )?;
+    let count: u64 = stmt.query_row(params!(did.to_string()), |row| row.get(0))?;
+    Ok(count)
 }
 
-#[test]
-fn test_bsky_profile() {
+/// Marks all notifications indexed up to `seen_at` (an RFC 3339 timestamp) as seen
+pub fn bsky_update_seen(srv: &mut AtpService, did: &Did, seen_at: &str) -> Result<()> {
+    // normalized to the same format as `indexed_at`, so they can be compared as strings
+    let seen_at = normalize_timestamp(seen_at)
+        .map_err(|e| XrpcError::BadRequest(format!("seenAt must be an RFC 3339 timestamp: {e}")))?;
+    let mut stmt = srv.atp_db.conn.prepare_cached(
+        "UPDATE bsky_notification SET seen_at = ?2 WHERE user_did = ?1 AND seen_at IS NULL AND indexed_at <= ?2",
+    )?;
+    stmt.execute(params!(did.to_string(), seen_at))?;
+    Ok(())
+}
+
+/// Default number of levels of replies returned with a thread
+pub const THREAD_DEFAULT_DEPTH: u64 = 6;
+
+/// Default number of ancestor posts returned with a thread
+pub const THREAD_DEFAULT_PARENT_HEIGHT: u64 = 80;
+
+/// Larger requested reply depths are reduced to this
+pub const THREAD_MAX_DEPTH: u64 = 10;
+
+/// Larger requested parent heights are reduced to this
+pub const THREAD_MAX_PARENT_HEIGHT: u64 = 100;
+
+/// Maximum number of nested (not direct) replies returned per post
+pub const THREAD_NESTED_REPLIES_LIMIT: u64 = 10;
+
+/// Maximum number of replies returned with a thread in total. Nested replies are left out once
+/// this is reached; the page of direct replies is always complete.
+pub const THREAD_MAX_REPLIES: u64 = 500;
+
+/// Parses a post AT URI into the author DID and record TID
+fn post_uri_parts(uri: &AtUri) -> Result<(Did, Tid)> {
+    let did = match uri.repository {
+        DidOrHost::Did(ref did_type, ref did_body) => {
+            Did::from_str(&format!("did:{did_type}:{did_body}"))?
+        }
+        _ => Err(anyhow!("expected a DID, not handle, in uri: {}", uri))?,
+    };
+    if uri.collection != Some("app.bsky.feed.post".to_string()) {
+        Err(anyhow!("expected a post collection in uri: {}", uri))?;
+    };
+    let tid = match uri.record {
+        Some(ref tid) => Tid::from_str(tid)?,
+        _ => Err(anyhow!("expected a record in uri: {}", uri))?,
+    };
+    Ok((did, tid))
+}
+
+/// Fetches a single post from the index, if it exists (and the author account is active)
+fn thread_post(
+    srv: &mut AtpService,
+    did: &Did,
+    tid: &Tid,
+    viewer: Option<&Did>,
+) -> Result<Option<app_bsky::PostView>> {
+    let row = {
+        let mut stmt = srv.atp_db
+            .conn
+            .prepare_cached("SELECT bsky_post.did, COALESCE(account.handle, remote_repo.handle, bsky_post.did), bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did LEFT JOIN remote_repo ON bsky_post.did = remote_repo.did WHERE bsky_post.did = ?1 AND bsky_post.tid = ?2 AND (account.did IS NULL OR (account.disabled = 0 AND account.deactivated = 0))")?;
+        let mut sql_rows = stmt.query(params!(did.to_string(), tid.to_string()))?;
+        let row = match sql_rows.next()? {
+            Some(sql_row) => feed_row(sql_row)?,
+            None => return Ok(None),
+        };
+        row
+    };
+    Ok(Some(feed_row_to_item(srv, row, viewer)?.post))
+}
+
+/// The parent of a post in a thread, with up to `height` ancestors (including the parent itself)
+fn thread_parent(
+    srv: &mut AtpService,
+    parent_uri: &str,
+    viewer: Option<&Did>,
+    height: u64,
+) -> Result<app_bsky::ThreadPostView> {
+    let post = match AtUri::from_str(parent_uri).and_then(|uri| post_uri_parts(&uri)) {
+        Ok((did, tid)) => thread_post(srv, &did, &tid, viewer)?,
+        Err(_) => None,
+    };
+    let post = match post {
+        Some(post) => post,
+        None => {
+            return Ok(app_bsky::ThreadPostView {
+                uri: Some(parent_uri.to_string()),
+                notFound: Some(true),
+                post: None,
+                parent: None,
+                replies: None,
+            })
+        }
+    };
+    let parent = match post.record.reply {
+        Some(ref reply) if height > 1 => Some(Box::new(thread_parent(
+            srv,
+            &reply.parent.uri,
+            viewer,
+            height - 1,
+        )?)),
+        _ => None,
+    };
+    Ok(app_bsky::ThreadPostView {
+        uri: None,
+        notFound: None,
+        post: Some(post),
+        parent,
+        replies: None,
+    })
+}
+
+/// A page of direct replies to a post (most recent first), each with nested replies down to
+/// `depth` levels. Only the direct replies are paginated; nested replies are limited to
+/// `THREAD_NESTED_REPLIES_LIMIT` per post, and `THREAD_MAX_REPLIES` in total.
+///
+/// `visited` holds the URIs of posts already in the thread; they are skipped if they show up again
+/// (which would only happen with a reply cycle). It also counts the posts towards the total.
+fn thread_replies(
+    srv: &mut AtpService,
+    uri: &str,
+    viewer: Option<&Did>,
+    depth: u64,
+    limit: u64,
+    cursor: Option<&str>,
+    visited: &mut HashSet<String>,
+) -> Result<(Vec<app_bsky::ThreadPostView>, Option<String>)> {
+    let (cursor_tid, cursor_did) = parse_tid_cursor(cursor)?;
+    let rows = {
+        let mut stmt = srv.atp_db
+            .conn
+            .prepare_cached("SELECT bsky_post.did, COALESCE(account.handle, remote_repo.handle, bsky_post.did), bsky_post.tid, bsky_post.cid, bsky_post.indexed_at FROM bsky_post LEFT JOIN account ON bsky_post.did = account.did LEFT JOIN remote_repo ON bsky_post.did = remote_repo.did WHERE bsky_post.reply_to_parent_uri = ?1 AND (account.did IS NULL OR (account.disabled = 0 AND account.deactivated = 0)) AND (?2 IS NULL OR bsky_post.tid < ?2 OR (bsky_post.tid = ?2 AND bsky_post.did < ?3)) ORDER BY bsky_post.tid DESC, bsky_post.did DESC LIMIT ?4")?;
+        let mut sql_rows = stmt.query(params!(uri, cursor_tid, cursor_did, limit))?;
+        let mut rows: Vec<FeedRow> = vec![];
+        while let Some(sql_row) = sql_rows.next()? {
+            rows.push(feed_row(sql_row)?);
+        }
+        rows
+    };
+    let next_cursor = next_feed_cursor(&rows, limit);
+    // all of these count towards the total before any nested replies are fetched
+    let rows: Vec<FeedRow> = rows
+        .into_iter()
+        .filter(|row| {
+            visited.insert(format!(
+                "at://{}/app.bsky.feed.post/{}",
+                row.item_did, row.item_post_tid
+            ))
+        })
+        .collect();
+    let mut replies = vec![];
+    for row in rows {
+        let post = feed_row_to_item(srv, row, viewer)?.post;
+        // `visited` includes the thread's root post
+        let remaining = (THREAD_MAX_REPLIES + 1).saturating_sub(visited.len() as u64);
+        let nested = if depth > 1 && remaining > 0 {
+            let nested_limit = limit.min(THREAD_NESTED_REPLIES_LIMIT).min(remaining);
+            Some(
+                thread_replies(
+                    srv,
+                    &post.uri,
+                    viewer,
+                    depth - 1,
+                    nested_limit,
+                    None,
+                    visited,
+                )?
+                .0,
+            )
+        } else {
+            None
+        };
+        replies.push(app_bsky::ThreadPostView {
+            uri: None,
+            notFound: None,
+            post: Some(post),
+            // parents are only included for the root of the returned thread
+            parent: None,
+            replies: nested,
+        });
+    }
+    Ok((replies, next_cursor))
+}
+
+/// Fetches a post, with up to `parent_height` ancestors, and replies nested up to `depth` levels.
+/// Both are capped (at `THREAD_MAX_PARENT_HEIGHT` and `THREAD_MAX_DEPTH`). `replies_cursor` is
+/// the `cursor` returned with the previous page of direct replies. `viewer` is the authenticated
+/// account (if any).
+pub fn bsky_get_thread(
+    srv: &mut AtpService,
+    uri: &AtUri,
+    viewer: Option<&Did>,
+    depth: Option<u64>,
+    parent_height: Option<u64>,
+    replies_limit: u64,
+    replies_cursor: Option<&str>,
+) -> Result<app_bsky::PostThread> {
+    let (did, tid) = post_uri_parts(uri)?;
+    let post = match thread_post(srv, &did, &tid, viewer)? {
+        Some(post) => post,
+        None => Err(XrpcError::NotFound("post not found".to_string()))?,
+    };
+
+    let parent_height = std::cmp::min(
+        parent_height.unwrap_or(THREAD_DEFAULT_PARENT_HEIGHT),
+        THREAD_MAX_PARENT_HEIGHT,
+    );
+    let parent = match post.record.reply {
+        Some(ref reply) if parent_height > 0 => Some(Box::new(thread_parent(
+            srv,
+            &reply.parent.uri,
+            viewer,
+            parent_height,
+        )?)),
+        _ => None,
+    };
+
+    let depth = std::cmp::min(depth.unwrap_or(THREAD_DEFAULT_DEPTH), THREAD_MAX_DEPTH);
+    let (replies, cursor) = if depth > 0 {
+        let mut visited = HashSet::from([post.uri.clone()]);
+        let (replies, cursor) = thread_replies(
+            srv,
+            &post.uri,
+            viewer,
+            depth,
+            replies_limit,
+            replies_cursor,
+            &mut visited,
+        )?;
+        (Some(replies), cursor)
+    } else {
+        (None, None)
+    };
+
+    Ok(app_bsky::PostThread {
+        thread: app_bsky::ThreadPostView {
+            uri: None,
+            notFound: None,
+            post: Some(post),
+            parent,
+            replies,
+        },
+        cursor,
+    })
+}
+
+/// Creates local accounts with the given handles, returning their DIDs (in the same order)
+#[cfg(test)]
+fn test_accounts(srv: &mut AtpService, handles: &[&str]) -> Vec<Did> {
+    use crate::create_account;
+    use adenosine::com_atproto;
+
+    handles
+        .iter()
+        .map(|handle| {
+            let req = com_atproto::AccountRequest {
+                email: format!("{handle}@bogus.com"),
+                handle: handle.to_string(),
+                password: "bogus".to_string(),
+                inviteCode: None,
+                recoveryKey: None,
+                did: None,
+            };
+            let session = create_account(srv, &req, true).unwrap();
+            Did::from_str(&session.did).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn test_bsky_profile_non_tid_key() {
+    use libipld::ipld;
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let did = test_accounts(&mut srv, &["alice.test"]).remove(0);
+
+    // a profile record written elsewhere, with a non-TID key
+    let commit_cid = srv.repo.lookup_commit(&did).unwrap().unwrap();
+    let last_commit = srv.repo.get_commit(&commit_cid).unwrap();
+    let mut map = srv.repo.mst_to_map(&last_commit.mst_cid).unwrap();
+    let record_cid = srv
+        .repo
+        .put_ipld(&ipld!({"displayName": "Alice", "description": "hello"}))
+        .unwrap();
+    map.insert("app.bsky.actor.profile/self".to_string(), record_cid);
+    let mst_cid = srv.repo.mst_from_map(&map).unwrap();
+    let root_cid = srv
+        .repo
+        .write_root(last_commit.meta_cid, Some(commit_cid), mst_cid)
+        .unwrap();
+    let sig = srv.pds_keypair.sign_bytes(root_cid.to_string().as_bytes());
+    srv.repo.write_commit(&did, root_cid, &sig).unwrap();
+    bsky_reindex(&mut srv, &did).unwrap();
+
+    let profile = bsky_get_profile(&mut srv, &did).unwrap();
+    assert_eq!(profile.displayName, Some("Alice".to_string()));
+    assert_eq!(profile.description, Some("hello".to_string()));
+    // the record index is used for other views of the account
+    assert_eq!(
+        bsky_user_view(&mut srv, &did).unwrap().displayName,
+        Some("Alice".to_string())
+    );
+    let found = bsky_search_actors(&mut srv, "hello", 50, None)
+        .unwrap()
+        .users;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].displayName, Some("Alice".to_string()));
+
+    // updating rewrites the existing record in place
+    let update = app_bsky::UpdateProfile {
+        displayName: Some("Alice A.".to_string()),
+        ..Default::default()
+    };
+    bsky_update_profile(&mut srv, &did, update).unwrap();
+    let profile = bsky_get_profile(&mut srv, &did).unwrap();
+    assert_eq!(profile.displayName, Some("Alice A.".to_string()));
+    assert_eq!(profile.description, Some("hello".to_string()));
+    assert_eq!(
+        bsky_user_view(&mut srv, &did).unwrap().displayName,
+        Some("Alice A.".to_string())
+    );
+    let commit_cid = srv.repo.lookup_commit(&did).unwrap().unwrap();
+    let mst_cid = srv.repo.get_commit(&commit_cid).unwrap().mst_cid;
+    let profile_keys: Vec<String> = srv
+        .repo
+        .mst_to_map(&mst_cid)
+        .unwrap()
+        .into_keys()
+        .filter(|k| k.starts_with("app.bsky.actor.profile/"))
+        .collect();
+    assert_eq!(profile_keys, vec!["app.bsky.actor.profile/self"]);
+}
+
+#[test]
+fn test_bsky_profile() {
     use crate::{create_account, created_at_now};
     use adenosine::com_atproto;
     use libipld::ipld;
@@ -399,6 +1448,7 @@ fn test_bsky_profile() {
         password: "bogus".to_string(),
         inviteCode: None,
         recoveryKey: None,
+        did: None,
     };
     let session = create_account(&mut srv, &req, true).unwrap();
     let did = Did::from_str(&session.did).unwrap();
@@ -411,23 +1461,70 @@ fn test_bsky_profile() {
     assert_eq!(profile.followsCount, 0);
     assert_eq!(profile.postsCount, 0);
 
-    let record = app_bsky::ProfileRecord {
-        displayName: "Test Name".to_string(),
+    // a new profile requires a display name
+    let update = app_bsky::UpdateProfile {
+        description: Some("short description".to_string()),
+        ..Default::default()
+    };
+    assert!(bsky_update_profile(&mut srv, &did, update).is_err());
+
+    let update = app_bsky::UpdateProfile {
+        displayName: Some("Test Name".to_string()),
         description: Some("short description".to_string()),
+        ..Default::default()
     };
-    bsky_update_profile(&mut srv, &did, record.clone()).unwrap();
+    bsky_update_profile(&mut srv, &did, update.clone()).unwrap();
     let profile = bsky_get_profile(&mut srv, &did).unwrap();
-    assert_eq!(profile.displayName, Some(record.displayName));
-    assert_eq!(profile.description, record.description);
+    assert_eq!(profile.displayName, update.displayName);
+    assert_eq!(profile.description, update.description);
 
-    let record = app_bsky::ProfileRecord {
-        displayName: "New Test Name".to_string(),
+    let update = app_bsky::UpdateProfile {
+        displayName: Some("New Test Name".to_string()),
         description: Some("longer description".to_string()),
+        ..Default::default()
     };
-    bsky_update_profile(&mut srv, &did, record.clone()).unwrap();
+    bsky_update_profile(&mut srv, &did, update.clone()).unwrap();
     let profile = bsky_get_profile(&mut srv, &did).unwrap();
-    assert_eq!(profile.displayName, Some(record.displayName));
-    assert_eq!(profile.description, record.description);
+    assert_eq!(profile.displayName, update.displayName);
+    assert_eq!(profile.description, update.description);
+    assert_eq!(profile.avatar, None);
+
+    // avatar blobs must have been uploaded by the account; other fields are kept
+    let avatar_cid = srv.repo.put_blob(b"not really a png").unwrap();
+    let avatar = app_bsky::Blob {
+        cid: avatar_cid.to_string(),
+        mimeType: "image/png".to_string(),
+    };
+    let update = app_bsky::UpdateProfile {
+        avatar: Some(avatar),
+        ..Default::default()
+    };
+    assert!(bsky_update_profile(&mut srv, &did, update.clone()).is_err());
+    srv.atp_db
+        .put_blob_meta(&did, &avatar_cid, "image/png", 16)
+        .unwrap();
+    bsky_update_profile(&mut srv, &did, update).unwrap();
+    let profile = bsky_get_profile(&mut srv, &did).unwrap();
+    assert_eq!(profile.displayName, Some("New Test Name".to_string()));
+    assert_eq!(
+        profile.avatar,
+        Some(blob_url(&srv, &did, &avatar_cid.to_string()))
+    );
+    assert_eq!(profile.banner, None);
+    assert_eq!(
+        bsky_user_view(&mut srv, &did).unwrap().avatar,
+        profile.avatar
+    );
+
+    // an empty description clears it
+    let update = app_bsky::UpdateProfile {
+        description: Some("".to_string()),
+        ..Default::default()
+    };
+    bsky_update_profile(&mut srv, &did, update).unwrap();
+    let profile = bsky_get_profile(&mut srv, &did).unwrap();
+    assert_eq!(profile.displayName, Some("New Test Name".to_string()));
+    assert_eq!(profile.description, None);
 
     let mutations = vec![
         Mutation::Create(
@@ -459,7 +1556,7 @@ fn test_bsky_profile() {
     srv.repo
         .mutate_repo(&did, &mutations, &srv.pds_keypair)
         .unwrap();
-    bsky_mutate_db(&mut srv.atp_db, &did, mutations).unwrap();
+    bsky_mutate_db(&srv.atp_db, &did, mutations).unwrap();
 
     let profile = bsky_get_profile(&mut srv, &did).unwrap();
     assert_eq!(profile.followersCount, 1);
@@ -487,6 +1584,7 @@ fn test_bsky_feeds() {
             password: "bogus".to_string(),
             inviteCode: None,
             recoveryKey: None,
+            did: None,
         };
         let session = create_account(&mut srv, &req, true).unwrap();
         Did::from_str(&session.did).unwrap()
@@ -498,6 +1596,7 @@ fn test_bsky_feeds() {
             password: "bogus".to_string(),
             inviteCode: None,
             recoveryKey: None,
+            did: None,
         };
         let session = create_account(&mut srv, &req, true).unwrap();
         Did::from_str(&session.did).unwrap()
@@ -509,22 +1608,23 @@ fn test_bsky_feeds() {
             password: "bogus".to_string(),
             inviteCode: None,
             recoveryKey: None,
+            did: None,
         };
         let session = create_account(&mut srv, &req, true).unwrap();
         Did::from_str(&session.did).unwrap()
     };
 
     // all feeds and timelines should be empty
-    let alice_feed = bsky_get_author_feed(&mut srv, &alice_did).unwrap();
-    let alice_timeline = bsky_get_timeline(&mut srv, &alice_did).unwrap();
+    let alice_feed = bsky_get_author_feed(&mut srv, &alice_did, None, 50, None).unwrap();
+    let alice_timeline = bsky_get_timeline(&mut srv, &alice_did, 50, None).unwrap();
     assert!(alice_feed.feed.is_empty());
     assert!(alice_timeline.feed.is_empty());
-    let bob_feed = bsky_get_author_feed(&mut srv, &bob_did).unwrap();
-    let bob_timeline = bsky_get_timeline(&mut srv, &bob_did).unwrap();
+    let bob_feed = bsky_get_author_feed(&mut srv, &bob_did, None, 50, None).unwrap();
+    let bob_timeline = bsky_get_timeline(&mut srv, &bob_did, 50, None).unwrap();
     assert!(bob_feed.feed.is_empty());
     assert!(bob_timeline.feed.is_empty());
-    let carol_feed = bsky_get_author_feed(&mut srv, &carol_did).unwrap();
-    let carol_timeline = bsky_get_timeline(&mut srv, &carol_did).unwrap();
+    let carol_feed = bsky_get_author_feed(&mut srv, &carol_did, None, 50, None).unwrap();
+    let carol_timeline = bsky_get_timeline(&mut srv, &carol_did, 50, None).unwrap();
     assert!(carol_feed.feed.is_empty());
     assert!(carol_timeline.feed.is_empty());
 
@@ -553,7 +1653,7 @@ fn test_bsky_feeds() {
     srv.repo
         .mutate_repo(&alice_did, &mutations, &srv.pds_keypair)
         .unwrap();
-    bsky_mutate_db(&mut srv.atp_db, &alice_did, mutations).unwrap();
+    bsky_mutate_db(&srv.atp_db, &alice_did, mutations).unwrap();
 
     // bob follows alice, likes first post, reposts second, replies third
     let alice_post3_uri = format!("at://{}/{}/{}", alice_did, post_nsid, alice_post3_tid);
@@ -582,7 +1682,7 @@ fn test_bsky_feeds() {
     srv.repo
         .mutate_repo(&bob_did, &mutations, &srv.pds_keypair)
         .unwrap();
-    bsky_mutate_db(&mut srv.atp_db, &bob_did, mutations).unwrap();
+    bsky_mutate_db(&srv.atp_db, &bob_did, mutations).unwrap();
 
     // carol follows bob
     let mutations = vec![Mutation::Create(
@@ -593,7 +1693,7 @@ fn test_bsky_feeds() {
     srv.repo
         .mutate_repo(&bob_did, &mutations, &srv.pds_keypair)
         .unwrap();
-    bsky_mutate_db(&mut srv.atp_db, &carol_did, mutations).unwrap();
+    bsky_mutate_db(&srv.atp_db, &carol_did, mutations).unwrap();
 
     // test alice profile: counts should be updated
     let alice_profile = bsky_get_profile(&mut srv, &alice_did).unwrap();
@@ -602,12 +1702,12 @@ fn test_bsky_feeds() {
     assert_eq!(alice_profile.postsCount, 3);
 
     // test alice timeline: still empty (?)
-    let alice_timeline = bsky_get_timeline(&mut srv, &alice_did).unwrap();
+    let alice_timeline = bsky_get_timeline(&mut srv, &alice_did, 50, None).unwrap();
     println!("{alice_timeline:?}");
     assert!(alice_timeline.feed.is_empty());
 
     // test alice feed: should have 3 posts, with correct counts
-    let alice_feed = bsky_get_author_feed(&mut srv, &alice_did).unwrap();
+    let alice_feed = bsky_get_author_feed(&mut srv, &alice_did, None, 50, None).unwrap();
     assert_eq!(alice_feed.feed.len(), 3);
 
     assert_eq!(
@@ -635,7 +1735,7 @@ fn test_bsky_feeds() {
     assert_eq!(alice_feed.feed[0].post.upvoteCount, 0);
 
     // test bob timeline: should include alice posts
-    let bob_timeline = bsky_get_timeline(&mut srv, &bob_did).unwrap();
+    let bob_timeline = bsky_get_timeline(&mut srv, &bob_did, 50, None).unwrap();
     println!("BOB TIMELINE ======");
     for item in bob_timeline.feed.iter() {
         println!("{item:?}");
@@ -650,7 +1750,7 @@ fn test_bsky_feeds() {
     assert_eq!(bob_timeline.feed[2].post.author.handle, "alice.test");
 
     // test bob feed: should include repost and reply
-    let bob_feed = bsky_get_author_feed(&mut srv, &bob_did).unwrap();
+    let bob_feed = bsky_get_author_feed(&mut srv, &bob_did, None, 50, None).unwrap();
     assert_eq!(bob_feed.feed.len(), 1);
     // TODO: handle reposts
     /*
@@ -666,13 +1766,13 @@ fn test_bsky_feeds() {
     assert_eq!(bob_feed.feed[0].post.author.handle, "bob.test");
 
     // test carol timeline: should include bob's repost and reply
-    let carol_timeline = bsky_get_timeline(&mut srv, &carol_did).unwrap();
+    let carol_timeline = bsky_get_timeline(&mut srv, &carol_did, 50, None).unwrap();
     // TODO: handle re-posts (+1 here)
     assert_eq!(carol_timeline.feed.len(), 1);
     // TODO: details
 
     // test carol feed: still empty
-    let carol_feed = bsky_get_author_feed(&mut srv, &carol_did).unwrap();
+    let carol_feed = bsky_get_author_feed(&mut srv, &carol_did, None, 50, None).unwrap();
     assert!(carol_feed.feed.is_empty());
 }
 
@@ -692,6 +1792,7 @@ fn test_bsky_thread() {
             password: "bogus".to_string(),
             inviteCode: None,
             recoveryKey: None,
+            did: None,
         };
         let session = create_account(&mut srv, &req, true).unwrap();
         Did::from_str(&session.did).unwrap()
@@ -703,6 +1804,7 @@ fn test_bsky_thread() {
             password: "bogus".to_string(),
             inviteCode: None,
             recoveryKey: None,
+            did: None,
         };
         let session = create_account(&mut srv, &req, true).unwrap();
         Did::from_str(&session.did).unwrap()
@@ -718,7 +1820,7 @@ fn test_bsky_thread() {
     srv.repo
         .mutate_repo(&alice_did, &mutations, &srv.pds_keypair)
         .unwrap();
-    bsky_mutate_db(&mut srv.atp_db, &alice_did, mutations).unwrap();
+    bsky_mutate_db(&srv.atp_db, &alice_did, mutations).unwrap();
     let alice_post1_uri = format!("at://{}/{}/{}", alice_did, post_nsid, alice_post1_tid);
 
     // bob likes and replies first post
@@ -731,7 +1833,7 @@ fn test_bsky_thread() {
     srv.repo
         .mutate_repo(&bob_did, &mutations, &srv.pds_keypair)
         .unwrap();
-    bsky_mutate_db(&mut srv.atp_db, &bob_did, mutations).unwrap();
+    bsky_mutate_db(&srv.atp_db, &bob_did, mutations).unwrap();
     let bob_post1_uri = format!("at://{}/{}/{}", bob_did, post_nsid, bob_post1_tid);
 
     // alice replies to bob reply
@@ -739,19 +1841,26 @@ fn test_bsky_thread() {
     let mutations = vec![Mutation::Create(
         post_nsid.clone(),
         alice_post2_tid.clone(),
-        ipld!({"text": "alice second post, replying to bob comment", "reply": {"parent": {"uri": bob_post1_uri.clone()}, "root": {"uri": alice_post1_uri}}}),
+        ipld!({"text": "alice second post, replying to bob comment", "reply": {"parent": {"uri": bob_post1_uri.clone()}, "root": {"uri": alice_post1_uri.clone()}}}),
     )];
     srv.repo
         .mutate_repo(&alice_did, &mutations, &srv.pds_keypair)
         .unwrap();
-    bsky_mutate_db(&mut srv.atp_db, &alice_did, mutations).unwrap();
-    let _alice_post2_uri = format!("at://{}/{}/{}", alice_did, post_nsid, alice_post2_tid);
+    bsky_mutate_db(&srv.atp_db, &alice_did, mutations).unwrap();
+    let alice_post2_uri = format!("at://{}/{}/{}", alice_did, post_nsid, alice_post2_tid);
 
     // get thread from bob's post
-    // TODO: should have both parent and children
-    let post = bsky_get_thread(&mut srv, &AtUri::from_str(&bob_post1_uri).unwrap(), None)
-        .unwrap()
-        .thread;
+    let post = bsky_get_thread(
+        &mut srv,
+        &AtUri::from_str(&bob_post1_uri).unwrap(),
+        None,
+        None,
+        None,
+        50,
+        None,
+    )
+    .unwrap()
+    .thread;
     let ppost = post.post.unwrap();
     assert_eq!(ppost.author.did, bob_did.to_string());
     assert_eq!(ppost.author.handle, "bob.test".to_string());
@@ -766,5 +1875,1038 @@ fn test_bsky_thread() {
         post_replies[0].post.as_ref().unwrap().author.did,
         alice_did.to_string()
     );
-    // TODO: root URI, etc
+    let parent = post.parent.unwrap();
+    assert_eq!(parent.post.unwrap().uri, alice_post1_uri);
+    assert_eq!(parent.parent, None);
+
+    // nested replies, limited by depth
+    let thread_uri = AtUri::from_str(&alice_post1_uri).unwrap();
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, None, None, 50, None)
+        .unwrap()
+        .thread;
+    assert_eq!(thread.parent, None);
+    let nested = thread.replies.unwrap()[0].replies.clone().unwrap();
+    assert_eq!(nested[0].post.as_ref().unwrap().uri, alice_post2_uri);
+    assert_eq!(nested[0].replies, Some(vec![]));
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, Some(1), None, 50, None)
+        .unwrap()
+        .thread;
+    assert_eq!(thread.replies.unwrap()[0].replies, None);
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, Some(0), None, 50, None)
+        .unwrap()
+        .thread;
+    assert_eq!(thread.replies, None);
+
+    // ancestors, limited by parent height
+    let thread_uri = AtUri::from_str(&alice_post2_uri).unwrap();
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, None, None, 50, None)
+        .unwrap()
+        .thread;
+    let parent = thread.parent.unwrap();
+    assert_eq!(parent.post.as_ref().unwrap().uri, bob_post1_uri);
+    assert_eq!(
+        parent.parent.unwrap().post.unwrap().uri,
+        alice_post1_uri.clone()
+    );
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, None, Some(1), 50, None)
+        .unwrap()
+        .thread;
+    assert_eq!(thread.parent.unwrap().parent, None);
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, None, Some(0), 50, None)
+        .unwrap()
+        .thread;
+    assert_eq!(thread.parent, None);
+
+    // missing parent posts are included as "not found"
+    let bob_post2_tid = srv.tid_gen.next_tid();
+    let missing_uri = format!(
+        "at://{}/{}/{}",
+        alice_did,
+        post_nsid,
+        srv.tid_gen.next_tid()
+    );
+    let mutations = vec![Mutation::Create(
+        post_nsid.clone(),
+        bob_post2_tid.clone(),
+        ipld!({"text": "reply to deleted post", "reply": {"parent": {"uri": missing_uri.clone()}, "root": {"uri": missing_uri.clone()}}}),
+    )];
+    srv.repo
+        .mutate_repo(&bob_did, &mutations, &srv.pds_keypair)
+        .unwrap();
+    bsky_mutate_db(&srv.atp_db, &bob_did, mutations).unwrap();
+    let bob_post2_uri = format!("at://{}/{}/{}", bob_did, post_nsid, bob_post2_tid);
+    let thread = bsky_get_thread(
+        &mut srv,
+        &AtUri::from_str(&bob_post2_uri).unwrap(),
+        None,
+        None,
+        None,
+        50,
+        None,
+    )
+    .unwrap()
+    .thread;
+    let parent = thread.parent.unwrap();
+    assert_eq!(parent.notFound, Some(true));
+    assert_eq!(parent.uri, Some(missing_uri));
+
+    // posts by deactivated accounts are hidden from threads and author feeds
+    srv.atp_db.set_account_deactivated(&bob_did, true).unwrap();
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, None, None, 50, None)
+        .unwrap()
+        .thread;
+    assert_eq!(thread.parent.unwrap().notFound, Some(true));
+    let thread_uri = AtUri::from_str(&alice_post1_uri).unwrap();
+    let thread = bsky_get_thread(&mut srv, &thread_uri, None, None, None, 50, None)
+        .unwrap()
+        .thread;
+    assert_eq!(thread.replies, Some(vec![]));
+    let bob_thread_uri = AtUri::from_str(&bob_post1_uri).unwrap();
+    assert!(bsky_get_thread(&mut srv, &bob_thread_uri, None, None, None, 50, None).is_err());
+    assert_eq!(
+        bsky_get_author_feed(&mut srv, &bob_did, None, 50, None)
+            .unwrap()
+            .feed
+            .len(),
+        0
+    );
+    srv.atp_db.set_account_deactivated(&bob_did, false).unwrap();
+    assert_eq!(
+        bsky_get_author_feed(&mut srv, &bob_did, None, 50, None)
+            .unwrap()
+            .feed
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn test_update_repo() {
+    use crate::{create_account, update_repo};
+    use adenosine::com_atproto;
+    use adenosine::crypto::KeyPair;
+    use libipld::ipld;
+
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let alice_did = {
+        let req = com_atproto::AccountRequest {
+            email: "alice@bogus.com".to_string(),
+            handle: "alice.test".to_string(),
+            password: "bogus".to_string(),
+            inviteCode: None,
+            recoveryKey: None,
+            did: None,
+        };
+        let session = create_account(&mut srv, &req, true).unwrap();
+        Did::from_str(&session.did).unwrap()
+    };
+    let head0 = srv.repo.lookup_commit(&alice_did).unwrap().unwrap();
+
+    // prepare a commit "elsewhere", then reset the head before pushing it
+    let keypair = srv.pds_keypair.clone();
+    let mutations = vec![Mutation::Create(
+        post_nsid.clone(),
+        srv.tid_gen.next_tid(),
+        ipld!({"text": "pushed post", "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    let commit1 = srv
+        .repo
+        .mutate_repo(&alice_did, &mutations, &keypair)
+        .unwrap();
+    let car1 = srv.repo.export_car(&commit1, None).unwrap();
+    srv.repo.link_repo(&alice_did, &head0).unwrap();
+    assert_eq!(
+        bsky_get_author_feed(&mut srv, &alice_did, None, 50, None)
+            .unwrap()
+            .feed
+            .len(),
+        0
+    );
+
+    assert_eq!(update_repo(&mut srv, &alice_did, &car1).unwrap(), commit1);
+    assert_eq!(srv.repo.lookup_commit(&alice_did).unwrap(), Some(commit1));
+    assert_eq!(
+        bsky_get_author_feed(&mut srv, &alice_did, None, 50, None)
+            .unwrap()
+            .feed
+            .len(),
+        1
+    );
+    // pushing the current head again is a no-op
+    assert_eq!(update_repo(&mut srv, &alice_did, &car1).unwrap(), commit1);
+
+    // a commit on top of the old head is not a fast-forward
+    srv.repo.link_repo(&alice_did, &head0).unwrap();
+    let mutations = vec![Mutation::Create(
+        post_nsid.clone(),
+        srv.tid_gen.next_tid(),
+        ipld!({"text": "forked post", "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    let fork_commit = srv
+        .repo
+        .mutate_repo(&alice_did, &mutations, &keypair)
+        .unwrap();
+    let fork_car = srv.repo.export_car(&fork_commit, None).unwrap();
+    srv.repo.link_repo(&alice_did, &commit1).unwrap();
+    assert!(update_repo(&mut srv, &alice_did, &fork_car).is_err());
+
+    // a commit signed by some other key is rejected
+    let mutations = vec![Mutation::Delete(post_nsid.clone(), srv.tid_gen.next_tid())];
+    let bad_commit = srv
+        .repo
+        .mutate_repo(&alice_did, &mutations, &KeyPair::new_random())
+        .unwrap();
+    let bad_car = srv.repo.export_car(&bad_commit, None).unwrap();
+    srv.repo.link_repo(&alice_did, &commit1).unwrap();
+    assert!(update_repo(&mut srv, &alice_did, &bad_car).is_err());
+    assert_eq!(srv.repo.lookup_commit(&alice_did).unwrap(), Some(commit1));
+    assert_eq!(
+        bsky_get_author_feed(&mut srv, &alice_did, None, 50, None)
+            .unwrap()
+            .feed
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_bsky_reindex() {
+    use libipld::ipld;
+
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let follow_nsid = Nsid::from_str("app.bsky.graph.follow").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(&mut srv, &["alice.test", "bob.test"]);
+    let (alice_did, bob_did) = (dids[0].clone(), dids[1].clone());
+
+    let post_tid = srv.tid_gen.next_tid();
+    let follow_tid = srv.tid_gen.next_tid();
+    let mutations = vec![
+        Mutation::Create(
+            post_nsid.clone(),
+            post_tid.clone(),
+            ipld!({"text": "first draft", "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        Mutation::Create(
+            follow_nsid.clone(),
+            follow_tid.clone(),
+            ipld!({"subject": {"did": bob_did.to_string(), "declarationCid": "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u"}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+    ];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    // updates replace the existing index row
+    let mutations = vec![Mutation::Update(
+        post_nsid.clone(),
+        post_tid.clone(),
+        ipld!({"text": "final version", "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    let feed = bsky_get_author_feed(&mut srv, &alice_did, None, 50, None)
+        .unwrap()
+        .feed;
+    assert_eq!(feed.len(), 1);
+    assert_eq!(feed[0].post.record.text, "final version");
+
+    // re-indexing keeps the original indexed_at timestamps, and removes rows for records which
+    // are not in the repo
+    let indexed_at = |srv: &mut AtpService| {
+        srv.atp_db
+            .list_records(&alice_did, &post_nsid, 10, None, None, false)
+            .unwrap()
+            .iter()
+            .map(|row| row.indexed_at.clone())
+            .collect::<Vec<String>>()
+    };
+    let before = indexed_at(&mut srv);
+    let stray = vec![Mutation::Create(
+        post_nsid.clone(),
+        srv.tid_gen.next_tid(),
+        ipld!({"text": "not in the repo", "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    bsky_mutate_db(&srv.atp_db, &alice_did, stray).unwrap();
+    assert_eq!(indexed_at(&mut srv).len(), 2);
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    bsky_reindex(&mut srv, &alice_did).unwrap();
+    assert_eq!(indexed_at(&mut srv), before);
+    assert_eq!(
+        bsky_get_author_feed(&mut srv, &alice_did, None, 50, None)
+            .unwrap()
+            .feed
+            .len(),
+        1
+    );
+
+    // simulate index drift, then re-generate
+    srv.atp_db.bsky_delete_did(&alice_did).unwrap();
+    assert_eq!(
+        bsky_get_author_feed(&mut srv, &alice_did, None, 50, None)
+            .unwrap()
+            .feed
+            .len(),
+        0
+    );
+    // re-indexing is idempotent; the count includes records created with the account
+    let count = bsky_reindex(&mut srv, &alice_did).unwrap();
+    assert_eq!(bsky_reindex(&mut srv, &alice_did).unwrap(), count);
+    let feed = bsky_get_author_feed(&mut srv, &alice_did, None, 50, None)
+        .unwrap()
+        .feed;
+    assert_eq!(feed.len(), 1);
+    assert_eq!(feed[0].post.record.text, "final version");
+
+    // deleted records are not re-indexed
+    let mutations = vec![Mutation::Delete(follow_nsid.clone(), follow_tid.clone())];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    assert_eq!(bsky_reindex(&mut srv, &alice_did).unwrap(), count - 1);
+
+    assert_eq!(srv.atp_db.indexed_repo_dids().unwrap(), {
+        let mut dids = vec![alice_did.clone(), bob_did.clone()];
+        dids.sort();
+        dids
+    });
+}
+
+#[test]
+fn test_bsky_remote_author_feed() {
+    use crate::db::RemoteRepo;
+    use libipld::ipld;
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let bob_did = Did::from_str("did:plc:remotebob").unwrap();
+    let post = ipld!({"text": "hello from elsewhere", "createdAt": "2022-11-01T00:00:00.000Z"});
+    // the record block would be in the blockstore after a remote sync
+    srv.repo.put_ipld(&post).unwrap();
+    let mutations = vec![Mutation::Create(
+        Nsid::from_str("app.bsky.feed.post").unwrap(),
+        srv.tid_gen.next_tid(),
+        post,
+    )];
+    bsky_mutate_db(&srv.atp_db, &bob_did, mutations).unwrap();
+
+    // without a verified handle, the DID is shown instead
+    let feed = bsky_get_author_feed(&mut srv, &bob_did, None, 50, None)
+        .unwrap()
+        .feed;
+    assert_eq!(feed.len(), 1);
+    assert_eq!(feed[0].post.author.did, bob_did.to_string());
+    assert_eq!(feed[0].post.author.handle, bob_did.to_string());
+
+    // same in the timeline of a local account following the remote author
+    let alice_did = test_accounts(&mut srv, &["alice.test"]).remove(0);
+    let mutations = vec![Mutation::Create(
+        Nsid::from_str("app.bsky.graph.follow").unwrap(),
+        srv.tid_gen.next_tid(),
+        ipld!({"subject": {"did": bob_did.to_string(), "declarationCid": "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u"}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    let timeline = bsky_get_timeline(&mut srv, &alice_did, 50, None)
+        .unwrap()
+        .feed;
+    assert_eq!(timeline.len(), 1);
+    assert_eq!(timeline[0].post.author.handle, bob_did.to_string());
+
+    srv.atp_db
+        .put_remote_repo(&RemoteRepo {
+            did: bob_did.clone(),
+            handle: Some("bob.remote.test".to_string()),
+            pds_url: Some("https://pds.remote.test".to_string()),
+            head_cid: None,
+        })
+        .unwrap();
+    let feed = bsky_get_author_feed(&mut srv, &bob_did, None, 50, None)
+        .unwrap()
+        .feed;
+    assert_eq!(feed[0].post.author.handle, "bob.remote.test");
+}
+
+#[test]
+fn test_bsky_feed_pagination() {
+    use libipld::ipld;
+
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let follow_nsid = Nsid::from_str("app.bsky.graph.follow").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(&mut srv, &["alice.test", "bob.test"]);
+    let (alice_did, bob_did) = (dids[0].clone(), dids[1].clone());
+
+    let mut mutations = vec![];
+    for i in 0..5 {
+        mutations.push(Mutation::Create(
+            post_nsid.clone(),
+            srv.tid_gen.next_tid(),
+            ipld!({"text": format!("post {i}"), "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ));
+    }
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    let mutations = vec![Mutation::Create(
+        follow_nsid,
+        srv.tid_gen.next_tid(),
+        ipld!({"subject": {"did": alice_did.to_string(), "declarationCid": "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u"}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    commit_mutations(&mut srv, &bob_did, mutations).unwrap();
+
+    // pages of 2, 2, 1; newest first
+    let mut texts: Vec<String> = vec![];
+    let mut cursor: Option<String> = None;
+    for expected_len in [2, 2, 1] {
+        let page = bsky_get_author_feed(&mut srv, &alice_did, None, 2, cursor.as_deref()).unwrap();
+        assert_eq!(page.feed.len(), expected_len);
+        texts.extend(page.feed.into_iter().map(|item| item.post.record.text));
+        cursor = page.cursor;
+    }
+    assert_eq!(cursor, None);
+    assert_eq!(
+        texts,
+        vec!["post 4", "post 3", "post 2", "post 1", "post 0"]
+    );
+
+    let page1 = bsky_get_timeline(&mut srv, &bob_did, 3, None).unwrap();
+    assert_eq!(page1.feed.len(), 3);
+    let page2 = bsky_get_timeline(&mut srv, &bob_did, 3, page1.cursor.as_deref()).unwrap();
+    assert_eq!(page2.feed.len(), 2);
+    assert_eq!(page2.feed[0].post.record.text, "post 1");
+    assert_eq!(page2.cursor, None);
+
+    assert!(bsky_get_timeline(&mut srv, &bob_did, 3, Some("bogus")).is_err());
+}
+
+#[test]
+fn test_bsky_notifications() {
+    use libipld::ipld;
+
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let like_nsid = Nsid::from_str("app.bsky.feed.like").unwrap();
+    let follow_nsid = Nsid::from_str("app.bsky.graph.follow").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(&mut srv, &["alice.test", "bob.test"]);
+    let (alice_did, bob_did) = (dids[0].clone(), dids[1].clone());
+
+    let alice_post_tid = srv.tid_gen.next_tid();
+    let alice_post_uri = format!("at://{alice_did}/{post_nsid}/{alice_post_tid}");
+    let alice_post_cid = "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u";
+    let mutations = vec![
+        Mutation::Create(
+            post_nsid.clone(),
+            alice_post_tid,
+            ipld!({"text": "hello world", "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        // liking your own post doesn't notify
+        Mutation::Create(
+            like_nsid.clone(),
+            srv.tid_gen.next_tid(),
+            ipld!({"subject": {"uri": alice_post_uri.clone(), "cid": alice_post_cid}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+    ];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    assert_eq!(bsky_notification_count(&mut srv, &alice_did).unwrap(), 0);
+
+    let bob_like_tid = srv.tid_gen.next_tid();
+    let mutations = vec![
+        Mutation::Create(
+            like_nsid.clone(),
+            bob_like_tid.clone(),
+            ipld!({"subject": {"uri": alice_post_uri.clone(), "cid": alice_post_cid}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        Mutation::Create(
+            post_nsid.clone(),
+            srv.tid_gen.next_tid(),
+            ipld!({
+                "text": "@alice.test hi!",
+                "reply": {
+                    "parent": {"uri": alice_post_uri.clone(), "cid": alice_post_cid},
+                    "root": {"uri": alice_post_uri.clone(), "cid": alice_post_cid},
+                },
+                "entities": [
+                    {"index": {"start": 0, "end": 11}, "type": "mention", "value": alice_did.to_string()},
+                ],
+                "createdAt": "2022-11-01T00:00:00.000Z",
+            }),
+        ),
+        Mutation::Create(
+            follow_nsid,
+            srv.tid_gen.next_tid(),
+            ipld!({"subject": {"did": alice_did.to_string(), "declarationCid": alice_post_cid}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+    ];
+    commit_mutations(&mut srv, &bob_did, mutations).unwrap();
+    assert_eq!(bsky_notification_count(&mut srv, &alice_did).unwrap(), 4);
+    assert_eq!(bsky_notification_count(&mut srv, &bob_did).unwrap(), 0);
+
+    let list = bsky_list_notifications(&mut srv, &alice_did, 50, None).unwrap();
+    let mut reasons: Vec<&str> = list
+        .notifications
+        .iter()
+        .map(|n| n.reason.as_str())
+        .collect();
+    reasons.sort();
+    assert_eq!(reasons, vec!["follow", "like", "mention", "reply"]);
+    let reply = list
+        .notifications
+        .iter()
+        .find(|n| n.reason == "reply")
+        .unwrap();
+    assert_eq!(reply.author.handle, "bob.test");
+    assert_eq!(
+        reply.reasonSubject.as_deref(),
+        Some(alice_post_uri.as_str())
+    );
+    assert_eq!(reply.record["text"], "@alice.test hi!");
+    assert!(!reply.isRead);
+
+    // pagination
+    let page1 = bsky_list_notifications(&mut srv, &alice_did, 3, None).unwrap();
+    assert_eq!(page1.notifications.len(), 3);
+    let page2 = bsky_list_notifications(&mut srv, &alice_did, 3, page1.cursor.as_deref()).unwrap();
+    assert_eq!(page2.notifications.len(), 1);
+    assert_eq!(page2.cursor, None);
+
+    // un-liking removes the notification
+    let mutations = vec![Mutation::Delete(like_nsid, bob_like_tid)];
+    commit_mutations(&mut srv, &bob_did, mutations).unwrap();
+    assert_eq!(bsky_notification_count(&mut srv, &alice_did).unwrap(), 3);
+
+    // timestamps are validated, and compared in UTC
+    assert!(matches!(
+        bsky_update_seen(&mut srv, &alice_did, "yesterday")
+            .unwrap_err()
+            .downcast_ref::<XrpcError>(),
+        Some(XrpcError::BadRequest(_))
+    ));
+    bsky_update_seen(&mut srv, &alice_did, "2000-01-01T09:00:00+08:00").unwrap();
+    assert_eq!(bsky_notification_count(&mut srv, &alice_did).unwrap(), 3);
+
+    // seen status survives re-indexing, which doesn't create duplicates
+    bsky_update_seen(&mut srv, &alice_did, "2100-01-01T08:00:00+08:00").unwrap();
+    assert_eq!(bsky_notification_count(&mut srv, &alice_did).unwrap(), 0);
+    bsky_reindex(&mut srv, &bob_did).unwrap();
+    assert_eq!(bsky_notification_count(&mut srv, &alice_did).unwrap(), 0);
+    let list = bsky_list_notifications(&mut srv, &alice_did, 50, None).unwrap();
+    assert_eq!(list.notifications.len(), 3);
+    assert!(list.notifications.iter().all(|n| n.isRead));
+}
+
+#[test]
+fn test_bsky_follows() {
+    use libipld::ipld;
+
+    let follow_nsid = Nsid::from_str("app.bsky.graph.follow").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(&mut srv, &["alice.test", "bob.test", "carol.test"]);
+    let (alice_did, bob_did, carol_did) = (dids[0].clone(), dids[1].clone(), dids[2].clone());
+    bsky_update_profile(
+        &mut srv,
+        &bob_did,
+        app_bsky::UpdateProfile {
+            displayName: Some("Bob".to_string()),
+            description: None,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let follow = |did: &Did| ipld!({"subject": {"did": did.to_string(), "declarationCid": "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u"}, "createdAt": "2022-11-01T00:00:00.000Z"});
+    for (follower, subject) in [
+        (&bob_did, &alice_did),
+        (&carol_did, &alice_did),
+        (&alice_did, &bob_did),
+    ] {
+        let mutations = vec![Mutation::Create(
+            follow_nsid.clone(),
+            srv.tid_gen.next_tid(),
+            follow(subject),
+        )];
+        commit_mutations(&mut srv, follower, mutations).unwrap();
+    }
+
+    let followers = bsky_get_followers(&mut srv, &alice_did, 50, None).unwrap();
+    assert_eq!(followers.subject.handle, "alice.test");
+    let handles: Vec<&str> = followers
+        .followers
+        .iter()
+        .map(|f| f.handle.as_str())
+        .collect();
+    assert_eq!(handles, vec!["carol.test", "bob.test"]);
+    assert_eq!(followers.followers[1].displayName.as_deref(), Some("Bob"));
+    assert!(followers.followers[0].subject.cid.is_some());
+    assert_eq!(followers.cursor, None);
+
+    let follows = bsky_get_follows(&mut srv, &alice_did, 50, None).unwrap();
+    assert_eq!(follows.follows.len(), 1);
+    assert_eq!(follows.follows[0].did, bob_did.to_string());
+    assert_eq!(follows.follows[0].displayName.as_deref(), Some("Bob"));
+    assert!(bsky_get_follows(&mut srv, &carol_did, 50, None)
+        .unwrap()
+        .follows[0]
+        .subject
+        .uri
+        .starts_with(&format!("at://{carol_did}/app.bsky.graph.follow/")));
+
+    // pagination
+    let page1 = bsky_get_followers(&mut srv, &alice_did, 1, None).unwrap();
+    assert_eq!(page1.followers[0].handle, "carol.test");
+    let page2 = bsky_get_followers(&mut srv, &alice_did, 1, page1.cursor.as_deref()).unwrap();
+    assert_eq!(page2.followers[0].handle, "bob.test");
+    let page3 = bsky_get_followers(&mut srv, &alice_did, 1, page2.cursor.as_deref()).unwrap();
+    assert!(page3.followers.is_empty());
+
+    // profile counts agree
+    let profile = bsky_get_profile(&mut srv, &alice_did).unwrap();
+    assert_eq!(profile.followersCount, 2);
+    assert_eq!(profile.followsCount, 1);
+}
+
+#[test]
+fn test_bsky_liked_by() {
+    use libipld::ipld;
+
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let like_nsid = Nsid::from_str("app.bsky.feed.like").unwrap();
+    let repost_nsid = Nsid::from_str("app.bsky.feed.repost").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(&mut srv, &["alice.test", "bob.test", "carol.test"]);
+    let (alice_did, bob_did, carol_did) = (dids[0].clone(), dids[1].clone(), dids[2].clone());
+
+    let post_tid = srv.tid_gen.next_tid();
+    let post_uri = format!("at://{alice_did}/{post_nsid}/{post_tid}");
+    let post_cid = "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u";
+    let mutations = vec![Mutation::Create(
+        post_nsid,
+        post_tid,
+        ipld!({"text": "hello world", "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+
+    let subject = ipld!({"subject": {"uri": post_uri.clone(), "cid": post_cid}, "createdAt": "2022-11-01T00:00:00.000Z"});
+    let bob_like_tid = srv.tid_gen.next_tid();
+    let bob_repost_tid = srv.tid_gen.next_tid();
+    let mutations = vec![
+        Mutation::Create(like_nsid.clone(), bob_like_tid.clone(), subject.clone()),
+        Mutation::Create(repost_nsid, bob_repost_tid.clone(), subject.clone()),
+    ];
+    commit_mutations(&mut srv, &bob_did, mutations).unwrap();
+    let mutations = vec![Mutation::Create(like_nsid, srv.tid_gen.next_tid(), subject)];
+    commit_mutations(&mut srv, &carol_did, mutations).unwrap();
+
+    let liked_by = bsky_get_liked_by(&mut srv, &post_uri, None, 50, None).unwrap();
+    let handles: Vec<&str> = liked_by
+        .likedBy
+        .iter()
+        .map(|v| v.actor.handle.as_str())
+        .collect();
+    assert_eq!(handles, vec!["carol.test", "bob.test"]);
+    assert_eq!(liked_by.cursor, None);
+    let reposted_by = bsky_get_reposted_by(&mut srv, &post_uri, None, 50, None).unwrap();
+    assert_eq!(reposted_by.repostedBy.len(), 1);
+    assert_eq!(reposted_by.repostedBy[0].actor.did, bob_did.to_string());
+
+    // pagination
+    let page1 = bsky_get_liked_by(&mut srv, &post_uri, None, 1, None).unwrap();
+    assert_eq!(page1.likedBy[0].actor.handle, "carol.test");
+    let page2 = bsky_get_liked_by(&mut srv, &post_uri, None, 1, page1.cursor.as_deref()).unwrap();
+    assert_eq!(page2.likedBy[0].actor.handle, "bob.test");
+    let page3 = bsky_get_liked_by(&mut srv, &post_uri, None, 1, page2.cursor.as_deref()).unwrap();
+    assert!(page3.likedBy.is_empty());
+
+    // viewer state
+    let feed = bsky_get_author_feed(&mut srv, &alice_did, Some(&bob_did), 50, None).unwrap();
+    let viewer = feed.feed[0].post.viewer.clone().unwrap();
+    assert_eq!(
+        viewer.like,
+        Some(format!("at://{bob_did}/app.bsky.feed.like/{bob_like_tid}"))
+    );
+    assert_eq!(
+        viewer.repost,
+        Some(format!(
+            "at://{bob_did}/app.bsky.feed.repost/{bob_repost_tid}"
+        ))
+    );
+    let feed = bsky_get_author_feed(&mut srv, &alice_did, Some(&alice_did), 50, None).unwrap();
+    assert_eq!(
+        feed.feed[0].post.viewer,
+        Some(app_bsky::PostViewerState::default())
+    );
+    let feed = bsky_get_author_feed(&mut srv, &alice_did, None, 50, None).unwrap();
+    assert_eq!(feed.feed[0].post.viewer, None);
+}
+
+#[test]
+fn test_fts_prefix_query() {
+    assert_eq!(fts_prefix_query("alice"), Some("\"alice\"*".to_string()));
+    assert_eq!(
+        fts_prefix_query("@alice.test"),
+        Some("\"alice test\"*".to_string())
+    );
+    assert_eq!(
+        fts_prefix_query("\"bob\" OR carol*"),
+        Some("\"bob\"* \"OR\"* \"carol\"*".to_string())
+    );
+    assert_eq!(fts_prefix_query(" .@ "), None);
+}
+
+#[test]
+fn test_bsky_actor_search() {
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(
+        &mut srv,
+        &["alice.test", "alicia.test", "bob.test", "carol.test"],
+    );
+    let (alice_did, bob_did, carol_did) = (dids[0].clone(), dids[2].clone(), dids[3].clone());
+    bsky_update_profile(
+        &mut srv,
+        &alice_did,
+        app_bsky::UpdateProfile {
+            displayName: Some("Alice Liddell".to_string()),
+            description: Some("down the rabbit hole".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    bsky_update_profile(
+        &mut srv,
+        &bob_did,
+        app_bsky::UpdateProfile {
+            displayName: Some("Alice's Friend Bob".to_string()),
+            description: None,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let search_handles = |srv: &mut AtpService, term: &str| -> Vec<String> {
+        bsky_search_actors(srv, term, 50, None)
+            .unwrap()
+            .users
+            .into_iter()
+            .map(|u| u.handle)
+            .collect()
+    };
+
+    // prefix matching, on handles and display names
+    let mut handles = search_handles(&mut srv, "ali");
+    handles.sort();
+    assert_eq!(handles, vec!["alice.test", "alicia.test", "bob.test"]);
+    assert_eq!(search_handles(&mut srv, "alice.test"), vec!["alice.test"]);
+    assert_eq!(search_handles(&mut srv, "@carol"), vec!["carol.test"]);
+    assert!(search_handles(&mut srv, "...").is_empty());
+
+    // handle matches rank above display name matches
+    assert_eq!(
+        search_handles(&mut srv, "alice"),
+        vec!["alice.test", "bob.test"]
+    );
+
+    // description, and profile updates
+    let results = bsky_search_actors(&mut srv, "rabbit", 50, None).unwrap();
+    assert_eq!(results.users.len(), 1);
+    assert_eq!(results.users[0].did, alice_did.to_string());
+    assert_eq!(
+        results.users[0].displayName.as_deref(),
+        Some("Alice Liddell")
+    );
+    bsky_update_profile(
+        &mut srv,
+        &alice_did,
+        app_bsky::UpdateProfile {
+            displayName: Some("Alice Kingsleigh".to_string()),
+            description: Some("down the rabbit hole".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(search_handles(&mut srv, "liddell").is_empty());
+    assert_eq!(search_handles(&mut srv, "kingsleigh"), vec!["alice.test"]);
+
+    // index survives re-indexing
+    bsky_reindex(&mut srv, &alice_did).unwrap();
+    assert_eq!(search_handles(&mut srv, "rabbit"), vec!["alice.test"]);
+
+    // pagination
+    let page1 = bsky_search_actors(&mut srv, "test", 3, None).unwrap();
+    assert_eq!(page1.users.len(), 3);
+    let page2 = bsky_search_actors(&mut srv, "test", 3, page1.cursor.as_deref()).unwrap();
+    assert_eq!(page2.users.len(), 1);
+    assert_eq!(page2.cursor, None);
+    assert!(bsky_search_actors(&mut srv, "test", 3, Some("bogus")).is_err());
+
+    let typeahead = bsky_search_typeahead(&mut srv, "ali", 2).unwrap();
+    assert_eq!(typeahead.users.len(), 2);
+
+    // handle changes, and deactivated accounts
+    crate::update_handle(&mut srv, &carol_did, "caroline.test").unwrap();
+    assert_eq!(search_handles(&mut srv, "caroline"), vec!["caroline.test"]);
+    srv.atp_db
+        .set_account_deactivated(&carol_did, true)
+        .unwrap();
+    assert!(search_handles(&mut srv, "caroline").is_empty());
+}
+
+#[test]
+fn test_bsky_post_search() {
+    use libipld::ipld;
+
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(&mut srv, &["alice.test", "bob.test"]);
+    let (alice_did, bob_did) = (dids[0].clone(), dids[1].clone());
+
+    let alice_tids = [srv.tid_gen.next_tid(), srv.tid_gen.next_tid()];
+    let mutations = vec![
+        Mutation::Create(
+            post_nsid.clone(),
+            alice_tids[0].clone(),
+            ipld!({"text": "hello world", "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        Mutation::Create(
+            post_nsid.clone(),
+            alice_tids[1].clone(),
+            ipld!({"text": "goodbye, cruel World!", "createdAt": "2022-11-03T00:00:00.000Z"}),
+        ),
+    ];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    let mutations = vec![Mutation::Create(
+        post_nsid.clone(),
+        srv.tid_gen.next_tid(),
+        ipld!({"text": "hello there", "createdAt": "2022-11-02T00:00:00.000Z"}),
+    )];
+    commit_mutations(&mut srv, &bob_did, mutations).unwrap();
+
+    let search_texts = |srv: &mut AtpService, search: PostSearch| -> Vec<String> {
+        bsky_search_posts(srv, &search, None, 50, None)
+            .unwrap()
+            .posts
+            .into_iter()
+            .map(|p| p.record.text)
+            .collect()
+    };
+    let text_search = |text: &str| PostSearch {
+        text: text.to_string(),
+        ..Default::default()
+    };
+
+    // most recent first; prefix and case-insensitive matching
+    assert_eq!(
+        search_texts(&mut srv, text_search("hello")),
+        vec!["hello there", "hello world"]
+    );
+    assert_eq!(
+        search_texts(&mut srv, text_search("wor")),
+        vec!["goodbye, cruel World!", "hello world"]
+    );
+    assert_eq!(
+        search_texts(&mut srv, text_search("hello world")),
+        vec!["hello world"]
+    );
+    assert!(search_texts(&mut srv, text_search("!!")).is_empty());
+
+    // filters
+    assert_eq!(
+        search_texts(
+            &mut srv,
+            PostSearch {
+                author: Some(alice_did.clone()),
+                ..text_search("hello")
+            }
+        ),
+        vec!["hello world"]
+    );
+    assert_eq!(
+        search_texts(
+            &mut srv,
+            PostSearch {
+                since: Some("2022-11-02".to_string()),
+                ..text_search("hello")
+            }
+        ),
+        vec!["hello there"]
+    );
+    assert_eq!(
+        search_texts(
+            &mut srv,
+            PostSearch {
+                until: Some("2022-11-02".to_string()),
+                ..text_search("hello")
+            }
+        ),
+        vec!["hello world"]
+    );
+
+    // pagination
+    let page1 = bsky_search_posts(&mut srv, &text_search("world"), None, 1, None).unwrap();
+    assert_eq!(page1.posts[0].record.text, "goodbye, cruel World!");
+    let page2 = bsky_search_posts(
+        &mut srv,
+        &text_search("world"),
+        None,
+        1,
+        page1.cursor.as_deref(),
+    )
+    .unwrap();
+    assert_eq!(page2.posts[0].record.text, "hello world");
+    let page3 = bsky_search_posts(
+        &mut srv,
+        &text_search("world"),
+        None,
+        1,
+        page2.cursor.as_deref(),
+    )
+    .unwrap();
+    assert!(page3.posts.is_empty());
+
+    // updates and deletes
+    let mutations = vec![
+        Mutation::Update(
+            post_nsid.clone(),
+            alice_tids[0].clone(),
+            ipld!({"text": "hello again", "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        Mutation::Delete(post_nsid, alice_tids[1].clone()),
+    ];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+    assert!(search_texts(&mut srv, text_search("world")).is_empty());
+    assert_eq!(
+        search_texts(&mut srv, text_search("again")),
+        vec!["hello again"]
+    );
+
+    // deactivated accounts are hidden
+    srv.atp_db
+        .set_account_deactivated(&alice_did, true)
+        .unwrap();
+    assert_eq!(
+        search_texts(&mut srv, text_search("hello")),
+        vec!["hello there"]
+    );
+}
+
+#[test]
+fn test_bsky_suggestions() {
+    use libipld::ipld;
+
+    let follow_nsid = Nsid::from_str("app.bsky.graph.follow").unwrap();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let dids = test_accounts(
+        &mut srv,
+        &[
+            "alice.test",
+            "bob.test",
+            "carol.test",
+            "dave.test",
+            "erin.test",
+            "frank.test",
+            "gina.test",
+        ],
+    );
+    let (alice, bob, carol, dave, erin, frank, gina) = (
+        &dids[0], &dids[1], &dids[2], &dids[3], &dids[4], &dids[5], &dids[6],
+    );
+
+    let follow = |did: &Did| ipld!({"subject": {"did": did.to_string(), "declarationCid": "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u"}, "createdAt": "2022-11-01T00:00:00.000Z"});
+    for (follower, subject) in [
+        (alice, bob),
+        (alice, carol),
+        (bob, dave),
+        (carol, dave),
+        (bob, erin),
+        (carol, alice),
+        (dave, erin),
+        (frank, gina),
+    ] {
+        let mutations = vec![Mutation::Create(
+            follow_nsid.clone(),
+            srv.tid_gen.next_tid(),
+            follow(subject),
+        )];
+        commit_mutations(&mut srv, follower, mutations).unwrap();
+    }
+
+    let suggested = |srv: &mut AtpService, viewer: &Did| -> Vec<String> {
+        bsky_get_suggestions(srv, Some(viewer), 50, None)
+            .unwrap()
+            .actors
+            .into_iter()
+            .map(|a| a.handle)
+            .collect()
+    };
+
+    // friends-of-friends by mutual count, then the most-followed local accounts
+    assert_eq!(
+        suggested(&mut srv, alice),
+        vec!["dave.test", "erin.test", "gina.test", "frank.test"]
+    );
+
+    // no follows yet: most-followed accounts first, never self or already-followed
+    let frank_suggestions = suggested(&mut srv, frank);
+    assert_eq!(frank_suggestions.len(), 5);
+    let mut top: Vec<String> = frank_suggestions[..2].to_vec();
+    top.sort();
+    assert_eq!(top, vec!["dave.test", "erin.test"]);
+    assert!(!frank_suggestions.contains(&"frank.test".to_string()));
+    assert!(!frank_suggestions.contains(&"gina.test".to_string()));
+
+    // pagination
+    let page1 = bsky_get_suggestions(&mut srv, Some(alice), 2, None).unwrap();
+    assert_eq!(page1.actors[0].did, dave.to_string());
+    let page2 = bsky_get_suggestions(&mut srv, Some(alice), 2, page1.cursor.as_deref()).unwrap();
+    assert_eq!(page2.actors[0].did, gina.to_string());
+    let page3 = bsky_get_suggestions(&mut srv, Some(alice), 2, page2.cursor.as_deref()).unwrap();
+    assert!(page3.actors.is_empty());
+    assert_eq!(page3.cursor, None);
+
+    // deactivated accounts are not suggested
+    srv.atp_db.set_account_deactivated(dave, true).unwrap();
+    assert_eq!(
+        suggested(&mut srv, alice),
+        vec!["erin.test", "gina.test", "frank.test"]
+    );
+    assert_eq!(
+        bsky_get_suggestions(&mut srv, None, 50, None)
+            .unwrap()
+            .actors[0]
+            .did,
+        erin.to_string()
+    );
+}
+
+#[test]
+fn test_bsky_post_embed() {
+    use libipld::ipld;
+
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let alice_did = test_accounts(&mut srv, &["alice.test"]).remove(0);
+
+    let image_cid = srv.repo.put_blob(b"not really a png").unwrap().to_string();
+    let mutations = vec![Mutation::Create(
+        post_nsid,
+        srv.tid_gen.next_tid(),
+        ipld!({"text": "look", "embed": {"images": [{"image": {"cid": image_cid.clone(), "mimeType": "image/png"}, "alt": "a picture"}], "external": {"uri": "https://example.com", "title": "Example", "description": "an example"}}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    commit_mutations(&mut srv, &alice_did, mutations).unwrap();
+
+    let feed = bsky_get_author_feed(&mut srv, &alice_did, None, 10, None).unwrap();
+    let embed = feed.feed[0].post.embed.clone().unwrap();
+    let image_url = format!(
+        "http://localhost/xrpc/com.atproto.sync.getBlob?did={}&cid={}",
+        alice_did, image_cid
+    );
+    assert_eq!(
+        embed.images,
+        Some(vec![app_bsky::EmbedImageView {
+            thumb: image_url.clone(),
+            fullsize: image_url,
+            alt: "a picture".to_string(),
+        }])
+    );
+    let external = embed.external.unwrap();
+    assert_eq!(external.uri, "https://example.com");
+    assert_eq!(external.thumb, None);
 }
@@ -0,0 +1,278 @@
+/// Implementation of the `com.atproto.sync.subscribeRepos` repository event stream.
+///
+/// Each WebSocket binary message is a "frame": a DAG-CBOR header object (`op` and `t` fields)
+/// followed immediately by a DAG-CBOR body object. Commit events are read from the `repo_seq`
+/// table, using a separate database connection per subscriber so the service mutex is not held.
+use crate::db::{AtpDatabase, RepoEvent};
+use crate::{AtpService, Result, XrpcError};
+use libipld::cbor::DagCborCodec;
+use libipld::prelude::Codec;
+use libipld::{Cid, Ipld};
+use log::{debug, warn};
+use rouille::websocket::SendError;
+use rouille::{Request, Response};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often idle subscriber threads check the database for new events
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of events read from the database in a single query
+const EVENT_BATCH_SIZE: u64 = 100;
+
+/// How long a stream can be idle before a heartbeat frame is sent. Rouille WebSockets can't be
+/// read without blocking (or pinged), so a failed heartbeat is how a disconnected subscriber of an
+/// idle stream is noticed.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum number of concurrent subscribers; each one holds a thread and a database connection
+const MAX_SUBSCRIBERS: usize = 32;
+
+static SUBSCRIBERS: AtomicUsize = AtomicUsize::new(0);
+
+/// One of the `MAX_SUBSCRIBERS` subscriber slots, released when dropped
+struct SubscriberSlot;
+
+impl SubscriberSlot {
+    fn acquire() -> Option<Self> {
+        if SUBSCRIBERS.fetch_add(1, Ordering::SeqCst) >= MAX_SUBSCRIBERS {
+            SUBSCRIBERS.fetch_sub(1, Ordering::SeqCst);
+            None
+        } else {
+            Some(SubscriberSlot)
+        }
+    }
+}
+
+impl Drop for SubscriberSlot {
+    fn drop(&mut self) {
+        SUBSCRIBERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn encode_frame(header: Ipld, body: Ipld) -> Result<Vec<u8>> {
+    let mut frame = DagCborCodec.encode(&header)?;
+    frame.extend(DagCborCodec.encode(&body)?);
+    Ok(frame)
+}
+
+fn ipld_map(fields: Vec<(&str, Ipld)>) -> Ipld {
+    Ipld::Map(
+        fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect::<BTreeMap<String, Ipld>>(),
+    )
+}
+
+/// Encodes a sequenced commit as a `#commit` event frame
+pub fn commit_frame(event: &RepoEvent) -> Result<Vec<u8>> {
+    let mut ops: Vec<Ipld> = vec![];
+    if let Some(op_list) = event.ops.as_array() {
+        for op in op_list {
+            let cid = match op["cid"].as_str() {
+                Some(cid) => Ipld::Link(Cid::from_str(cid)?),
+                None => Ipld::Null,
+            };
+            ops.push(ipld_map(vec![
+                (
+                    "action",
+                    Ipld::String(op["action"].as_str().unwrap_or("").to_string()),
+                ),
+                (
+                    "path",
+                    Ipld::String(op["path"].as_str().unwrap_or("").to_string()),
+                ),
+                ("cid", cid),
+            ]));
+        }
+    }
+    let header = ipld_map(vec![
+        ("op", Ipld::Integer(1)),
+        ("t", Ipld::String("#commit".to_string())),
+    ]);
+    let body = ipld_map(vec![
+        ("seq", Ipld::Integer(event.seq as i128)),
+        ("repo", Ipld::String(event.did.to_string())),
+        ("commit", Ipld::Link(event.commit_cid)),
+        ("prev", event.prev_cid.map(Ipld::Link).unwrap_or(Ipld::Null)),
+        ("ops", Ipld::List(ops)),
+        ("blocks", Ipld::Bytes(event.blocks.clone())),
+        ("time", Ipld::String(event.time.clone())),
+    ]);
+    encode_frame(header, body)
+}
+
+/// Encodes an `#info` frame. `Heartbeat` frames are sent on idle streams (clients ignore info
+/// names they don't know).
+pub fn info_frame(name: &str) -> Result<Vec<u8>> {
+    let header = ipld_map(vec![
+        ("op", Ipld::Integer(1)),
+        ("t", Ipld::String("#info".to_string())),
+    ]);
+    let body = ipld_map(vec![("name", Ipld::String(name.to_string()))]);
+    encode_frame(header, body)
+}
+
+/// Encodes an error frame. The stream is closed after sending one of these.
+pub fn error_frame(error: &str, message: &str) -> Result<Vec<u8>> {
+    let header = ipld_map(vec![("op", Ipld::Integer(-1))]);
+    let body = ipld_map(vec![
+        ("error", Ipld::String(error.to_string())),
+        ("message", Ipld::String(message.to_string())),
+    ]);
+    encode_frame(header, body)
+}
+
+/// Upgrades the request to a WebSocket, and spawns a thread to stream events to the subscriber.
+///
+/// If a `cursor` (sequence number) is provided, all events after that point are replayed first;
+/// otherwise only new events are sent. At most `MAX_SUBSCRIBERS` streams are open at a time.
+pub fn subscribe_repos_handler(srv: &Mutex<AtpService>, request: &Request) -> Result<Response> {
+    let cursor: Option<u64> = match request.get_param("cursor") {
+        None => None,
+        Some(v) => Some(v.parse().map_err(|_| {
+            XrpcError::BadRequest(format!("invalid 'cursor' query parameter: {v}"))
+        })?),
+    };
+    let slot = match SubscriberSlot::acquire() {
+        Some(slot) => slot,
+        None => Err(XrpcError::BadRequest(
+            "too many subscribers, try again later".to_string(),
+        ))?,
+    };
+    let (mut db, latest) = {
+        let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+        let latest = srv.atp_db.latest_repo_seq()?;
+        (srv.atp_db.new_connection()?, latest)
+    };
+    let (response, websocket) = rouille::websocket::start::<String>(request, None)
+        .map_err(|e| XrpcError::BadRequest(format!("WebSocket upgrade failed: {e}")))?;
+
+    std::thread::spawn(move || {
+        let _slot = slot;
+        let mut ws = match websocket.recv() {
+            Ok(ws) => ws,
+            Err(_) => return,
+        };
+        if let Some(cursor) = cursor {
+            if cursor > latest {
+                if let Ok(frame) = error_frame("FutureCursor", "cursor is ahead of the stream") {
+                    let _ = ws.send_binary(&frame);
+                }
+                return;
+            }
+        }
+        if let Err(e) = stream_events(&mut db, &mut ws, cursor.unwrap_or(latest)) {
+            debug!("subscribeRepos stream ended: {}", e);
+        }
+    });
+    Ok(response)
+}
+
+/// Sends a frame; `Ok(false)` means the client has gone away, which is a normal end of the stream
+fn send_frame(ws: &mut rouille::websocket::Websocket, frame: &[u8]) -> Result<bool> {
+    match ws.send_binary(frame) {
+        Ok(()) => Ok(true),
+        Err(SendError::Closed) => Ok(false),
+        Err(SendError::IoError(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(false),
+        Err(SendError::IoError(e)) => Err(e.into()),
+    }
+}
+
+/// Sends events after `cursor` to the WebSocket, then polls for new events until the connection
+/// is closed (which is noticed on the next failed send, or failed heartbeat if idle).
+fn stream_events(
+    db: &mut AtpDatabase,
+    ws: &mut rouille::websocket::Websocket,
+    mut cursor: u64,
+) -> Result<()> {
+    let mut last_sent = Instant::now();
+    while !ws.is_closed() {
+        let events = db.repo_events_since(cursor, EVENT_BATCH_SIZE)?;
+        if events.is_empty() {
+            if last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+                if !send_frame(ws, &info_frame("Heartbeat")?)? {
+                    return Ok(());
+                }
+                last_sent = Instant::now();
+            }
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+        for event in events.iter() {
+            let frame = match commit_frame(event) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("failed to encode repo event seq={}: {}", event.seq, e);
+                    cursor = event.seq;
+                    continue;
+                }
+            };
+            if !send_frame(ws, &frame)? {
+                return Ok(());
+            }
+            last_sent = Instant::now();
+            cursor = event.seq;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_commit_frame() {
+    use adenosine::identifiers::Did;
+    use serde_json::json;
+
+    let commit_cid =
+        Cid::from_str("bafyreiagt55jzvkenoa4yik77dhomagq2uj26ix4cijj7kd2py2u3s43ve").unwrap();
+    let record_cid =
+        Cid::from_str("bafyreicivoa3p3ttcebdn2zfkdzenkd2uk3gxxlaz43qvueeip6yysvq2m").unwrap();
+    let event = RepoEvent {
+        seq: 7,
+        did: Did::from_str("did:plc:dummy").unwrap(),
+        commit_cid,
+        prev_cid: None,
+        ops: json!([
+            {"action": "create", "path": "app.bsky.feed.post/3jqfcqzm3fo2j", "cid": record_cid.to_string()},
+            {"action": "delete", "path": "app.bsky.feed.post/3jqfcqzm3fp2j", "cid": null},
+        ]),
+        blocks: b"car bytes".to_vec(),
+        time: "2022-11-01T00:00:00.000Z".to_string(),
+    };
+    let frame = commit_frame(&event).unwrap();
+    let header_bytes = DagCborCodec
+        .encode(&ipld_map(vec![
+            ("op", Ipld::Integer(1)),
+            ("t", Ipld::String("#commit".to_string())),
+        ]))
+        .unwrap();
+    assert!(frame.starts_with(&header_bytes));
+
+    let body: Ipld = DagCborCodec.decode(&frame[header_bytes.len()..]).unwrap();
+    assert_eq!(body.get("seq").unwrap(), &Ipld::Integer(7));
+    assert_eq!(body.get("commit").unwrap(), &Ipld::Link(commit_cid));
+    assert_eq!(body.get("prev").unwrap(), &Ipld::Null);
+    assert_eq!(
+        body.get("blocks").unwrap(),
+        &Ipld::Bytes(b"car bytes".to_vec())
+    );
+    let ops = body.get("ops").unwrap();
+    assert_eq!(
+        ops.get(0).unwrap().get("cid").unwrap(),
+        &Ipld::Link(record_cid)
+    );
+    assert_eq!(ops.get(1).unwrap().get("cid").unwrap(), &Ipld::Null);
+}
+
+#[test]
+fn test_subscriber_slots() {
+    let slots: Vec<SubscriberSlot> = std::iter::from_fn(SubscriberSlot::acquire).collect();
+    assert_eq!(slots.len(), MAX_SUBSCRIBERS);
+    assert!(SubscriberSlot::acquire().is_none());
+    drop(slots);
+    assert!(SubscriberSlot::acquire().is_some());
+}
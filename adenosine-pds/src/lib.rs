@@ -1,7 +1,8 @@
-use adenosine::created_at_now;
-use adenosine::identifiers::{AtUri, Did, Nsid, Ticker, Tid};
+use adenosine::identifiers::{AtUri, Did, DidOrHost, Nsid, Ticker, Tid};
+use adenosine::{created_at_now, normalize_timestamp};
 use anyhow::{anyhow, Result};
 use askama::Template;
+use libipld::Cid;
 use log::{debug, error, info, warn};
 use rouille::{router, Request, Response};
 use serde_json::{json, Value};
@@ -9,21 +10,31 @@ use std::fmt;
 use std::io::Read;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
 
 mod db;
 mod db_bsky;
+mod firehose;
+pub mod mailer;
+mod remote_sync;
+pub mod spider;
 mod web;
+mod webhook;
 
 use adenosine::app_bsky;
 use adenosine::com_atproto;
-use adenosine::crypto::KeyPair;
+use adenosine::crypto::{KeyPair, PubKey};
 use adenosine::ipld::{ipld_into_json_value, json_value_into_ipld};
 use adenosine::plc;
 use adenosine::plc::DidDocMeta;
 use adenosine::repo::{Mutation, RepoStore};
-use db::AtpDatabase;
+use adenosine::resolve;
+use db::{random_token, AtpDatabase};
+pub use db_bsky::bsky_reindex;
 use db_bsky::*;
+use firehose::*;
+use mailer::{FileMailer, Mailer, SmtpMailer};
 use web::*;
 
 #[derive(Debug)]
@@ -53,6 +64,7 @@ pub struct AtpService {
     pub pds_keypair: KeyPair,
     pub tid_gen: Ticker,
     pub config: AtpServiceConfig,
+    pub mailer: Arc<dyn Mailer>,
 }
 
 #[derive(Clone, Debug)]
@@ -60,8 +72,16 @@ pub struct AtpServiceConfig {
     pub listen_host_port: String,
     pub public_url: String,
     pub registration_domain: Option<String>,
-    pub invite_code: Option<String>,
+    pub invite_code_required: bool,
+    pub admin_password: Option<String>,
     pub homepage_handle: Option<String>,
+    /// SMTP relay ("host:port") for outgoing mail. If not set, mail is written to stdout
+    pub smtp_server: Option<String>,
+    pub mail_from: String,
+    /// PLC directory server, for resolving did:plc identifiers of remote accounts
+    pub plc_host: String,
+    /// How often to sync remote repositories followed by local accounts. Disabled if not set
+    pub remote_sync_interval: Option<std::time::Duration>,
 }
 
 impl Default for AtpServiceConfig {
@@ -70,8 +90,13 @@ impl Default for AtpServiceConfig {
             listen_host_port: "localhost:3030".to_string(),
             public_url: "http://localhost".to_string(),
             registration_domain: None,
-            invite_code: None,
+            invite_code_required: false,
+            admin_password: None,
             homepage_handle: None,
+            smtp_server: None,
+            mail_from: "adenosine-pds@localhost".to_string(),
+            plc_host: "https://plc.directory".to_string(),
+            remote_sync_interval: None,
         }
     }
 }
@@ -129,28 +154,80 @@ impl AtpService {
         keypair: KeyPair,
         config: AtpServiceConfig,
     ) -> Result<Self> {
+        let mailer: Arc<dyn Mailer> = match config.smtp_server {
+            Some(ref server) => Arc::new(SmtpMailer {
+                server: server.clone(),
+                from: config.mail_from.clone(),
+            }),
+            None => Arc::new(FileMailer {
+                path: None,
+                from: config.mail_from.clone(),
+            }),
+        };
         Ok(AtpService {
             repo: RepoStore::open(blockstore_db_path)?,
             atp_db: AtpDatabase::open(atp_db_path)?,
             pds_keypair: keypair,
             tid_gen: Ticker::new(),
             config,
+            mailer,
         })
     }
 
     pub fn new_ephemeral() -> Result<Self> {
+        let config = AtpServiceConfig::default();
         Ok(AtpService {
             repo: RepoStore::open_ephemeral()?,
             atp_db: AtpDatabase::open_ephemeral()?,
             pds_keypair: KeyPair::new_random(),
             tid_gen: Ticker::new(),
-            config: AtpServiceConfig::default(),
+            mailer: Arc::new(FileMailer {
+                path: None,
+                from: config.mail_from.clone(),
+            }),
+            config,
         })
     }
 
-    pub fn run_server(self) -> Result<()> {
+    /// Populates the generic record index for repositories which were indexed before it existed.
+    /// The database migration which added the table can't read repository contents, so it only
+    /// lists the repositories, and this runs at server start instead. Each repository is marked
+    /// once done, so this is only slow once; failures are re-tried at the next start.
+    pub fn backfill_record_index(&mut self) -> Result<()> {
+        for did in self.atp_db.record_backfill_dids()? {
+            let commit_cid = match self.repo.lookup_commit(&did)? {
+                Some(cid) => cid,
+                None => {
+                    self.atp_db.mark_record_backfilled(&did)?;
+                    continue;
+                }
+            };
+            let mst_cid = self.repo.get_commit(&commit_cid)?.mst_cid;
+            if self.repo.mst_to_map(&mst_cid)?.is_empty() {
+                self.atp_db.mark_record_backfilled(&did)?;
+                continue;
+            }
+            match bsky_reindex(self, &did) {
+                Ok(count) => {
+                    info!("backfilled record index for {} ({} records)", did, count);
+                    self.atp_db.mark_record_backfilled(&did)?;
+                }
+                Err(e) => error!("failed to backfill record index for {}: {}", did, e),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run_server(mut self) -> Result<()> {
+        self.backfill_record_index()?;
         let config = self.config.clone();
-        let srv = Mutex::new(self);
+        let webhook_db = self.atp_db.new_connection()?;
+        std::thread::spawn(move || webhook::run_delivery_worker(webhook_db));
+        let srv = Arc::new(Mutex::new(self));
+        if let Some(interval) = config.remote_sync_interval {
+            let srv = srv.clone();
+            std::thread::spawn(move || remote_sync::run_remote_sync_worker(&srv, interval));
+        }
 
         let log_ok = |req: &Request, resp: &Response, elap: std::time::Duration| {
             info!(
@@ -187,11 +264,20 @@ impl AtpService {
                             Err(e) => web_wrap(Err(e)),
                         }
                     },
+                    (GET) ["/.well-known/atproto-did"] => {
+                        match atproto_did_view_handler(&srv, request) {
+                            Ok(resp) => resp,
+                            Err(e) => web_wrap(Err(e)),
+                        }
+                    },
                     (GET) ["/about"] => {
                         let host = request.header("Host").unwrap_or("localhost");
                         let view = AboutView { domain: host.to_string() };
                         Response::html(view.render().unwrap())
                     },
+                    (GET) ["/search"] => {
+                        web_wrap(search_view_handler(&srv, request))
+                    },
                     (GET) ["/u/{handle}", handle: String] => {
                         web_wrap(account_view_handler(&srv, &handle, request))
                     },
@@ -243,6 +329,23 @@ impl AtpService {
                             }
                         }
                     },
+                    (GET) ["/xrpc/com.atproto.sync.getBlob"] => {
+                        // raw bytes, not JSON; blobs are content-addressed, so can be cached.
+                        // browsers must not sniff user content in to some other (eg, HTML) type
+                        match xrpc_get_blob_handler(&srv, request) {
+                            Ok((mime_type, blob)) => Response::from_data(mime_type, blob)
+                                .with_unique_header("X-Content-Type-Options", "nosniff")
+                                .with_public_cache(86400),
+                            Err(e) => xrpc_wrap::<Value>(Err(e)),
+                        }
+                    },
+                    (GET) ["/xrpc/com.atproto.sync.subscribeRepos"] => {
+                        // WebSocket upgrade, not JSON; errors before the upgrade are still JSON
+                        match subscribe_repos_handler(&srv, request) {
+                            Ok(resp) => resp,
+                            Err(e) => xrpc_wrap::<Value>(Err(e)),
+                        }
+                    },
                     (GET) ["/xrpc/{endpoint}", endpoint: String] => {
                         xrpc_wrap(xrpc_get_handler(&srv, &endpoint, request))
                     },
@@ -259,6 +362,132 @@ fn xrpc_required_param(request: &Request, key: &str) -> Result<String> {
     )))?)
 }
 
+/// Levels of nested replies shown on web thread pages
+const WEB_THREAD_DEPTH: u64 = 4;
+
+/// Number of parent posts shown on web thread pages
+const WEB_THREAD_PARENT_HEIGHT: u64 = 10;
+
+/// Largest blob (eg, image) which can be uploaded, in bytes
+const BLOB_MAX_SIZE: u64 = 1_000_000;
+
+/// Blob types accepted for upload. Blobs are served from the PDS domain, so this is limited to
+/// image formats (and not, eg, HTML)
+const BLOB_MIME_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Checks that uploaded blob content starts with the file signature ("magic bytes") of the claimed
+/// type (one of `BLOB_MIME_TYPES`), so that other content can't be served under an image type
+fn blob_matches_mime_type(mime_type: &str, blob: &[u8]) -> bool {
+    match mime_type {
+        "image/png" => blob.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => blob.starts_with(&[0xFF, 0xD8, 0xFF]),
+        "image/gif" => blob.starts_with(b"GIF87a") || blob.starts_with(b"GIF89a"),
+        "image/webp" => blob.len() >= 12 && &blob[0..4] == b"RIFF" && &blob[8..12] == b"WEBP",
+        _ => false,
+    }
+}
+
+/// How long email verification tokens are valid for, as an SQLite date modifier
+const EMAIL_VERIFY_EXPIRY: &str = "+24 hours";
+
+/// How long password reset tokens are valid for, as an SQLite date modifier
+const PASSWORD_RESET_EXPIRY: &str = "+1 hour";
+
+/// Parses a required query parameter which is either a DID or the handle of a local account
+fn xrpc_actor_param(srv: &mut AtpService, request: &Request, key: &str) -> Result<Did> {
+    let actor = xrpc_required_param(request, key)?;
+    if actor.starts_with("did:") {
+        return Did::from_str(&actor);
+    }
+    match srv.atp_db.resolve_handle(&actor)? {
+        Some(did) => Ok(did),
+        None => Err(XrpcError::NotFound(format!("unknown handle: {actor}")))?,
+    }
+}
+
+/// Parses the query parameters for post search: 'q' (required), and optional 'author' (DID or
+/// local handle), 'since', and 'until'
+fn post_search_params(srv: &mut AtpService, request: &Request) -> Result<PostSearch> {
+    let author = match request.get_param("author") {
+        Some(v) if !v.is_empty() => Some(xrpc_actor_param(srv, request, "author")?),
+        _ => None,
+    };
+    Ok(PostSearch {
+        text: xrpc_required_param(request, "q")?,
+        author,
+        since: request.get_param("since").filter(|v| !v.is_empty()),
+        until: request.get_param("until").filter(|v| !v.is_empty()),
+    })
+}
+
+/// Parses an optional non-negative integer query parameter
+fn xrpc_u64_param(request: &Request, key: &str) -> Result<Option<u64>> {
+    match request.get_param(key) {
+        None => Ok(None),
+        Some(val) => match val.parse::<u64>() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => Err(XrpcError::BadRequest(format!(
+                "invalid '{key}' query parameter: {val}"
+            )))?,
+        },
+    }
+}
+
+/// Parses an optional 'limit' query parameter, with a default and maximum value
+fn xrpc_limit_param(request: &Request, default: u64, max: u64) -> Result<u64> {
+    match request.get_param("limit") {
+        None => Ok(default),
+        Some(val) => match val.parse::<u64>() {
+            Ok(limit) if limit >= 1 => Ok(std::cmp::min(limit, max)),
+            _ => Err(XrpcError::BadRequest(format!(
+                "invalid 'limit' query parameter: {val}"
+            )))?,
+        },
+    }
+}
+
+/// Encodes a record key as an opaque pagination cursor, for `com.atproto.repo.listRecords`
+fn encode_rkey_cursor(rkey: &str) -> String {
+    data_encoding::BASE64URL_NOPAD.encode(rkey.as_bytes())
+}
+
+/// Parses an optional 'cursor' query parameter (from `encode_rkey_cursor()`) into a record key
+fn xrpc_rkey_cursor_param(request: &Request) -> Result<Option<String>> {
+    let cursor = match request.get_param("cursor") {
+        None => return Ok(None),
+        Some(cursor) => cursor,
+    };
+    match data_encoding::BASE64URL_NOPAD
+        .decode(cursor.as_bytes())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+    {
+        Some(rkey) if !rkey.is_empty() => Ok(Some(rkey)),
+        _ => Err(XrpcError::BadRequest(format!("invalid cursor: {cursor}")))?,
+    }
+}
+
+/// Checks for HTTP basic auth ("admin" user) matching the configured admin password. Admin
+/// endpoints are disabled entirely if no password is configured.
+fn xrpc_check_admin_auth(srv: &AtpService, request: &Request) -> Result<()> {
+    let admin_password = match srv.config.admin_password {
+        Some(ref password) => password,
+        None => Err(XrpcError::Forbidden(
+            "admin endpoints are not enabled on this PDS".to_string(),
+        ))?,
+    };
+    // constant-time comparison, so response timing doesn't leak how much of the password matched
+    match rouille::input::basic_http_auth(request) {
+        Some(creds)
+            if creds.login == "admin"
+                && bool::from(creds.password.as_bytes().ct_eq(admin_password.as_bytes())) =>
+        {
+            Ok(())
+        }
+        _ => Err(XrpcError::Forbidden("require admin auth".to_string()))?,
+    }
+}
+
 /// Returns DID of validated user
 fn xrpc_check_auth_header(
     srv: &mut AtpService,
@@ -285,6 +514,15 @@ fn xrpc_check_auth_header(
     Ok(did)
 }
 
+/// Like `xrpc_check_auth_header()`, but for endpoints where auth is optional (eg, to include
+/// viewer state). An invalid token is still an error.
+fn xrpc_optional_auth(srv: &mut AtpService, request: &Request) -> Result<Option<Did>> {
+    match request.header("Authorization") {
+        None => Ok(None),
+        Some(_) => Ok(Some(xrpc_check_auth_header(srv, request, None)?)),
+    }
+}
+
 fn xrpc_get_handler(
     srv: &Mutex<AtpService>,
     method: &str,
@@ -299,7 +537,7 @@ fn xrpc_get_handler(
             }
             // TODO: optional "links" object with "privacyPolicy" and "termsOfService" URLs
             Ok(
-                json!({"availableUserDomains": avail_domains, "inviteCodeRequired": srv.config.invite_code.is_some()}),
+                json!({"availableUserDomains": avail_domains, "inviteCodeRequired": srv.config.invite_code_required, "publicUrl": srv.config.public_url, "signingKey": srv.pds_keypair.pubkey().to_did_key()}),
             )
         }
         "com.atproto.repo.getRecord" => {
@@ -307,48 +545,90 @@ fn xrpc_get_handler(
             let collection = Nsid::from_str(&xrpc_required_param(request, "collection")?)?;
             let rkey = Tid::from_str(&xrpc_required_param(request, "rkey")?)?;
             let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
-            let key = format!("{collection}/{rkey}");
-            match srv.repo.get_atp_record(&did, &collection, &rkey) {
-                // TODO: format as JSON, not text debug
-                Ok(Some(ipld)) => Ok(ipld_into_json_value(ipld)),
-                Ok(None) => Err(anyhow!(XrpcError::NotFound(format!(
-                    "could not find record: {key}"
-                )))),
-                Err(e) => Err(e),
+            if !srv.atp_db.account_is_active(&did)? {
+                Err(XrpcError::NotFound(format!(
+                    "repository not available: {did}"
+                )))?;
             }
+            let key = format!("{collection}/{rkey}");
+            let cid = match srv.repo.get_atp_record_cid(&did, &collection, &rkey)? {
+                Some(cid) => cid,
+                None => Err(XrpcError::NotFound(format!("could not find record: {key}")))?,
+            };
+            Ok(json!(com_atproto::repo::RecordView {
+                uri: format!("at://{did}/{key}"),
+                cid: cid.to_string(),
+                value: ipld_into_json_value(srv.repo.get_ipld(&cid)?),
+            }))
         }
         "com.atproto.sync.getRoot" => {
             let did = Did::from_str(&xrpc_required_param(request, "did")?)?;
             let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            if !srv.atp_db.account_is_active(&did)? {
+                Err(XrpcError::NotFound(format!(
+                    "repository not available: {did}"
+                )))?;
+            }
             srv.repo
                 .lookup_commit(&did)?
                 .map(|v| json!({ "root": v.to_string() }))
                 .ok_or(XrpcError::NotFound(format!("no repository found for DID: {did}")).into())
         }
         "com.atproto.repo.listRecords" => {
-            // TODO: limit, before, after, tid, reverse
             // TODO: handle non-DID 'user'
-            // TODO: limit result set size
             let did = Did::from_str(&xrpc_required_param(request, "user")?)?;
             let collection = Nsid::from_str(&xrpc_required_param(request, "collection")?)?;
-            let mut record_list: Vec<Value> = vec![];
-            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
-            let commit_cid = &srv.repo.lookup_commit(&did)?.unwrap();
-            let last_commit = srv.repo.get_commit(commit_cid)?;
-            let full_map = srv.repo.mst_to_map(&last_commit.mst_cid)?;
-            let prefix = format!("{collection}/");
-            for (mst_key, cid) in full_map.iter() {
-                //debug!("{}", mst_key);
-                if mst_key.starts_with(&prefix) {
-                    let record = srv.repo.get_ipld(cid)?;
-                    record_list.push(json!({
-                        "uri": format!("at://{did}{mst_key}"),
-                        "cid": cid.to_string(),
-                        "value": ipld_into_json_value(record),
-                    }));
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let mut before = request.get_param("before");
+            let mut after = request.get_param("after");
+            let reverse = match request.get_param("reverse").as_deref() {
+                None | Some("false") => false,
+                Some("true") => true,
+                Some(val) => Err(XrpcError::BadRequest(format!(
+                    "invalid 'reverse' query parameter: {val}"
+                )))?,
+            };
+            // the cursor is the last record key of the previous page, in the direction of listing
+            if let Some(cursor) = xrpc_rkey_cursor_param(request)? {
+                if reverse {
+                    after = Some(cursor);
+                } else {
+                    before = Some(cursor);
                 }
             }
-            Ok(json!({ "records": record_list }))
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            if !srv.atp_db.account_is_active(&did)? {
+                Err(XrpcError::NotFound(format!(
+                    "repository not available: {did}"
+                )))?;
+            }
+            let records = srv.atp_db.list_records(
+                &did,
+                &collection,
+                limit,
+                before.as_deref(),
+                after.as_deref(),
+                reverse,
+            )?;
+            let cursor = match records.last() {
+                Some(r) if records.len() as u64 >= limit => Some(encode_rkey_cursor(&r.rkey)),
+                _ => None,
+            };
+            let record_list: Vec<Value> = records
+                .into_iter()
+                .map(|r| {
+                    json!({
+                        "uri": format!("at://{}/{}/{}", did, r.collection, r.rkey),
+                        "cid": r.cid.to_string(),
+                        "value": r.value,
+                    })
+                })
+                .collect();
+            let mut resp = json!({ "records": record_list });
+            if let Some(cursor) = cursor {
+                resp["cursor"] = json!(cursor);
+            }
+            Ok(resp)
         }
         "com.atproto.session.get" => {
             let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
@@ -373,6 +653,11 @@ fn xrpc_get_handler(
             let did = Did::from_str(&xrpc_required_param(request, "user")?)?;
 
             let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            if !srv.atp_db.account_is_active(&did)? {
+                Err(XrpcError::NotFound(format!(
+                    "repository not available: {did}"
+                )))?;
+            }
             let did_doc = srv.atp_db.get_did_doc(&did)?;
             let collections: Vec<String> = srv.repo.collections(&did)?;
             let desc = com_atproto::repo::Describe {
@@ -384,6 +669,67 @@ fn xrpc_get_handler(
             };
             Ok(json!(desc))
         }
+        "com.atproto.account.getInviteCodes" => {
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
+            let codes = srv.atp_db.list_invite_codes(&auth_did.to_string())?;
+            Ok(json!({ "codes": codes }))
+        }
+        // =========== com.atproto.admin methods (HTTP basic auth)
+        "com.atproto.admin.listAccounts" => {
+            let limit = xrpc_limit_param(request, 50, 500)?;
+            let cursor = request.get_param("cursor");
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            let accounts = srv
+                .atp_db
+                .admin_list_accounts(None, limit, cursor.as_deref())?;
+            let cursor = accounts.last().map(|a| a.handle.clone());
+            Ok(json!({ "accounts": accounts, "cursor": cursor }))
+        }
+        "com.atproto.admin.searchAccounts" => {
+            let term = xrpc_required_param(request, "term")?;
+            let limit = xrpc_limit_param(request, 50, 500)?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            let accounts = srv.atp_db.admin_list_accounts(Some(&term), limit, None)?;
+            Ok(json!({ "accounts": accounts }))
+        }
+        "com.atproto.admin.listWebhooks" => {
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            let webhooks = srv.atp_db.list_webhooks()?;
+            Ok(json!({ "webhooks": webhooks }))
+        }
+        "com.atproto.admin.getRepo" => {
+            let did = Did::from_str(&xrpc_required_param(request, "did")?)?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            let account = srv
+                .atp_db
+                .admin_get_account(&did)?
+                .ok_or(XrpcError::NotFound(format!(
+                    "no local account for DID: {did}"
+                )))?;
+            let (head, collections, record_count) = match srv.repo.lookup_commit(&did)? {
+                Some(commit_cid) => {
+                    let commit = srv.repo.get_commit(&commit_cid)?;
+                    let record_count = srv.repo.mst_to_map(&commit.mst_cid)?.len() as u64;
+                    (
+                        Some(commit_cid.to_string()),
+                        srv.repo.collections(&did)?,
+                        record_count,
+                    )
+                }
+                None => (None, vec![], 0),
+            };
+            Ok(json!(com_atproto::admin::RepoView {
+                account,
+                head,
+                collections,
+                recordCount: record_count,
+            }))
+        }
         // =========== app.bsky methods
         "app.bsky.actor.getProfile" => {
             // TODO did or handle
@@ -393,34 +739,169 @@ fn xrpc_get_handler(
             Ok(json!(bsky_get_profile(&mut srv, &did)?))
         }
         "app.bsky.actor.search" => {
-            // TODO: actual implementation
-            let _term = xrpc_required_param(request, "term")?;
-            Ok(json!({"users": []}))
+            let term = xrpc_required_param(request, "term")?;
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            // the lexicon calls the cursor parameter 'before'
+            let cursor = request
+                .get_param("before")
+                .or_else(|| request.get_param("cursor"));
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            Ok(json!(bsky_search_actors(
+                &mut srv,
+                &term,
+                limit,
+                cursor.as_deref()
+            )?))
         }
         "app.bsky.actor.searchTypeahead" => {
-            // TODO: actual implementation
-            let _term = xrpc_required_param(request, "term")?;
-            Ok(json!({"users": []}))
+            let term = xrpc_required_param(request, "term")?;
+            let limit = xrpc_limit_param(request, 10, 100)?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            Ok(json!(bsky_search_typeahead(&mut srv, &term, limit)?))
         }
         "app.bsky.actor.getSuggestions" => {
-            // TODO: actual implementation
-            Ok(json!({"actors": []}))
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let viewer = xrpc_optional_auth(&mut srv, request)?;
+            Ok(json!(bsky_get_suggestions(
+                &mut srv,
+                viewer.as_ref(),
+                limit,
+                cursor.as_deref()
+            )?))
         }
         "app.bsky.feed.getAuthorFeed" => {
             // TODO did or handle
             let did = Did::from_str(&xrpc_required_param(request, "author")?)?;
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
             let mut srv = srv.lock().unwrap();
-            Ok(json!(bsky_get_author_feed(&mut srv, &did)?))
+            let viewer = xrpc_optional_auth(&mut srv, request)?;
+            Ok(json!(bsky_get_author_feed(
+                &mut srv,
+                &did,
+                viewer.as_ref(),
+                limit,
+                cursor.as_deref()
+            )?))
         }
         "app.bsky.feed.getTimeline" => {
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
             let mut srv = srv.lock().unwrap();
             let auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
-            Ok(json!(bsky_get_timeline(&mut srv, auth_did)?))
+            Ok(json!(bsky_get_timeline(
+                &mut srv,
+                auth_did,
+                limit,
+                cursor.as_deref()
+            )?))
         }
         "app.bsky.feed.getPostThread" => {
             let uri = AtUri::from_str(&xrpc_required_param(request, "uri")?)?;
+            let depth = xrpc_u64_param(request, "depth")?;
+            let parent_height = xrpc_u64_param(request, "parentHeight")?;
+            // non-standard: pagination of direct replies
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
             let mut srv = srv.lock().unwrap();
-            Ok(json!(bsky_get_thread(&mut srv, &uri, None)?))
+            let viewer = xrpc_optional_auth(&mut srv, request)?;
+            Ok(json!(bsky_get_thread(
+                &mut srv,
+                &uri,
+                viewer.as_ref(),
+                depth,
+                parent_height,
+                limit,
+                cursor.as_deref()
+            )?))
+        }
+        "app.bsky.feed.getLikedBy" => {
+            let uri = xrpc_required_param(request, "uri")?;
+            let cid = request.get_param("cid");
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            Ok(json!(bsky_get_liked_by(
+                &mut srv,
+                &uri,
+                cid,
+                limit,
+                cursor.as_deref()
+            )?))
+        }
+        "app.bsky.feed.getRepostedBy" => {
+            let uri = xrpc_required_param(request, "uri")?;
+            let cid = request.get_param("cid");
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            Ok(json!(bsky_get_reposted_by(
+                &mut srv,
+                &uri,
+                cid,
+                limit,
+                cursor.as_deref()
+            )?))
+        }
+        "app.bsky.feed.searchPosts" => {
+            let limit = xrpc_limit_param(request, 25, 100)?;
+            let cursor = request.get_param("cursor");
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let search = post_search_params(&mut srv, request)?;
+            let viewer = xrpc_optional_auth(&mut srv, request)?;
+            Ok(json!(bsky_search_posts(
+                &mut srv,
+                &search,
+                viewer.as_ref(),
+                limit,
+                cursor.as_deref()
+            )?))
+        }
+        "app.bsky.feed.getVotes" => {
+            // older name for getLikedBy; there are no down-votes, only likes
+            let uri = xrpc_required_param(request, "uri")?;
+            let cid = request.get_param("cid");
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
+            if request.get_param("direction").as_deref() == Some("down") {
+                return Ok(json!({"uri": uri, "cid": cid, "votes": []}));
+            }
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let liked_by = bsky_get_liked_by(&mut srv, &uri, cid, limit, cursor.as_deref())?;
+            let votes: Vec<Value> = liked_by
+                .likedBy
+                .into_iter()
+                .map(|v| json!({"direction": "up", "actor": v.actor, "createdAt": v.createdAt, "indexedAt": v.indexedAt}))
+                .collect();
+            Ok(
+                json!({"uri": liked_by.uri, "cid": liked_by.cid, "cursor": liked_by.cursor, "votes": votes}),
+            )
+        }
+        "app.bsky.graph.getFollowers" => {
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let did = xrpc_actor_param(&mut srv, request, "user")?;
+            Ok(json!(bsky_get_followers(
+                &mut srv,
+                &did,
+                limit,
+                cursor.as_deref()
+            )?))
+        }
+        "app.bsky.graph.getFollows" => {
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let did = xrpc_actor_param(&mut srv, request, "user")?;
+            Ok(json!(bsky_get_follows(
+                &mut srv,
+                &did,
+                limit,
+                cursor.as_deref()
+            )?))
         }
         "app.bsky.graph.getMemberships" => {
             // TODO: actual implementation
@@ -429,16 +910,21 @@ fn xrpc_get_handler(
             Ok(json!({"memberships": []}))
         }
         "app.bsky.notification.getCount" => {
-            // TODO: actual implementation
             let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
-            let _auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
-            Ok(json!({"count": 0}))
+            let auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
+            Ok(json!({ "count": bsky_notification_count(&mut srv, auth_did)? }))
         }
         "app.bsky.notification.list" => {
-            // TODO: actual implementation
+            let limit = xrpc_limit_param(request, 50, 100)?;
+            let cursor = request.get_param("cursor");
             let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
-            let _auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
-            Ok(json!({"notifications": []}))
+            let auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
+            Ok(json!(bsky_list_notifications(
+                &mut srv,
+                auth_did,
+                limit,
+                cursor.as_deref()
+            )?))
         }
         _ => Err(anyhow!(XrpcError::NotFound(format!(
             "XRPC endpoint handler not found: {method}"
@@ -449,9 +935,42 @@ fn xrpc_get_handler(
 fn xrpc_get_repo_handler(srv: &Mutex<AtpService>, request: &Request) -> Result<Vec<u8>> {
     let did = Did::from_str(&xrpc_required_param(request, "did")?)?;
     let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+    if !srv.atp_db.account_is_active(&did)? {
+        Err(XrpcError::NotFound(format!(
+            "repository not available: {did}"
+        )))?;
+    }
+    let from = match request.get_param("from") {
+        Some(v) => Some(
+            Cid::from_str(&v)
+                .map_err(|_| XrpcError::BadRequest(format!("invalid 'from' commit CID: {v}")))?,
+        ),
+        None => None,
+    };
     // TODO: don't unwrap here
     let commit_cid = srv.repo.lookup_commit(&did)?.unwrap();
-    srv.repo.export_car(&commit_cid, None)
+    srv.repo.export_car(&commit_cid, from.as_ref())
+}
+
+fn xrpc_get_blob_handler(srv: &Mutex<AtpService>, request: &Request) -> Result<(String, Vec<u8>)> {
+    let did = Did::from_str(&xrpc_required_param(request, "did")?)?;
+    let cid_param = xrpc_required_param(request, "cid")?;
+    let cid = Cid::from_str(&cid_param)
+        .map_err(|_| XrpcError::BadRequest(format!("invalid blob CID: {cid_param}")))?;
+    let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+    if !srv.atp_db.account_is_active(&did)? {
+        Err(XrpcError::NotFound(format!(
+            "repository not available: {did}"
+        )))?;
+    }
+    let mime_type = match srv.atp_db.get_blob_mime_type(&did, &cid)? {
+        Some(v) => v,
+        None => Err(XrpcError::NotFound(format!("blob not found: {cid}")))?,
+    };
+    match srv.repo.get_blob(&cid)? {
+        Some(blob) => Ok((mime_type, blob)),
+        None => Err(XrpcError::NotFound(format!("blob not found: {cid}")))?,
+    }
 }
 
 pub fn create_account(
@@ -493,23 +1012,111 @@ pub fn create_account(
         };
         (did, meta.did_doc())
     };
+    register_account(srv, req, &did, &did_doc)
+}
 
-    // register in ATP DB and generate DID doc
+/// Compares two service URLs, ignoring case in the scheme and host, default ports, and trailing
+/// slashes. Unparseable URLs never match.
+fn service_url_eq(a: &str, b: &str) -> bool {
+    let normalize = |url: &str| {
+        reqwest::Url::parse(url)
+            .ok()
+            .map(|u| u.as_str().trim_end_matches('/').to_string())
+    };
+    match (normalize(a), normalize(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Creates a new account bound to an externally controlled did:web, without generating a new
+/// identity.
+///
+/// The DID document should already have been fetched (eg, with `resolve::resolve_did_web()`). It
+/// must list this PDS as the `AtpPersonalDataServer` service, and include the PDS signing key, so
+/// that repository commits made by this PDS can be verified against the DID.
+pub fn create_account_external_did(
+    srv: &mut AtpService,
+    req: &com_atproto::AccountRequest,
+    did: &Did,
+    did_doc: &Value,
+) -> Result<com_atproto::Session> {
+    if srv.atp_db.account_exists(&req.handle, &req.email)? {
+        Err(XrpcError::BadRequest(
+            "handle or email already exists".to_string(),
+        ))?;
+    };
+    if srv.atp_db.resolve_did(did)?.is_some() {
+        Err(XrpcError::BadRequest(format!(
+            "DID already registered on this PDS: {did}"
+        )))?;
+    }
+    if did_doc["id"] != did.to_string() {
+        Err(XrpcError::BadRequest(
+            "DID document is for a different DID".to_string(),
+        ))?;
+    }
+    if !plc::did_doc_pds_url(did_doc)
+        .map(|url| service_url_eq(&url, &srv.config.public_url))
+        .unwrap_or(false)
+    {
+        Err(XrpcError::BadRequest(format!(
+            "DID document does not list this PDS ({}) as AtpPersonalDataServer",
+            srv.config.public_url
+        )))?;
+    }
+    let pds_key = srv.pds_keypair.pubkey().to_did_key();
+    let has_pds_key = did_doc["verificationMethod"]
+        .as_array()
+        .map(|methods| methods.iter().any(|m| m["publicKeyMultibase"] == pds_key))
+        .unwrap_or(false);
+    if !has_pds_key {
+        Err(XrpcError::BadRequest(format!(
+            "DID document does not include the PDS signing key ({pds_key})"
+        )))?;
+    }
+    debug!("registering account for external DID: {}", did);
+    register_account(srv, req, did, did_doc)
+}
+
+/// Common account registration, once a DID and DID document are determined: creates the account
+/// row (consuming any invite code), stores the DID document, inserts an empty repository, and
+/// returns a new session.
+fn register_account(
+    srv: &mut AtpService,
+    req: &com_atproto::AccountRequest,
+    did: &Did,
+    did_doc: &Value,
+) -> Result<com_atproto::Session> {
     let recovery_key = req
         .recoveryKey
         .clone()
         .unwrap_or(srv.pds_keypair.pubkey().to_did_key());
-    srv.atp_db
-        .create_account(&did, &req.handle, &req.password, &req.email, &recovery_key)?;
-    srv.atp_db.put_did_doc(&did, &did_doc)?;
+    let invite_code = if srv.config.invite_code_required {
+        Some(req.inviteCode.as_deref().ok_or(XrpcError::Forbidden(
+            "an invite code is required".to_string(),
+        ))?)
+    } else {
+        None
+    };
+    srv.atp_db.create_account(
+        did,
+        &req.handle,
+        &req.password,
+        &req.email,
+        &recovery_key,
+        invite_code,
+    )?;
+    srv.atp_db.put_did_doc(did, &req.handle, did_doc)?;
 
     // insert empty MST repository
     let root_cid = {
         let empty_map_cid = srv.repo.mst_from_map(&Default::default())?;
-        let meta_cid = srv.repo.write_metadata(&did)?;
+        let meta_cid = srv.repo.write_metadata(did)?;
         srv.repo.write_root(meta_cid, None, empty_map_cid)?
     };
-    let _commit_cid = srv.repo.write_commit(&did, root_cid, "XXX-dummy-sig")?;
+    let sig = srv.pds_keypair.sign_bytes(root_cid.to_string().as_bytes());
+    let _commit_cid = srv.repo.write_commit(did, root_cid, &sig)?;
 
     let keypair = srv.pds_keypair.clone();
     let sess = srv
@@ -518,6 +1125,359 @@ pub fn create_account(
     Ok(sess)
 }
 
+/// Sets a new password for a local account, by handle, directly against the database (eg, for the
+/// `reset-password` CLI command). All existing sessions for the account are removed.
+pub fn reset_password(atp_db_path: &PathBuf, handle: &str, password: &str) -> Result<()> {
+    let mut atp_db = AtpDatabase::open(atp_db_path)?;
+    let did = atp_db
+        .resolve_handle(handle)?
+        .ok_or(anyhow!("no local account for handle: {}", handle))?;
+    atp_db.set_password(&did, password)
+}
+
+/// Creates an admin invite code directly against the database (eg, for the `create-invite-code`
+/// CLI command). The optional expiry must be an RFC 3339 timestamp.
+pub fn create_invite_code(
+    atp_db_path: &PathBuf,
+    max_uses: u64,
+    expires_at: Option<&str>,
+) -> Result<String> {
+    if max_uses == 0 {
+        return Err(anyhow!("invite code uses must be at least 1"));
+    }
+    let expires_at = match expires_at {
+        Some(ts) => Some(
+            normalize_timestamp(ts)
+                .map_err(|e| anyhow!("expiry must be an RFC 3339 timestamp: {}", e))?,
+        ),
+        None => None,
+    };
+    let mut atp_db = AtpDatabase::open(atp_db_path)?;
+    atp_db.create_invite_code("admin", max_uses, expires_at.as_deref())
+}
+
+/// Permanently deletes a local account, after checking the password and confirmation token.
+///
+/// For did:plc accounts with a `plc_prev` (the most recent operation in the PLC directory, see
+/// `plc_last_op_cid()`), a tombstone operation is signed with the PDS key, recorded, and returned,
+/// to be submitted to the PLC directory. The repository alias is removed, which leaves the repo
+/// blocks to blockstore garbage collection.
+pub fn delete_account(
+    srv: &mut AtpService,
+    did: &Did,
+    password: &str,
+    token: &str,
+    plc_prev: Option<Cid>,
+) -> Result<Option<Value>> {
+    let plc_op = plc_prev.map(|prev| plc::TombstoneOp::new(Some(prev), &srv.pds_keypair).to_json());
+    srv.atp_db
+        .delete_account(did, password, token, plc_op.as_ref())?;
+    srv.repo.unlink_repo(did)?;
+    info!("deleted account: {}", did);
+    Ok(plc_op)
+}
+
+/// CID of the most recent operation for a did:plc identity, from the PLC directory. `None` if the
+/// directory doesn't know about the DID (eg, local testing identities).
+fn plc_last_op_cid(plc_host: &str, did: &Did) -> Result<Option<Cid>> {
+    match resolve::fetch_plc_log(plc_host, did)? {
+        Some(log) => match log.last() {
+            Some(op) => Ok(Some(plc::op_cid(op)?)),
+            None => Err(anyhow!("empty PLC operation log for: {}", did)),
+        },
+        None => {
+            warn!("DID not registered with PLC directory: {}", did);
+            Ok(None)
+        }
+    }
+}
+
+/// Checks and imports the repository for an existing DID which is migrating from another PDS, as
+/// the first step of `import_migrated_account()`.
+///
+/// The current DID document should already have been resolved from the PLC directory, and the
+/// repository (CAR bytes) fetched from the old PDS, which must be the PDS listed in the DID
+/// document. The DID update operation must be signed by the current signing key in the DID
+/// document, and must point at this PDS (and its signing key). The imported repository is verified
+/// (including the head commit signature), but not yet linked to the DID. Returns the head commit
+/// CID of the imported repository.
+///
+/// If this succeeds, the caller should submit the DID operation to the PLC directory (without
+/// holding the service lock), and then call `import_migrated_account()`.
+pub fn check_migrated_account(
+    srv: &mut AtpService,
+    req: &com_atproto::ImportMigrationRequest,
+    did_doc: &Value,
+    car_bytes: &[u8],
+) -> Result<Cid> {
+    let did = Did::from_str(&req.did)?;
+    if did_doc["id"] != did.to_string() {
+        Err(XrpcError::BadRequest(
+            "fetched DID document is for a different DID".to_string(),
+        ))?;
+    }
+    if !plc::did_doc_pds_url(did_doc)
+        .map(|url| service_url_eq(&url, &req.fromPds))
+        .unwrap_or(false)
+    {
+        Err(XrpcError::Forbidden(format!(
+            "DID document does not list {} as the current PDS",
+            req.fromPds
+        )))?;
+    }
+    let op = plc::UpdateOp::from_json(&req.op)
+        .map_err(|e| XrpcError::BadRequest(format!("invalid DID operation: {e}")))?;
+    if op.service != srv.config.public_url || op.signingKey != srv.pds_keypair.pubkey().to_did_key()
+    {
+        Err(XrpcError::BadRequest(
+            "DID operation does not point to this PDS".to_string(),
+        ))?;
+    }
+    let signing_key = plc::did_doc_key(did_doc, "signingKey").ok_or(XrpcError::BadRequest(
+        "no signing key in DID document".to_string(),
+    ))?;
+    let signing_pubkey = PubKey::from_did_key(&signing_key)?;
+    op.verify(&signing_pubkey)
+        .map_err(|_| XrpcError::Forbidden("DID operation signature did not verify".to_string()))?;
+
+    if srv.atp_db.account_exists(&req.handle, &req.email)? {
+        Err(XrpcError::BadRequest(
+            "handle or email already exists".to_string(),
+        ))?;
+    };
+    if srv.repo.lookup_commit(&did)?.is_some() {
+        Err(XrpcError::BadRequest(format!(
+            "repository already exists on this PDS: {did}"
+        )))?;
+    }
+
+    // import (which verifies the MST structure) and check that the repo is actually for this DID,
+    // before pointing the DID alias at it
+    let commit_cid = srv
+        .repo
+        .import_car_bytes(car_bytes, None)
+        .map_err(|e| XrpcError::BadRequest(format!("failed to import repository: {e}")))?;
+    let commit = srv.repo.get_commit(&commit_cid)?;
+    if commit.did != did {
+        Err(XrpcError::BadRequest(format!(
+            "imported repository is for a different DID: {}",
+            commit.did
+        )))?;
+    }
+    srv.repo
+        .verify_commit_sig(&commit_cid, &signing_pubkey)
+        .map_err(|e| XrpcError::Forbidden(format!("imported repository: {e}")))?;
+
+    if srv.config.invite_code_required {
+        let code = req.inviteCode.as_deref().ok_or(XrpcError::Forbidden(
+            "an invite code is required".to_string(),
+        ))?;
+        // checked up front, because the PLC submission can't be undone. the code is actually
+        // consumed along with account creation
+        srv.atp_db.check_invite_code(code)?;
+    }
+    Ok(commit_cid)
+}
+
+/// Creates a local account for an existing DID which is migrating from another PDS, after
+/// `check_migrated_account()` has imported the repository and the DID operation has been accepted
+/// by the PLC directory. The bsky index tables are populated from the repository.
+pub fn import_migrated_account(
+    srv: &mut AtpService,
+    req: &com_atproto::ImportMigrationRequest,
+    did_doc: &Value,
+    commit_cid: &Cid,
+) -> Result<com_atproto::Session> {
+    let did = Did::from_str(&req.did)?;
+    let op = plc::UpdateOp::from_json(&req.op)?;
+    let recovery_key = plc::did_doc_key(did_doc, "recoveryKey")
+        .or_else(|| plc::did_doc_key(did_doc, "signingKey"))
+        .ok_or(XrpcError::BadRequest(
+            "no signing key in DID document".to_string(),
+        ))?;
+    let invite_code = if srv.config.invite_code_required {
+        req.inviteCode.as_deref()
+    } else {
+        None
+    };
+
+    srv.atp_db.create_account(
+        &did,
+        &req.handle,
+        &req.password,
+        &req.email,
+        &recovery_key,
+        invite_code,
+    )?;
+    let mut new_did_doc = op.apply(did_doc);
+    new_did_doc["alsoKnownAs"] = json!([format!("https://{}", req.handle)]);
+    srv.atp_db.put_did_doc(&did, &req.handle, &new_did_doc)?;
+    srv.repo.link_repo(&did, commit_cid)?;
+    let count = bsky_reindex(srv, &did)?;
+    info!("imported migrated account ({} records): {}", count, did);
+
+    let keypair = srv.pds_keypair.clone();
+    let sess = srv
+        .atp_db
+        .create_session(&req.handle, &req.password, &keypair)?;
+    Ok(sess)
+}
+
+/// Whether a handle is a sub-domain of a (registration) domain. The domain itself, or other
+/// domains which just happen to end with the same characters, don't count.
+fn handle_under_domain(handle: &str, domain: &str) -> bool {
+    handle.ends_with(&format!(".{domain}"))
+}
+
+/// Checks whether an existing account can switch to a new handle. Returns `false` if the account
+/// already has the handle, in which case there is nothing to do.
+fn check_update_handle(srv: &mut AtpService, did: &Did, handle: &str) -> Result<bool> {
+    if let Some(existing) = srv.atp_db.resolve_handle(handle)? {
+        if &existing == did {
+            return Ok(false);
+        }
+        Err(XrpcError::BadRequest("handle already exists".to_string()))?;
+    }
+    // did:web identifiers are tied to a domain, so the handle can't move independently
+    if did.did_type() == "web" {
+        Err(XrpcError::BadRequest(
+            "can not update handle for did:web accounts".to_string(),
+        ))?;
+    }
+    Ok(true)
+}
+
+/// Switches an existing account to a new handle.
+///
+/// Any verification that the account controls the new handle (eg, for domains outside the
+/// registration domain), and publishing the handle to the PLC directory for did:plc accounts, needs
+/// to happen before calling this.
+pub fn update_handle(srv: &mut AtpService, did: &Did, handle: &str) -> Result<()> {
+    if !check_update_handle(srv, did, handle)? {
+        return Ok(());
+    }
+    debug!("updating handle for {}: {}", did, handle);
+    srv.atp_db.update_handle(did, handle)
+}
+
+/// Writes a batch of mutations to a local repository as a single new signed commit, and then
+/// updates the bsky index and appends the commit to the event sequence (for `subscribeRepos`).
+///
+/// This is the path all local record writes should go through. Returns the new commit CID.
+pub fn commit_mutations(srv: &mut AtpService, did: &Did, mutations: Vec<Mutation>) -> Result<Cid> {
+    let prev_cid = match srv.repo.lookup_commit(did)? {
+        Some(cid) => cid,
+        None => Err(XrpcError::NotFound(format!("repository not found: {did}")))?,
+    };
+    let keypair = srv.pds_keypair.clone();
+    let commit_cid = srv.repo.mutate_repo(did, &mutations, &keypair)?;
+    index_commit(srv, did, &prev_cid, &commit_cid, mutations)?;
+    Ok(commit_cid)
+}
+
+/// Handles everything that follows a new commit to a local repository (which is already linked
+/// to the DID): appends the commit to the event sequence, queues webhook payloads, and applies the
+/// mutations to the bsky index.
+fn index_commit(
+    srv: &mut AtpService,
+    did: &Did,
+    prev_cid: &Cid,
+    commit_cid: &Cid,
+    mutations: Vec<Mutation>,
+) -> Result<()> {
+    // the event is computed from the applied mutations and the changed parts of the MST, instead
+    // of reading in (or walking every block of) the full repository
+    let ops: Vec<Value> = mutations
+        .iter()
+        .map(|m| {
+            let cid = m.record_cid()?.map(|c| c.to_string());
+            Ok(json!({"action": m.action(), "path": m.mst_key(), "cid": cid}))
+        })
+        .collect::<Result<Vec<Value>>>()?;
+    let blocks = srv.repo.export_commit_diff_car(commit_cid, prev_cid)?;
+    let seq = srv
+        .atp_db
+        .sequence_commit(did, commit_cid, Some(prev_cid), &json!(ops), &blocks)?;
+
+    // one webhook payload per record operation; delivered by a background worker
+    let payloads: Vec<Value> = ops
+        .iter()
+        .map(|op| {
+            let path = op["path"].as_str().unwrap_or("");
+            let (collection, rkey) = path.split_once('/').unwrap_or((path, ""));
+            json!({
+                "did": did.to_string(),
+                "collection": collection,
+                "rkey": rkey,
+                "operation": op["action"],
+                "cid": op["cid"],
+                "commit": commit_cid.to_string(),
+                "seq": seq,
+            })
+        })
+        .collect();
+    srv.atp_db.enqueue_webhook_payloads(&payloads)?;
+
+    bsky_mutate_db(&srv.atp_db, did, mutations)?;
+    Ok(())
+}
+
+/// Accepts a pushed repository (CAR file bytes) for a local account.
+///
+/// The new commit must be for the same DID, signed by the signing key in the account's DID
+/// document, and descend from the current repo head (only "fast-forward" updates are allowed).
+/// Record-level changes are indexed the same way as for local writes. Returns the new head.
+pub fn update_repo(srv: &mut AtpService, did: &Did, car_bytes: &[u8]) -> Result<Cid> {
+    let prev_cid = match srv.repo.lookup_commit(did)? {
+        Some(cid) => cid,
+        None => Err(XrpcError::NotFound(format!("repository not found: {did}")))?,
+    };
+    let commit_cid = srv
+        .repo
+        .import_car_bytes(car_bytes, None)
+        .map_err(|e| XrpcError::BadRequest(format!("failed to import repository: {e}")))?;
+    if commit_cid == prev_cid {
+        return Ok(commit_cid);
+    }
+    let commit = srv
+        .repo
+        .get_commit(&commit_cid)
+        .map_err(|e| XrpcError::BadRequest(format!("CAR root is not a repo commit: {e}")))?;
+    if &commit.did != did {
+        Err(XrpcError::BadRequest(format!(
+            "pushed repository is for a different DID: {}",
+            commit.did
+        )))?;
+    }
+    let signing_key = plc::did_doc_key(&srv.atp_db.get_did_doc(did)?, "signingKey")
+        .ok_or(anyhow!("no signing key in DID document: {}", did))?;
+    srv.repo
+        .verify_commit_sig(&commit_cid, &PubKey::from_did_key(&signing_key)?)
+        .map_err(|e| XrpcError::Forbidden(format!("{e}")))?;
+    // a missing commit in between means the push didn't include the full history since our head
+    let fast_forward = srv
+        .repo
+        .commit_descends_from(&commit_cid, &prev_cid)
+        .unwrap_or(false);
+    if !fast_forward {
+        Err(XrpcError::BadRequest(format!(
+            "rejecting non-fast-forward update: commit {commit_cid} does not descend from current head {prev_cid}"
+        )))?;
+    }
+
+    let prev_mst_cid = srv.repo.get_commit(&prev_cid)?.mst_cid;
+    let mutations = srv.repo.mst_diff(Some(&prev_mst_cid), &commit.mst_cid)?;
+    srv.repo.link_repo(did, &commit_cid)?;
+    info!(
+        "accepted repo push for {} ({} record changes): {}",
+        did,
+        mutations.len(),
+        commit_cid
+    );
+    index_commit(srv, did, &prev_cid, &commit_cid, mutations)?;
+    Ok(commit_cid)
+}
+
 fn xrpc_post_handler(
     srv: &Mutex<AtpService>,
     method: &str,
@@ -529,26 +1489,367 @@ fn xrpc_post_handler(
             let req: com_atproto::AccountRequest = rouille::input::json_input(request)
                 .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
             // TODO: validate handle, email, recoverykey
-            let mut srv = srv.lock().unwrap();
-            if let Some(ref domain) = srv.config.registration_domain {
-                // TODO: better matching, should not allow arbitrary sub-domains
-                if !req.handle.ends_with(domain) {
-                    Err(XrpcError::BadRequest(format!(
+            let under_domain = {
+                let srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                // invite code itself gets validated and consumed along with account creation
+                if srv.config.invite_code_required && req.inviteCode.is_none() {
+                    Err(XrpcError::Forbidden(
+                        "a valid invite code is required".to_string(),
+                    ))?;
+                };
+                match srv.config.registration_domain {
+                    // TODO: better matching, should not allow arbitrary sub-domains
+                    Some(ref domain) => handle_under_domain(&req.handle, domain),
+                    None => Err(XrpcError::BadRequest(
+                        "account registration is disabled on this PDS".to_string(),
+                    ))?,
+                }
+            };
+            // externally controlled did:web identities need the DID document fetched, and the
+            // handle verified if it isn't under the registration domain. important that this
+            // happens without holding the mutex, because it could be slow!
+            let external = match req.did {
+                None if !under_domain => Err(XrpcError::BadRequest(
+                    "handle is not under registration domain".to_string(),
+                ))?,
+                None => None,
+                Some(ref did) => {
+                    let did = Did::from_str(did)?;
+                    if !did.starts_with("did:web:") {
+                        Err(XrpcError::BadRequest(
+                            "only did:web identities can be registered with an existing DID"
+                                .to_string(),
+                        ))?;
+                    }
+                    let did_doc = resolve::resolve_did_web(&did).map_err(|e| {
+                        XrpcError::BadRequest(format!("failed to fetch DID document: {e}"))
+                    })?;
+                    if !under_domain && *did != format!("did:web:{}", req.handle) {
+                        let resolved =
+                            resolve::resolve_handle_well_known(&req.handle).map_err(|e| {
+                                XrpcError::BadRequest(format!(
+                                    "failed to verify handle {}: {e}",
+                                    req.handle
+                                ))
+                            })?;
+                        if resolved != did {
+                            Err(XrpcError::Forbidden(format!(
+                                "handle {} resolves to a different DID: {resolved}",
+                                req.handle
+                            )))?;
+                        }
+                    }
+                    Some((did, did_doc))
+                }
+            };
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let sess = match external {
+                Some((did, did_doc)) => {
+                    create_account_external_did(&mut srv, &req, &did, &did_doc)?
+                }
+                None => create_account(&mut srv, &req, true)?,
+            };
+            let did = Did::from_str(&sess.did)?;
+            let token =
+                srv.atp_db
+                    .create_account_token(&did, "email_verify", EMAIL_VERIFY_EXPIRY)?;
+            let (mailer, public_url) = (srv.mailer.clone(), srv.config.public_url.clone());
+            // important to not hold the mutex while sending mail, because it could be slow!
+            drop(srv);
+            let body = format!("Welcome to {public_url}!\n\nTo verify your email address, use this token (valid for 24 hours):\n\n    {token}\n");
+            if let Err(e) = mailer.send(&req.email, "Verify your email address", &body) {
+                warn!("failed to send verification email for {}: {}", did, e);
+            }
+            Ok(json!(sess))
+        }
+        "com.atproto.account.confirmEmail" => {
+            let req: com_atproto::TokenRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            match srv
+                .atp_db
+                .consume_account_token(&req.token, "email_verify")?
+            {
+                Some(did) => srv.atp_db.set_email_verified(&did)?,
+                None => Err(XrpcError::Forbidden(
+                    "email verification token is not valid or has expired".to_string(),
+                ))?,
+            };
+            Ok(json!({}))
+        }
+        "com.atproto.account.requestPasswordReset" => {
+            let req: com_atproto::PasswordResetRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            // always succeeds, to not reveal which email addresses have accounts
+            let did = match srv.atp_db.resolve_email(&req.email)? {
+                Some(did) => did,
+                None => return Ok(json!({})),
+            };
+            let token =
+                srv.atp_db
+                    .create_account_token(&did, "password_reset", PASSWORD_RESET_EXPIRY)?;
+            let (mailer, public_url) = (srv.mailer.clone(), srv.config.public_url.clone());
+            drop(srv);
+            let body = format!("A password reset was requested for your account at {public_url}.\n\nTo set a new password, use this token (valid for 1 hour):\n\n    {token}\n\nIf you did not request this, you can ignore this message.\n");
+            if let Err(e) = mailer.send(&req.email, "Password reset", &body) {
+                warn!("failed to send password reset email for {}: {}", did, e);
+            }
+            Ok(json!({}))
+        }
+        "com.atproto.account.resetPassword" => {
+            let req: com_atproto::ResetPasswordRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            match srv
+                .atp_db
+                .consume_account_token(&req.token, "password_reset")?
+            {
+                Some(did) => {
+                    info!("resetting password: {}", did);
+                    srv.atp_db.set_password(&did, &req.password)?;
+                }
+                None => Err(XrpcError::Forbidden(
+                    "password reset token is not valid or has expired".to_string(),
+                ))?,
+            };
+            Ok(json!({}))
+        }
+        "com.atproto.account.requestMigration" => {
+            let req: com_atproto::RequestMigrationRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            PubKey::from_did_key(&req.signingKey)
+                .map_err(|e| XrpcError::BadRequest(format!("invalid signing key: {e}")))?;
+            let (auth_did, keypair, plc_host) = {
+                let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                let auth_did = xrpc_check_auth_header(&mut srv, request, None)?;
+                (
+                    auth_did,
+                    srv.pds_keypair.clone(),
+                    srv.config.plc_host.clone(),
+                )
+            };
+            if auth_did.did_type() != "plc" {
+                Err(XrpcError::BadRequest(
+                    "only did:plc accounts can be migrated".to_string(),
+                ))?;
+            }
+            // the operation has to follow the most recent one in the PLC directory. important that
+            // this happens without holding the mutex, because it could be slow!
+            let prev = match resolve::fetch_plc_log(&plc_host, &auth_did)? {
+                Some(log) if !log.is_empty() => plc::op_cid(&log[log.len() - 1])?,
+                _ => Err(XrpcError::BadRequest(format!(
+                    "DID is not registered with the PLC directory: {auth_did}"
+                )))?,
+            };
+            // the PDS key is the current signing key for all local did:plc accounts
+            let op = plc::UpdateOp::new(req.signingKey, req.service, Some(prev), &keypair);
+            Ok(json!({ "op": op.to_json() }))
+        }
+        "com.atproto.account.importMigration" => {
+            let req: com_atproto::ImportMigrationRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let did = Did::from_str(&req.did)?;
+            if did.did_type() != "plc" {
+                Err(XrpcError::BadRequest(
+                    "only did:plc accounts can be migrated".to_string(),
+                ))?;
+            }
+            let plc_host = {
+                let srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                match srv.config.registration_domain {
+                    Some(ref domain) if handle_under_domain(&req.handle, domain) => {}
+                    Some(ref domain) => Err(XrpcError::BadRequest(format!(
                         "handle is not under registration domain ({domain})"
+                    )))?,
+                    None => Err(XrpcError::BadRequest(
+                        "account registration is disabled on this PDS".to_string(),
+                    ))?,
+                };
+                srv.config.plc_host.clone()
+            };
+            // important that these fetches happen without holding the mutex, because they could
+            // be slow! the current DID document comes from the PLC directory, not the old PDS,
+            // because the old PDS is chosen by the caller.
+            let did_doc = resolve::resolve_did_plc(&plc_host, &did)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to resolve DID: {e}")))?;
+            let car_bytes = resolve::fetch_repo_car(&req.fromPds, &did, None)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to fetch repository: {e}")))?;
+            let commit_cid = {
+                let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                check_migrated_account(&mut srv, &req, &did_doc, &car_bytes)?
+            };
+            // once this succeeds, the DID points to this PDS. also slow, so without the mutex
+            resolve::submit_plc_op(&plc_host, &did, &req.op).map_err(|e| {
+                XrpcError::BadRequest(format!("PLC directory rejected DID operation: {e}"))
+            })?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let sess = import_migrated_account(&mut srv, &req, &did_doc, &commit_cid)?;
+            Ok(json!(sess))
+        }
+        "com.atproto.account.completeMigration" => {
+            let req: com_atproto::CompleteMigrationRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let op = plc::UpdateOp::from_json(&req.op)
+                .map_err(|e| XrpcError::BadRequest(format!("invalid DID operation: {e}")))?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let auth_did = xrpc_check_auth_header(&mut srv, request, None)?;
+            op.verify(&srv.pds_keypair.pubkey()).map_err(|_| {
+                XrpcError::Forbidden("DID operation was not signed by this PDS".to_string())
+            })?;
+            if op.service == srv.config.public_url {
+                Err(XrpcError::BadRequest(
+                    "DID operation does not move account to a new PDS".to_string(),
+                ))?;
+            }
+            let handle = srv
+                .atp_db
+                .resolve_did(&auth_did)?
+                .expect("registered account has handle");
+            let did_doc = op.apply(&srv.atp_db.get_did_doc(&auth_did)?);
+            srv.atp_db.put_did_doc(&auth_did, &handle, &did_doc)?;
+            srv.atp_db.set_account_deactivated(&auth_did, true)?;
+            info!("account migrated to {}: {}", op.service, auth_did);
+            Ok(json!({}))
+        }
+        "com.atproto.handle.update" => {
+            let req: com_atproto::HandleUpdateRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let handle = match DidOrHost::from_str(&req.handle) {
+                Ok(DidOrHost::Host(handle)) => handle,
+                _ => Err(XrpcError::BadRequest(format!(
+                    "not a valid handle: {}",
+                    req.handle
+                )))?,
+            };
+            let (did, needs_verification, plc_host, keypair) = {
+                let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                let did = xrpc_check_auth_header(&mut srv, request, None)?;
+                if !check_update_handle(&mut srv, &did, &handle)? {
+                    return Ok(json!({}));
+                }
+                let under_domain = match srv.config.registration_domain {
+                    Some(ref domain) => handle_under_domain(&handle, domain),
+                    None => false,
+                };
+                (
+                    did,
+                    !under_domain,
+                    srv.config.plc_host.clone(),
+                    srv.pds_keypair.clone(),
+                )
+            };
+            // domains outside of the registration domain need to point back to the account DID.
+            // important that this happens without holding the mutex, because it could be slow!
+            if needs_verification {
+                let resolved = resolve::resolve_handle_well_known(&handle).map_err(|e| {
+                    XrpcError::BadRequest(format!("failed to verify handle {handle}: {e}"))
+                })?;
+                if resolved != did {
+                    Err(XrpcError::Forbidden(format!(
+                        "handle {handle} resolves to a different DID: {resolved}"
                     )))?;
                 }
+            }
+            // the DID document in the PLC directory needs to follow the new handle. this is done
+            // before switching locally, so a rejected operation leaves the account unchanged. the
+            // PDS key is the current signing key for all local did:plc accounts.
+            if did.did_type() == "plc" {
+                if let Some(prev) = plc_last_op_cid(&plc_host, &did)? {
+                    let op = plc::UpdateHandleOp::new(handle.to_string(), Some(prev), &keypair);
+                    resolve::submit_plc_op(&plc_host, &did, &op.to_json()).map_err(|e| {
+                        XrpcError::BadRequest(format!("PLC directory rejected handle update: {e}"))
+                    })?;
+                }
+            }
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            update_handle(&mut srv, &did, &handle)?;
+            Ok(json!({}))
+        }
+        "com.atproto.account.requestDelete" => {
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let auth_did = xrpc_check_auth_header(&mut srv, request, None)?;
+            let (email, _) =
+                srv.atp_db
+                    .get_account_email(&auth_did)?
+                    .ok_or(XrpcError::NotFound(format!(
+                        "no local account for DID: {auth_did}"
+                    )))?;
+            let token = srv.atp_db.create_delete_token(&auth_did)?;
+            let (mailer, public_url) = (srv.mailer.clone(), srv.config.public_url.clone());
+            drop(srv);
+            let body = format!("Deletion of your account at {public_url} was requested.\n\nTo permanently delete the account, use this token (valid for 15 minutes):\n\n    {token}\n\nIf you did not request this, you should change your password.\n");
+            if let Err(e) = mailer.send(&email, "Account deletion", &body) {
+                warn!(
+                    "failed to send account deletion email for {}: {}",
+                    auth_did, e
+                );
+                Err(anyhow!("failed to send account deletion email"))?;
+            }
+            Ok(json!({}))
+        }
+        "com.atproto.account.delete" => {
+            let req: com_atproto::AccountDeleteRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let did = Did::from_str(&req.did)?;
+            // check credentials before making any requests to the PLC directory
+            let plc_host = {
+                let srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                srv.atp_db
+                    .check_delete_account(&did, &req.password, &req.token)?;
+                srv.config.plc_host.clone()
+            };
+            // important that this happens without holding the mutex, because it could be slow!
+            let plc_prev = if did.did_type() == "plc" {
+                plc_last_op_cid(&plc_host, &did)?
             } else {
+                None
+            };
+            let plc_op = {
+                let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                delete_account(&mut srv, &did, &req.password, &req.token, plc_prev)?
+            };
+            if let Some(ref op) = plc_op {
+                // the account is already gone locally at this point; the signed operation is kept
+                // in the tombstone table, so it could be re-submitted
+                if let Err(e) = resolve::submit_plc_op(&plc_host, &did, op) {
+                    error!("failed to submit PLC tombstone for {}: {}", did, e);
+                }
+            }
+            Ok(json!({}))
+        }
+        "com.atproto.account.deactivate" | "com.atproto.account.activate" => {
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
+            let deactivated = method == "com.atproto.account.deactivate";
+            info!("setting account deactivated={}: {}", deactivated, auth_did);
+            srv.atp_db.set_account_deactivated(auth_did, deactivated)?;
+            Ok(json!({}))
+        }
+        "com.atproto.account.createInviteCode" => {
+            let req: com_atproto::CreateInviteCodeRequest = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            if req.useCount == 0 {
                 Err(XrpcError::BadRequest(
-                    "account registration is disabled on this PDS".to_string(),
+                    "useCount must be at least 1".to_string(),
                 ))?;
+            }
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            let creator = match req.forAccount {
+                Some(ref did) => Did::from_str(did)?.to_string(),
+                None => "admin".to_string(),
             };
-            if srv.config.invite_code.is_some() && srv.config.invite_code != req.inviteCode {
-                Err(XrpcError::Forbidden(
-                    "a valid invite code is required".to_string(),
-                ))?;
+            let expires_at = match req.expiresAt {
+                Some(ref ts) => Some(normalize_timestamp(ts).map_err(|e| {
+                    XrpcError::BadRequest(format!("expiresAt must be an RFC 3339 timestamp: {e}"))
+                })?),
+                None => None,
             };
-            let sess = create_account(&mut srv, &req, true)?;
-            Ok(json!(sess))
+            let code =
+                srv.atp_db
+                    .create_invite_code(&creator, req.useCount, expires_at.as_deref())?;
+            Ok(json!({ "code": code }))
         }
         "com.atproto.session.create" => {
             let req: com_atproto::SessionRequest = rouille::input::json_input(request)
@@ -632,9 +1933,7 @@ fn xrpc_post_handler(
                 };
                 mutations.push(m);
             }
-            let keypair = srv.pds_keypair.clone();
-            srv.repo.mutate_repo(&did, &mutations, &keypair)?;
-            bsky_mutate_db(&mut srv.atp_db, &did, mutations)?;
+            commit_mutations(&mut srv, &did, mutations)?;
             Ok(json!({}))
         }
         "com.atproto.repo.createRecord" => {
@@ -649,9 +1948,7 @@ fn xrpc_post_handler(
                 srv.tid_gen.next_tid(),
                 json_value_into_ipld(create.record),
             )];
-            let keypair = srv.pds_keypair.clone();
-            srv.repo.mutate_repo(&did, &mutations, &keypair)?;
-            bsky_mutate_db(&mut srv.atp_db, &did, mutations)?;
+            commit_mutations(&mut srv, &did, mutations)?;
             Ok(json!({}))
         }
         "com.atproto.repo.putRecord" => {
@@ -668,9 +1965,7 @@ fn xrpc_post_handler(
                 tid,
                 json_value_into_ipld(put.record),
             )];
-            let keypair = srv.pds_keypair.clone();
-            srv.repo.mutate_repo(&did, &mutations, &keypair)?;
-            bsky_mutate_db(&mut srv.atp_db, &did, mutations)?;
+            commit_mutations(&mut srv, &did, mutations)?;
             Ok(json!({}))
         }
         "com.atproto.repo.deleteRecord" => {
@@ -682,9 +1977,7 @@ fn xrpc_post_handler(
             let _auth_did = &xrpc_check_auth_header(&mut srv, request, Some(&did))?;
 
             let mutations: Vec<Mutation> = vec![Mutation::Delete(collection, tid)];
-            let keypair = srv.pds_keypair.clone();
-            srv.repo.mutate_repo(&did, &mutations, &keypair)?;
-            bsky_mutate_db(&mut srv.atp_db, &did, mutations)?;
+            commit_mutations(&mut srv, &did, mutations)?;
             Ok(json!({}))
         }
         "com.atproto.sync.updateRepo" => {
@@ -692,27 +1985,152 @@ fn xrpc_post_handler(
             let did = Did::from_str(&xrpc_required_param(request, "did")?)?;
             // important that this read is before we take the mutex, because it could be slow!
             let mut car_bytes: Vec<u8> = Default::default();
-            // TODO: unwrap()
-            request.data().unwrap().read_to_end(&mut car_bytes)?;
-            let mut srv = srv.lock().unwrap();
+            request
+                .data()
+                .ok_or(XrpcError::BadRequest("missing request body".to_string()))?
+                .read_to_end(&mut car_bytes)?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
             let _auth_did = &xrpc_check_auth_header(&mut srv, request, Some(&did))?;
-            srv.repo
-                .import_car_bytes(&car_bytes, Some(did.to_string()))?;
-            // TODO: need to update atp_db
+            update_repo(&mut srv, &did, &car_bytes)?;
             Ok(json!({}))
         }
+        "com.atproto.blob.upload" => {
+            let mime_type = match request.header("Content-Type") {
+                Some(v) if BLOB_MIME_TYPES.contains(&v) => v.to_string(),
+                other => Err(XrpcError::BadRequest(format!(
+                    "unsupported blob Content-Type: {}",
+                    other.unwrap_or("none")
+                )))?,
+            };
+            // check auth first, so unauthenticated requests never get their body read
+            let auth_did = {
+                let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+                xrpc_check_auth_header(&mut srv, request, None)?
+            };
+            // read without holding the mutex (like updateRepo), and without trusting Content-Length
+            let mut blob: Vec<u8> = Default::default();
+            request
+                .data()
+                .ok_or(XrpcError::BadRequest("missing request body".to_string()))?
+                .take(BLOB_MAX_SIZE + 1)
+                .read_to_end(&mut blob)?;
+            if blob.is_empty() || blob.len() as u64 > BLOB_MAX_SIZE {
+                Err(XrpcError::BadRequest(format!(
+                    "blob must be between 1 and {BLOB_MAX_SIZE} bytes"
+                )))?;
+            }
+            if !blob_matches_mime_type(&mime_type, &blob) {
+                Err(XrpcError::BadRequest(format!(
+                    "blob content is not of type {mime_type}"
+                )))?;
+            }
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            let cid = srv.repo.put_blob(&blob)?;
+            srv.atp_db
+                .put_blob_meta(&auth_did, &cid, &mime_type, blob.len() as u64)?;
+            Ok(json!({ "cid": cid.to_string() }))
+        }
+        // =========== com.atproto.admin methods (HTTP basic auth)
+        "com.atproto.admin.disableAccount" | "com.atproto.admin.enableAccount" => {
+            let req: com_atproto::admin::AccountRef = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let did = Did::from_str(&req.did)?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            let disabled = method == "com.atproto.admin.disableAccount";
+            info!("admin setting account disabled={}: {}", disabled, did);
+            srv.atp_db.set_account_disabled(&did, disabled)?;
+            Ok(json!({}))
+        }
+        "com.atproto.admin.takeDownRecord" => {
+            let req: com_atproto::admin::TakeDownRecord = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let uri = AtUri::from_str(&req.uri)?;
+            let did = match uri.repository {
+                DidOrHost::Did(ref did_type, ref did_body) => {
+                    Did::from_str(&format!("did:{did_type}:{did_body}"))?
+                }
+                _ => Err(XrpcError::BadRequest(format!(
+                    "expected a DID, not handle, in uri: {uri}"
+                )))?,
+            };
+            let (collection, tid) = match (uri.collection, uri.record) {
+                (Some(ref collection), Some(ref record)) => {
+                    (Nsid::from_str(collection)?, Tid::from_str(record)?)
+                }
+                _ => Err(XrpcError::BadRequest(
+                    "expected a full record URI".to_string(),
+                ))?,
+            };
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            if srv.repo.get_atp_record(&did, &collection, &tid)?.is_none() {
+                Err(XrpcError::NotFound(format!(
+                    "could not find record: {}",
+                    req.uri
+                )))?;
+            }
+            // the PDS holds the repo signing key, so a take-down is just a deletion commit
+            info!("admin taking down record: {}", req.uri);
+            let mutations: Vec<Mutation> = vec![Mutation::Delete(collection, tid)];
+            commit_mutations(&mut srv, &did, mutations)?;
+            Ok(json!({}))
+        }
+        "com.atproto.admin.createWebhook" => {
+            let req: com_atproto::admin::CreateWebhook = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            if !(req.url.starts_with("https://") || req.url.starts_with("http://")) {
+                Err(XrpcError::BadRequest(format!(
+                    "webhook URL must be http(s): {}",
+                    req.url
+                )))?;
+            }
+            let secret = req.secret.unwrap_or_else(random_token);
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            info!("admin creating webhook: {}", req.url);
+            let id = srv.atp_db.create_webhook(&req.url, &secret)?;
+            Ok(json!({ "id": id, "url": req.url, "secret": secret }))
+        }
+        "com.atproto.admin.deleteWebhook" => {
+            let req: com_atproto::admin::WebhookRef = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            info!("admin deleting webhook: {}", req.id);
+            if !srv.atp_db.delete_webhook(req.id)? {
+                Err(XrpcError::NotFound(format!(
+                    "no webhook with id: {}",
+                    req.id
+                )))?;
+            }
+            Ok(json!({}))
+        }
+        "com.atproto.admin.reindexRepo" => {
+            let req: com_atproto::admin::AccountRef = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
+            let did = Did::from_str(&req.did)?;
+            let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+            xrpc_check_admin_auth(&srv, request)?;
+            info!("admin re-indexing repo: {}", did);
+            let count = bsky_reindex(&mut srv, &did)?;
+            Ok(json!({ "recordCount": count }))
+        }
         // =========== app.bsky methods
         "app.bsky.actor.updateProfile" => {
-            let profile: app_bsky::ProfileRecord = rouille::input::json_input(request)?;
+            let update: app_bsky::UpdateProfile = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
             let mut srv = srv.lock().unwrap();
             let auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
-            bsky_update_profile(&mut srv, auth_did, profile)?;
+            bsky_update_profile(&mut srv, auth_did, update)?;
             Ok(json!({}))
         }
         "app.bsky.notification.updateSeen" => {
-            // TODO: actual implementation
+            let req: app_bsky::UpdateSeen = rouille::input::json_input(request)
+                .map_err(|e| XrpcError::BadRequest(format!("failed to parse JSON body: {e}")))?;
             let mut srv = srv.lock().unwrap();
-            let _auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
+            let auth_did = &xrpc_check_auth_header(&mut srv, request, None)?;
+            bsky_update_seen(&mut srv, auth_did, &req.seenAt)?;
             Ok(json!({}))
         }
         _ => Err(anyhow!(XrpcError::NotFound(format!(
@@ -754,6 +2172,18 @@ fn did_doc_view_handler(srv: &Mutex<AtpService>, request: &Request) -> Result<Re
     ))?
 }
 
+/// Serves the account DID for the requested domain, for handle verification by other services.
+fn atproto_did_view_handler(srv: &Mutex<AtpService>, request: &Request) -> Result<Response> {
+    let host = request.header("Host").unwrap_or("localhost");
+    let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+    match srv.atp_db.resolve_handle(host)? {
+        Some(did) => Ok(Response::text(did.to_string())),
+        None => Err(XrpcError::NotFound(
+            "no account registered at this domain".to_string(),
+        ))?,
+    }
+}
+
 // TODO: did, collection, tid have already been parsed by this point
 fn account_view_handler(
     srv: &Mutex<AtpService>,
@@ -769,12 +2199,20 @@ fn account_view_handler(
         .ok_or(XrpcError::NotFound(format!(
             "no DID found for handle: {handle}"
         )))?;
+    if !srv.atp_db.account_is_active(&did)? {
+        Err(XrpcError::NotFound(format!(
+            "account not available: {handle}"
+        )))?;
+    }
 
+    let cursor = request.get_param("cursor");
+    let feed = bsky_get_author_feed(&mut srv, &did, None, 20, cursor.as_deref())?;
     Ok(AccountView {
         domain: host.to_string(),
         did: did.clone(),
         profile: bsky_get_profile(&mut srv, &did)?,
-        feed: bsky_get_author_feed(&mut srv, &did)?.feed,
+        feed: feed.feed,
+        cursor: feed.cursor,
     }
     .render()?)
 }
@@ -793,12 +2231,42 @@ fn thread_view_handler(
 
     // TODO: could construct URI directly
     let uri = AtUri::from_str(&format!("at://{did}/{collection}/{tid}"))?;
-    Ok(ThreadView {
+    let cursor = request.get_param("cursor");
+    let thread = bsky_get_thread(
+        &mut srv,
+        &uri,
+        None,
+        Some(WEB_THREAD_DEPTH),
+        Some(WEB_THREAD_PARENT_HEIGHT),
+        20,
+        cursor.as_deref(),
+    )?;
+    Ok(ThreadView::new(host.to_string(), did, collection, tid.clone(), thread).render()?)
+}
+
+fn search_view_handler(srv: &Mutex<AtpService>, request: &Request) -> Result<String> {
+    let host = request.header("Host").unwrap_or("localhost");
+    let query = request.get_param("q").unwrap_or_default();
+    let author = request.get_param("author").unwrap_or_default();
+    let results = if query.trim().is_empty() {
+        None
+    } else {
+        let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+        let search = post_search_params(&mut srv, request)?;
+        let cursor = request.get_param("cursor");
+        Some(bsky_search_posts(
+            &mut srv,
+            &search,
+            None,
+            20,
+            cursor.as_deref(),
+        )?)
+    };
+    Ok(SearchView {
         domain: host.to_string(),
-        did,
-        collection,
-        tid: tid.clone(),
-        post: bsky_get_thread(&mut srv, &uri, None)?.thread,
+        query,
+        author,
+        results,
     }
     .render()?)
 }
@@ -808,6 +2276,11 @@ fn repo_view_handler(srv: &Mutex<AtpService>, did: &str, request: &Request) -> R
     let did = Did::from_str(did)?;
 
     let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+    if !srv.atp_db.account_is_active(&did)? {
+        Err(XrpcError::NotFound(format!(
+            "repository not available: {did}"
+        )))?;
+    }
     let did_doc = srv.atp_db.get_did_doc(&did)?;
     let commit_cid = &srv.repo.lookup_commit(&did)?.unwrap();
     let commit = srv.repo.get_commit(commit_cid)?;
@@ -839,30 +2312,39 @@ fn collection_view_handler(
     let did = Did::from_str(did)?;
     let collection = Nsid::from_str(collection)?;
 
-    let mut record_list: Vec<Value> = vec![];
+    let limit = 100;
+    let before = request.get_param("cursor");
     let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
-    let commit_cid = &srv.repo.lookup_commit(&did)?.unwrap();
-    let last_commit = srv.repo.get_commit(commit_cid)?;
-    let full_map = srv.repo.mst_to_map(&last_commit.mst_cid)?;
-    let prefix = format!("{collection}/");
-    for (mst_key, cid) in full_map.iter() {
-        debug!("{}", mst_key);
-        if mst_key.starts_with(&prefix) {
-            let record = srv.repo.get_ipld(cid)?;
-            record_list.push(json!({
-                "uri": format!("at://{did}{mst_key}"),
-                "tid": mst_key.split('/').nth(2).unwrap(),
-                "cid": cid,
-                "value": ipld_into_json_value(record),
-            }));
-        }
+    if !srv.atp_db.account_is_active(&did)? {
+        Err(XrpcError::NotFound(format!(
+            "repository not available: {did}"
+        )))?;
     }
+    let records =
+        srv.atp_db
+            .list_records(&did, &collection, limit, before.as_deref(), None, false)?;
+    let cursor = match records.last() {
+        Some(r) if records.len() as u64 >= limit => Some(r.rkey.clone()),
+        _ => None,
+    };
+    let record_list: Vec<Value> = records
+        .into_iter()
+        .map(|r| {
+            json!({
+                "uri": format!("at://{}/{}/{}", did, r.collection, r.rkey),
+                "tid": r.rkey,
+                "cid": r.cid,
+                "value": r.value,
+            })
+        })
+        .collect();
 
     Ok(CollectionView {
         domain: host.to_string(),
         did,
         collection,
         records: record_list,
+        cursor,
     }
     .render()?)
 }
@@ -880,6 +2362,11 @@ fn record_view_handler(
     let rkey = Tid::from_str(tid)?;
 
     let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+    if !srv.atp_db.account_is_active(&did)? {
+        Err(XrpcError::NotFound(format!(
+            "repository not available: {did}"
+        )))?;
+    }
     let key = format!("{collection}/{rkey}");
     let record = match srv.repo.get_atp_record(&did, &collection, &rkey) {
         Ok(Some(ipld)) => ipld_into_json_value(ipld),
@@ -897,3 +2384,388 @@ fn record_view_handler(
     }
     .render()?)
 }
+
+#[test]
+fn test_admin_auth() {
+    let admin_request = |creds: Option<&str>| {
+        let headers = match creds {
+            Some(creds) => vec![(
+                "Authorization".to_string(),
+                format!("Basic {}", data_encoding::BASE64.encode(creds.as_bytes())),
+            )],
+            None => vec![],
+        };
+        Request::fake_http(
+            "GET",
+            "/xrpc/com.atproto.admin.listAccounts",
+            headers,
+            vec![],
+        )
+    };
+    let is_forbidden = |result: Result<Value>| {
+        matches!(
+            result.unwrap_err().downcast_ref::<XrpcError>(),
+            Some(XrpcError::Forbidden(_))
+        )
+    };
+    let method = "com.atproto.admin.listAccounts";
+
+    // admin endpoints are disabled without a configured password
+    let srv = Mutex::new(AtpService::new_ephemeral().unwrap());
+    assert!(is_forbidden(xrpc_get_handler(
+        &srv,
+        method,
+        &admin_request(Some("admin:"))
+    )));
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    srv.config.admin_password = Some("secret".to_string());
+    let srv = Mutex::new(srv);
+    for creds in [None, Some("admin:wrong"), Some("bob:secret")] {
+        assert!(is_forbidden(xrpc_get_handler(
+            &srv,
+            method,
+            &admin_request(creds)
+        )));
+    }
+    assert!(xrpc_get_handler(&srv, method, &admin_request(Some("admin:secret"))).is_ok());
+}
+
+#[test]
+fn test_import_migration_checks() {
+    let is_forbidden = |result: Result<Cid>| {
+        matches!(
+            result.unwrap_err().downcast_ref::<XrpcError>(),
+            Some(XrpcError::Forbidden(_))
+        )
+    };
+
+    // an account on the old PDS, with the current DID document as the PLC directory would have it
+    let old_keypair = KeyPair::new_random();
+    let create_op = plc::CreateOp::new(
+        "alice.test".to_string(),
+        "https://old.example.com".to_string(),
+        &old_keypair,
+        None,
+    );
+    let did = create_op.did_plc();
+    let did_doc = create_op.did_doc();
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let update_op = plc::UpdateOp::new(
+        srv.pds_keypair.pubkey().to_did_key(),
+        srv.config.public_url.clone(),
+        None,
+        &old_keypair,
+    );
+    let mut req = com_atproto::ImportMigrationRequest {
+        did: did.to_string(),
+        handle: "alice.test".to_string(),
+        email: "alice@example.com".to_string(),
+        password: "password".to_string(),
+        inviteCode: None,
+        fromPds: "https://evil.example.com".to_string(),
+        op: update_op.to_json(),
+    };
+
+    // a repository whose head commit was signed by some other key
+    let mut old_repo = RepoStore::open_ephemeral().unwrap();
+    let mst_cid = old_repo.mst_from_map(&Default::default()).unwrap();
+    let meta_cid = old_repo.write_metadata(&did).unwrap();
+    let root_cid = old_repo.write_root(meta_cid, None, mst_cid).unwrap();
+    let sig = KeyPair::new_random().sign_bytes(root_cid.to_string().as_bytes());
+    let commit_cid = old_repo.write_commit(&did, root_cid, &sig).unwrap();
+    let car_bytes = old_repo.export_car(&commit_cid, None).unwrap();
+
+    // the repository must come from the PDS listed in the DID document
+    assert!(is_forbidden(check_migrated_account(
+        &mut srv, &req, &did_doc, &car_bytes
+    )));
+
+    // and the head commit must be signed by the current signing key
+    req.fromPds = "https://old.example.com/".to_string();
+    assert!(is_forbidden(check_migrated_account(
+        &mut srv, &req, &did_doc, &car_bytes
+    )));
+    assert!(!srv
+        .atp_db
+        .account_exists("alice.test", "alice@example.com")
+        .unwrap());
+}
+
+#[test]
+fn test_create_account_external_did() {
+    let is_bad_request = |result: Result<com_atproto::Session>| {
+        matches!(
+            result.unwrap_err().downcast_ref::<XrpcError>(),
+            Some(XrpcError::BadRequest(_))
+        )
+    };
+    assert!(service_url_eq(
+        "https://PDS.Example.com/",
+        "https://pds.example.com"
+    ));
+    assert!(service_url_eq(
+        "https://pds.example.com:443",
+        "https://pds.example.com/"
+    ));
+    assert!(!service_url_eq(
+        "https://pds.example.com",
+        "http://pds.example.com"
+    ));
+    assert!(!service_url_eq(
+        "https://pds.example.com",
+        "https://other.example.com"
+    ));
+    assert!(!service_url_eq("", ""));
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let did = Did::from_str("did:web:alice.example.com").unwrap();
+    let req = com_atproto::AccountRequest {
+        email: "alice@example.com".to_string(),
+        handle: "alice.example.com".to_string(),
+        password: "password".to_string(),
+        inviteCode: None,
+        recoveryKey: None,
+        did: Some(did.to_string()),
+    };
+    let pds_key = srv.pds_keypair.pubkey().to_did_key();
+    let meta = DidDocMeta {
+        did: did.clone(),
+        user_url: "https://alice.example.com".to_string(),
+        // differs from the configured public URL only in case and trailing slash
+        service_url: "HTTP://LOCALHOST/".to_string(),
+        recovery_didkey: pds_key.clone(),
+        signing_didkey: pds_key,
+    };
+
+    let wrong_did = DidDocMeta {
+        did: Did::from_str("did:web:bob.example.com").unwrap(),
+        ..meta.clone()
+    };
+    assert!(is_bad_request(create_account_external_did(
+        &mut srv,
+        &req,
+        &did,
+        &wrong_did.did_doc()
+    )));
+
+    let wrong_service = DidDocMeta {
+        service_url: "https://other.example.com".to_string(),
+        ..meta.clone()
+    };
+    assert!(is_bad_request(create_account_external_did(
+        &mut srv,
+        &req,
+        &did,
+        &wrong_service.did_doc()
+    )));
+
+    let other_key = KeyPair::new_random().pubkey().to_did_key();
+    let missing_key = DidDocMeta {
+        recovery_didkey: other_key.clone(),
+        signing_didkey: other_key,
+        ..meta.clone()
+    };
+    assert!(is_bad_request(create_account_external_did(
+        &mut srv,
+        &req,
+        &did,
+        &missing_key.did_doc()
+    )));
+    assert!(srv.atp_db.resolve_did(&did).unwrap().is_none());
+
+    let sess = create_account_external_did(&mut srv, &req, &did, &meta.did_doc()).unwrap();
+    assert_eq!(sess.did, did.to_string());
+    assert!(srv.atp_db.resolve_did(&did).unwrap().is_some());
+}
+
+#[test]
+fn test_backfill_record_index() {
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let req = com_atproto::AccountRequest {
+        email: "alice@bogus.com".to_string(),
+        handle: "alice.test".to_string(),
+        password: "bogus".to_string(),
+        inviteCode: None,
+        recoveryKey: None,
+        did: None,
+    };
+    let session = create_account(&mut srv, &req, true).unwrap();
+    let did = Did::from_str(&session.did).unwrap();
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let mutations = vec![Mutation::Create(
+        post_nsid.clone(),
+        srv.tid_gen.next_tid(),
+        json_value_into_ipld(json!({"text": "hello", "createdAt": "2022-11-01T00:00:00.000Z"})),
+    )];
+    commit_mutations(&mut srv, &did, mutations).unwrap();
+    let list_posts = |srv: &mut AtpService| {
+        srv.atp_db
+            .list_records(&did, &post_nsid, 10, None, None, false)
+            .unwrap()
+            .len()
+    };
+    assert_eq!(list_posts(&mut srv), 1);
+    assert!(srv.atp_db.record_backfill_dids().unwrap().is_empty());
+
+    // as if the record table was just created, and the repository listed, by migrations
+    srv.atp_db.conn.execute("DELETE FROM record", []).unwrap();
+    srv.atp_db
+        .conn
+        .execute(
+            "INSERT INTO record_backfill (did) VALUES (?1)",
+            [did.to_string()],
+        )
+        .unwrap();
+    assert_eq!(list_posts(&mut srv), 0);
+    srv.backfill_record_index().unwrap();
+    assert_eq!(list_posts(&mut srv), 1);
+    assert!(srv.atp_db.record_backfill_dids().unwrap().is_empty());
+}
+
+#[test]
+fn test_get_record_reply() {
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let req = com_atproto::AccountRequest {
+        email: "alice@bogus.com".to_string(),
+        handle: "alice.test".to_string(),
+        password: "bogus".to_string(),
+        inviteCode: None,
+        recoveryKey: None,
+        did: None,
+    };
+    let session = create_account(&mut srv, &req, true).unwrap();
+    let did = Did::from_str(&session.did).unwrap();
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let srv = Mutex::new(srv);
+    let get_record = |tid: &Tid| -> com_atproto::repo::RecordView {
+        let request = Request::fake_http(
+            "GET",
+            format!(
+                "/xrpc/com.atproto.repo.getRecord?user={did}&collection={post_nsid}&rkey={tid}"
+            ),
+            vec![],
+            vec![],
+        );
+        let resp = xrpc_get_handler(&srv, "com.atproto.repo.getRecord", &request).unwrap();
+        serde_json::from_value(resp).unwrap()
+    };
+    let create_post = |record: Value| -> Tid {
+        let tid = srv.lock().unwrap().tid_gen.next_tid();
+        let mutations = vec![Mutation::Create(
+            post_nsid.clone(),
+            tid.clone(),
+            json_value_into_ipld(record),
+        )];
+        commit_mutations(&mut srv.lock().unwrap(), &did, mutations).unwrap();
+        tid
+    };
+
+    // replying to a thread root
+    let root_tid = create_post(json!({"text": "root", "createdAt": created_at_now()}));
+    let root = get_record(&root_tid);
+    assert_eq!(root.uri, format!("at://{did}/{post_nsid}/{root_tid}"));
+    assert_eq!(root.value["text"], "root");
+    let reply = app_bsky::PostReply::new(&root);
+    assert_eq!(reply.parent.uri, root.uri);
+    assert_eq!(reply.parent.cid, Some(root.cid.clone()));
+    assert_eq!(reply.root, reply.parent);
+
+    // replying to a reply keeps the thread root
+    let reply_tid =
+        create_post(json!({"text": "reply", "reply": reply, "createdAt": created_at_now()}));
+    let parent = get_record(&reply_tid);
+    let reply = app_bsky::PostReply::new(&parent);
+    assert_eq!(reply.parent.uri, parent.uri);
+    assert_eq!(reply.root.uri, root.uri);
+    assert_eq!(reply.root.cid, Some(root.cid));
+}
+
+#[test]
+fn test_handle_under_domain() {
+    assert!(handle_under_domain("alice.example.com", "example.com"));
+    assert!(handle_under_domain("alice.bob.example.com", "example.com"));
+    assert!(!handle_under_domain("example.com", "example.com"));
+    assert!(!handle_under_domain("evilexample.com", "example.com"));
+    assert!(!handle_under_domain("alice.evilexample.com", "example.com"));
+}
+
+#[test]
+fn test_list_records_cursor() {
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let req = com_atproto::AccountRequest {
+        email: "alice@bogus.com".to_string(),
+        handle: "alice.test".to_string(),
+        password: "bogus".to_string(),
+        inviteCode: None,
+        recoveryKey: None,
+        did: None,
+    };
+    let session = create_account(&mut srv, &req, true).unwrap();
+    let did = Did::from_str(&session.did).unwrap();
+    let post_nsid = Nsid::from_str("app.bsky.feed.post").unwrap();
+    let mutations = (0..3)
+        .map(|i| {
+            Mutation::Create(
+                post_nsid.clone(),
+                srv.tid_gen.next_tid(),
+                json_value_into_ipld(
+                    json!({"text": format!("post {i}"), "createdAt": created_at_now()}),
+                ),
+            )
+        })
+        .collect();
+    commit_mutations(&mut srv, &did, mutations).unwrap();
+    let srv = Mutex::new(srv);
+    let list_records = |params: &str| -> Result<Value> {
+        let request = Request::fake_http(
+            "GET",
+            format!(
+                "/xrpc/com.atproto.repo.listRecords?user={did}&collection={post_nsid}&limit=2{params}"
+            ),
+            vec![],
+            vec![],
+        );
+        xrpc_get_handler(&srv, "com.atproto.repo.listRecords", &request)
+    };
+
+    let page = list_records("").unwrap();
+    assert_eq!(page["records"].as_array().unwrap().len(), 2);
+    let cursor = page["cursor"].as_str().unwrap().to_string();
+
+    // the last page has no cursor at all
+    let page = list_records(&format!("&cursor={cursor}")).unwrap();
+    assert_eq!(page["records"].as_array().unwrap().len(), 1);
+    assert_eq!(page["records"][0]["value"]["text"], "post 0");
+    assert!(page.get("cursor").is_none());
+
+    assert!(list_records("&cursor=not*a*cursor").is_err());
+}
+
+#[test]
+fn test_blob_matches_mime_type() {
+    assert!(blob_matches_mime_type(
+        "image/png",
+        b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"
+    ));
+    assert!(blob_matches_mime_type(
+        "image/jpeg",
+        &[0xFF, 0xD8, 0xFF, 0xE0]
+    ));
+    assert!(blob_matches_mime_type("image/gif", b"GIF89a\x01\0\x01\0"));
+    assert!(blob_matches_mime_type(
+        "image/webp",
+        b"RIFF\x24\0\0\0WEBPVP8 "
+    ));
+    assert!(!blob_matches_mime_type(
+        "image/png",
+        b"<html><script>alert(1)</script></html>"
+    ));
+    assert!(!blob_matches_mime_type(
+        "image/png",
+        &[0xFF, 0xD8, 0xFF, 0xE0]
+    ));
+    assert!(!blob_matches_mime_type("image/webp", b"RIFF"));
+    assert!(!blob_matches_mime_type("text/html", b"<html></html>"));
+}
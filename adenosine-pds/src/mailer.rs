@@ -0,0 +1,161 @@
+/// Outgoing email (password resets, email verification, etc)
+///
+/// The SMTP implementation is intentionally minimal: plain-text SMTP with no TLS or
+/// authentication, intended for handing off mail to a local relay (eg, postfix on localhost).
+use anyhow::{anyhow, Result};
+use log::info;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub trait Mailer: Send + Sync {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()>;
+}
+
+/// Formats a plain-text message, including headers, with CRLF line endings. Body lines starting
+/// with a period are "dot-stuffed", as required by the SMTP DATA command.
+fn format_message(from: &str, to: &str, subject: &str, body: &str) -> Result<String> {
+    for val in [from, to, subject] {
+        if val.contains('\r') || val.contains('\n') {
+            return Err(anyhow!("newline in email header value"));
+        }
+    }
+    let mut msg = format!(
+        "From: {from}\r\nTo: {to}\r\nSubject: {subject}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n"
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            msg.push('.');
+        }
+        msg.push_str(line);
+        msg.push_str("\r\n");
+    }
+    Ok(msg)
+}
+
+/// Writes messages to a file (appending), or to stdout if no path is given. Useful for
+/// development and tests.
+pub struct FileMailer {
+    pub path: Option<PathBuf>,
+    pub from: String,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        let msg = format_message(&self.from, to, subject, body)?;
+        match self.path {
+            Some(ref path) => {
+                let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(f, "{msg}")?;
+            }
+            None => println!("{msg}"),
+        }
+        Ok(())
+    }
+}
+
+pub struct SmtpMailer {
+    /// SMTP server as "host:port"
+    pub server: String,
+    pub from: String,
+}
+
+impl SmtpMailer {
+    /// Reads a (possibly multi-line) SMTP reply, and checks the status code
+    fn expect_reply(reader: &mut BufReader<TcpStream>, code: u16) -> Result<()> {
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(anyhow!("SMTP connection closed unexpectedly"));
+            }
+            // continuation lines look like "250-..."; the final line is "250 ..."
+            if line.len() >= 4 && line.as_bytes()[3] == b'-' {
+                continue;
+            }
+            return match line.get(0..3).map(|v| v.parse::<u16>()) {
+                Some(Ok(c)) if c == code => Ok(()),
+                _ => Err(anyhow!("unexpected SMTP reply: {}", line.trim_end())),
+            };
+        }
+    }
+
+    fn command(
+        writer: &mut TcpStream,
+        reader: &mut BufReader<TcpStream>,
+        cmd: &str,
+        code: u16,
+    ) -> Result<()> {
+        writer.write_all(format!("{cmd}\r\n").as_bytes())?;
+        Self::expect_reply(reader, code)
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        let msg = format_message(&self.from, to, subject, body)?;
+        let mut writer = TcpStream::connect(&self.server)?;
+        writer.set_read_timeout(Some(Duration::from_secs(30)))?;
+        writer.set_write_timeout(Some(Duration::from_secs(30)))?;
+        let mut reader = BufReader::new(writer.try_clone()?);
+        let helo_domain = self.from.rsplit('@').next().unwrap_or("localhost");
+
+        Self::expect_reply(&mut reader, 220)?;
+        Self::command(
+            &mut writer,
+            &mut reader,
+            &format!("HELO {helo_domain}"),
+            250,
+        )?;
+        Self::command(
+            &mut writer,
+            &mut reader,
+            &format!("MAIL FROM:<{}>", self.from),
+            250,
+        )?;
+        Self::command(&mut writer, &mut reader, &format!("RCPT TO:<{to}>"), 250)?;
+        Self::command(&mut writer, &mut reader, "DATA", 354)?;
+        Self::command(&mut writer, &mut reader, &format!("{msg}."), 250)?;
+        Self::command(&mut writer, &mut reader, "QUIT", 221)?;
+        info!("sent email via SMTP to {}: {}", to, subject);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_format_message() {
+    let msg = format_message(
+        "pds@example.com",
+        "alice@example.com",
+        "Hello",
+        "line one\n.dot line",
+    )
+    .unwrap();
+    assert!(msg.starts_with("From: pds@example.com\r\nTo: alice@example.com\r\n"));
+    assert!(msg.ends_with("\r\n\r\nline one\r\n..dot line\r\n"));
+
+    assert!(format_message(
+        "pds@example.com",
+        "alice@example.com\r\nBcc: x",
+        "Hello",
+        ""
+    )
+    .is_err());
+}
+
+#[test]
+fn test_file_mailer() {
+    let path = std::env::temp_dir().join(format!("adenosine-mailer-test-{}", std::process::id()));
+    let mailer = FileMailer {
+        path: Some(path.clone()),
+        from: "pds@example.com".to_string(),
+    };
+    mailer
+        .send("alice@example.com", "Reset", "token: abc123")
+        .unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(contents.contains("Subject: Reset\r\n"));
+    assert!(contents.contains("token: abc123"));
+}
@@ -0,0 +1,5 @@
+
+-- keep a copy of the current handle next to the DID document, so that handle changes can be
+-- applied to both together
+ALTER TABLE did_doc ADD COLUMN handle TEXT;
+UPDATE did_doc SET handle = (SELECT account.handle FROM account WHERE account.did = did_doc.did);
@@ -0,0 +1,19 @@
+
+CREATE TABLE invite_code(
+    code                TEXT PRIMARY KEY NOT NULL,
+    -- DID of the account this code was created for, or 'admin'
+    creator             TEXT NOT NULL,
+    max_uses            INTEGER NOT NULL,
+    use_count           INTEGER NOT NULL DEFAULT 0,
+    disabled            BOOLEAN NOT NULL DEFAULT 0,
+    expires_at          TIMESTAMP WITH TIME ZONE,
+    created_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
+CREATE INDEX invite_code_creator_idx on invite_code(creator);
+
+CREATE TABLE invite_code_use(
+    code                TEXT NOT NULL,
+    used_by_did         TEXT NOT NULL,
+    used_at             TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') ),
+    PRIMARY KEY(code, used_by_did)
+);
@@ -0,0 +1,3 @@
+
+-- accounts can be disabled by an admin (eg, for moderation)
+ALTER TABLE account ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+
+ALTER TABLE account ADD COLUMN deactivated BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE account_delete_token(
+    did                 TEXT PRIMARY KEY NOT NULL,
+    token               TEXT NOT NULL,
+    expires_at          TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE did_tombstone(
+    did                 TEXT PRIMARY KEY NOT NULL,
+    -- signed did:plc tombstone operation (JSON), if applicable
+    plc_op_json         TEXT,
+    deleted_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
@@ -0,0 +1,13 @@
+
+ALTER TABLE account ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT 0;
+
+-- single-use, expiring tokens sent to the account email address
+CREATE TABLE account_token(
+    token               TEXT PRIMARY KEY NOT NULL,
+    did                 TEXT NOT NULL,
+    -- 'password_reset' or 'email_verify'
+    purpose             TEXT NOT NULL,
+    expires_at          TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
+CREATE INDEX account_token_did_idx on account_token(did);
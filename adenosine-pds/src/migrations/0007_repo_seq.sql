@@ -0,0 +1,13 @@
+
+-- sequenced log of repository commits, for the subscribeRepos event stream
+CREATE TABLE repo_seq(
+    seq                 INTEGER PRIMARY KEY AUTOINCREMENT,
+    did                 TEXT NOT NULL,
+    commit_cid          TEXT NOT NULL,
+    prev_cid            TEXT,
+    -- JSON array of {action, path, cid} objects
+    ops_json            TEXT NOT NULL,
+    -- CAR file bytes with the new blocks in this commit
+    blocks              BLOB NOT NULL,
+    created_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
@@ -0,0 +1,22 @@
+
+-- per-server outbound webhook subscriptions, notified of all local repo writes
+CREATE TABLE webhook(
+    id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+    url                 TEXT NOT NULL,
+    -- shared secret for the HMAC-SHA256 signature header
+    secret              TEXT NOT NULL,
+    created_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
+
+-- durable queue of pending webhook deliveries
+CREATE TABLE webhook_outbox(
+    id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id          INTEGER NOT NULL,
+    payload_json        TEXT NOT NULL,
+    attempts            INTEGER NOT NULL DEFAULT 0,
+    -- NULL once delivery has been given up on
+    next_attempt_at     TIMESTAMP WITH TIME ZONE DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') ),
+    last_error          TEXT,
+    created_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
+CREATE INDEX webhook_outbox_next_attempt_idx on webhook_outbox(next_attempt_at);
@@ -0,0 +1,12 @@
+
+-- crawl frontier for the 'spider' command, persisted so crawls can be resumed
+CREATE TABLE spider_frontier(
+    did                 TEXT PRIMARY KEY NOT NULL,
+    -- 'pending', 'in_progress', 'done', or 'error'
+    status              TEXT NOT NULL DEFAULT 'pending',
+    commit_cid          TEXT,
+    last_error          TEXT,
+    added_at            TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') ),
+    updated_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
+CREATE INDEX spider_frontier_status_idx on spider_frontier(status, added_at);
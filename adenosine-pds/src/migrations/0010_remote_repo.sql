@@ -0,0 +1,12 @@
+
+-- remote (non-local) repositories followed by local accounts, kept in sync by a background worker
+CREATE TABLE remote_repo(
+    did                 TEXT PRIMARY KEY NOT NULL,
+    handle              TEXT,
+    pds_url             TEXT,
+    -- last commit imported and indexed; NULL forces a full re-fetch
+    head_cid            TEXT,
+    last_error          TEXT,
+    checked_at          TIMESTAMP WITH TIME ZONE,
+    created_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') )
+);
@@ -0,0 +1,11 @@
+-- generic index of the current records in every repository, for all collections. populated by
+-- re-indexing (`adenosine-pds reindex --all`) for repositories which existed before this table
+CREATE TABLE record(
+    did                 TEXT NOT NULL,
+    collection          TEXT NOT NULL,
+    rkey                TEXT NOT NULL,
+    record_cid          TEXT NOT NULL,
+    record_json         TEXT NOT NULL,
+    indexed_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') ),
+    PRIMARY KEY(did, collection, rkey)
+);
@@ -0,0 +1,7 @@
+-- notifications are generated when records are indexed. 'subject_uri' and 'subject_cid' are the
+-- record which caused the notification (eg, a like), 'author_did' is the account which created
+-- it, and 'reason_subject' is the post it is about (if any)
+ALTER TABLE bsky_notification ADD COLUMN author_did TEXT;
+ALTER TABLE bsky_notification ADD COLUMN reason_subject TEXT;
+CREATE UNIQUE INDEX bsky_notification_unique_idx on bsky_notification(user_did, subject_uri, reason);
+CREATE INDEX bsky_notification_subject_uri_idx on bsky_notification(subject_uri);
@@ -0,0 +1,13 @@
+-- full-text index of actor handles and profile fields, for app.bsky.actor.search. rows are
+-- re-written when an account is created or changes handle, and when a profile record is indexed
+CREATE VIRTUAL TABLE bsky_actor_fts USING fts5(
+    did UNINDEXED,
+    handle,
+    display_name,
+    description,
+    prefix = '2 3'
+);
+INSERT INTO bsky_actor_fts (did, handle, display_name, description)
+    SELECT actor.did, COALESCE((SELECT handle FROM account WHERE account.did = actor.did), (SELECT handle FROM remote_repo WHERE remote_repo.did = actor.did), ''), json_extract(profile.record_json, '$.displayName'), json_extract(profile.record_json, '$.description')
+    FROM (SELECT did FROM account UNION SELECT did FROM record WHERE collection = 'app.bsky.actor.profile') AS actor
+    LEFT JOIN record AS profile ON profile.did = actor.did AND profile.collection = 'app.bsky.actor.profile' AND profile.rkey = (SELECT MAX(rkey) FROM record WHERE record.did = actor.did AND record.collection = 'app.bsky.actor.profile');
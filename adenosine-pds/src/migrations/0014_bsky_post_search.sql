@@ -0,0 +1,7 @@
+-- full-text index of post text, for app.bsky.feed.searchPosts. maintained along with bsky_post
+CREATE VIRTUAL TABLE bsky_post_fts USING fts5(
+    did UNINDEXED,
+    tid UNINDEXED,
+    text
+);
+INSERT INTO bsky_post_fts (did, tid, text) SELECT did, tid, json_extract(record_json, '$.text') FROM bsky_post;
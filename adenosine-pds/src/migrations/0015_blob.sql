@@ -0,0 +1,9 @@
+-- metadata for uploaded blobs (eg, images); the bytes themselves are in the blockstore
+CREATE TABLE blob(
+    did                 TEXT NOT NULL,
+    cid                 TEXT NOT NULL,
+    mime_type           TEXT NOT NULL,
+    size                INTEGER NOT NULL,
+    created_at          TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT ( strftime('%Y-%m-%dT%H:%M:%fZ', 'now') ),
+    PRIMARY KEY (did, cid)
+);
@@ -0,0 +1,14 @@
+-- repositories which have been synced or mirrored, and so should have rows in the index tables
+CREATE VIEW indexed_repo AS
+    SELECT did FROM account
+    UNION SELECT did FROM remote_repo WHERE head_cid IS NOT NULL
+    UNION SELECT did FROM spider_frontier WHERE status = 'done';
+
+-- repositories which were indexed before the generic record index existed, and still need to be
+-- backfilled (at server start). rows are removed once a repository has been backfilled
+CREATE TABLE record_backfill(
+    did                 TEXT PRIMARY KEY NOT NULL
+);
+INSERT INTO record_backfill (did)
+    SELECT did FROM indexed_repo
+    WHERE NOT EXISTS (SELECT 1 FROM record WHERE record.did = indexed_repo.did);
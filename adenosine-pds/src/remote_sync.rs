@@ -0,0 +1,175 @@
+/// Background worker which keeps copies of remote repositories followed by local accounts up to
+/// date, so that posts by accounts on other servers show up in timelines.
+///
+/// Each followed remote DID is periodically resolved to its PDS, and the current repo root is
+/// polled with `com.atproto.sync.getRoot`. When the root has changed, only the blocks since the
+/// last synced commit are fetched, and the record-level differences are applied to the bsky
+/// index. Network requests happen without holding the service lock.
+///
+/// The handle claimed in a remote DID document is only recorded (and shown in feeds) once it has
+/// been checked to resolve back to the same DID.
+use crate::db::RemoteRepo;
+use crate::{bsky_mutate_db, bsky_reindex, AtpService, Result, XrpcError};
+use adenosine::crypto::PubKey;
+use adenosine::identifiers::Did;
+use adenosine::plc;
+use adenosine::resolve;
+use anyhow::anyhow;
+use log::{debug, info, warn};
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Returns the handle claimed in a remote DID document, but only if the handle also resolves back
+/// to the same DID. Otherwise the DID is shown in place of a handle, so a remote account can't
+/// claim somebody else's handle.
+///
+/// `known_handle` is the previously verified handle, which isn't re-checked if unchanged.
+fn verified_handle(did: &Did, did_doc: &Value, known_handle: Option<&str>) -> Option<String> {
+    let handle = did_doc["alsoKnownAs"][0].as_str().map(|v| {
+        v.trim_start_matches("https://")
+            .trim_start_matches("at://")
+            .to_string()
+    })?;
+    if known_handle == Some(handle.as_str()) {
+        return Some(handle);
+    }
+    match resolve::resolve_handle_well_known(&handle) {
+        Ok(resolved) if &resolved == did => Some(handle),
+        Ok(resolved) => {
+            warn!(
+                "handle {} claimed by {} resolves to a different DID: {}",
+                handle, did, resolved
+            );
+            None
+        }
+        Err(e) => {
+            warn!("failed to verify handle {} for {}: {}", handle, did, e);
+            None
+        }
+    }
+}
+
+/// Syncs a single remote repository. Returns the number of changed records that were indexed.
+pub fn sync_remote_repo(srv: &Mutex<AtpService>, did: &Did) -> Result<u64> {
+    let (known, plc_host) = {
+        let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+        (
+            srv.atp_db.get_remote_repo(did)?,
+            srv.config.plc_host.clone(),
+        )
+    };
+    let old_head = known.as_ref().and_then(|r| r.head_cid);
+
+    let did_doc = match did.did_type().as_str() {
+        "web" => resolve::resolve_did_web(did)?,
+        "plc" => resolve::resolve_did_plc(&plc_host, did)?,
+        other => Err(anyhow!("unsupported DID method: {}", other))?,
+    };
+    let pds_url =
+        plc::did_doc_pds_url(&did_doc).ok_or(anyhow!("no PDS service in DID document: {}", did))?;
+    let signing_key = plc::did_doc_key(&did_doc, "signingKey")
+        .ok_or(anyhow!("no signing key in DID document: {}", did))?;
+    let handle = verified_handle(
+        did,
+        &did_doc,
+        known.as_ref().and_then(|r| r.handle.as_deref()),
+    );
+    let remote = RemoteRepo {
+        did: did.clone(),
+        handle,
+        pds_url: Some(pds_url.clone()),
+        head_cid: old_head,
+    };
+
+    let root = resolve::fetch_repo_root(&pds_url, did)?;
+    if Some(root) == old_head {
+        debug!("remote repo unchanged: {}", did);
+        let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+        srv.atp_db.put_remote_repo(&remote)?;
+        return Ok(0);
+    }
+    let car_bytes = resolve::fetch_repo_car(&pds_url, did, old_head.as_ref())?;
+
+    let mut srv = srv.lock().or(Err(XrpcError::MutexPoisoned))?;
+    // never overwrite the repository of a local account
+    if srv.atp_db.resolve_did(did)?.is_some() {
+        Err(anyhow!("not syncing local account as remote repo: {}", did))?;
+    }
+    let commit_cid = srv.repo.import_car_bytes(&car_bytes, None)?;
+    let commit = srv.repo.get_commit(&commit_cid)?;
+    if &commit.did != did {
+        Err(anyhow!(
+            "fetched repository is for a different DID: {}",
+            commit.did
+        ))?;
+    }
+    srv.repo
+        .verify_commit_sig(&commit_cid, &PubKey::from_did_key(&signing_key)?)?;
+
+    let count = match old_head {
+        Some(old_head) => {
+            if !srv.repo.commit_descends_from(&commit_cid, &old_head)? {
+                Err(anyhow!(
+                    "remote commit {} does not descend from last synced commit {}",
+                    commit_cid,
+                    old_head
+                ))?;
+            }
+            let old_mst_cid = srv.repo.get_commit(&old_head)?.mst_cid;
+            let mutations = srv.repo.mst_diff(Some(&old_mst_cid), &commit.mst_cid)?;
+            let count = mutations.len() as u64;
+            srv.repo.link_repo(did, &commit_cid)?;
+            bsky_mutate_db(&srv.atp_db, did, mutations)?;
+            count
+        }
+        None => {
+            srv.repo.link_repo(did, &commit_cid)?;
+            bsky_reindex(&mut srv, did)?
+        }
+    };
+    srv.atp_db.put_remote_repo(&RemoteRepo {
+        head_cid: Some(commit_cid),
+        ..remote
+    })?;
+    Ok(count)
+}
+
+/// Runs forever, syncing all followed remote repositories every `interval`. Intended to be run in
+/// a dedicated thread.
+pub fn run_remote_sync_worker(srv: &Mutex<AtpService>, interval: Duration) {
+    loop {
+        std::thread::sleep(interval);
+        let dids = match srv.lock() {
+            Ok(mut srv) => srv.atp_db.remote_followed_dids(),
+            Err(_) => return,
+        };
+        let dids = match dids {
+            Ok(dids) => dids,
+            Err(e) => {
+                warn!("failed to list followed remote repos: {}", e);
+                continue;
+            }
+        };
+        for did in dids.iter() {
+            match sync_remote_repo(srv, did) {
+                Ok(0) => (),
+                Ok(count) => info!("synced remote repo ({} changed records): {}", count, did),
+                Err(e) => {
+                    warn!("failed to sync remote repo {}: {}", did, e);
+                    // network failures are retried incrementally; anything that went wrong after
+                    // fetching (bad blocks, history, or signature) forces a full re-fetch
+                    let reset_head = e.downcast_ref::<reqwest::Error>().is_none();
+                    if let Ok(mut srv) = srv.lock() {
+                        let res = srv
+                            .atp_db
+                            .set_remote_repo_error(did, &e.to_string(), reset_head);
+                        if let Err(db_err) = res {
+                            warn!("failed to record remote sync error: {}", db_err);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
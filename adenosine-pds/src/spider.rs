@@ -0,0 +1,368 @@
+/// Crawler which mirrors remote repositories in to the local blockstore and bsky index.
+///
+/// Starting from a set of seed DIDs, each repository is fetched from the PDS listed in its DID
+/// document, imported (with MST and commit signature verification), and indexed. The DIDs of
+/// followed accounts and liked posts are added to the crawl frontier, which is persisted in the
+/// ATP database so that crawls can be interrupted and resumed.
+use crate::{bsky_reindex, AtpService, Result, XrpcError};
+use adenosine::crypto::PubKey;
+use adenosine::identifiers::{AtUri, Did, DidOrHost};
+use adenosine::ipld::ipld_into_json_value;
+use adenosine::plc;
+use adenosine::repo::RepoStore;
+use adenosine::resolve;
+use anyhow::anyhow;
+use libipld::Cid;
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct SpiderConfig {
+    /// Number of repositories to fetch in parallel
+    pub concurrency: usize,
+    /// Pause by each worker after each fetch, to limit the request rate to remote servers
+    pub delay: Duration,
+    /// Stop after this many repositories have been processed (in this run)
+    pub max_repos: Option<u64>,
+    /// PLC directory server for resolving did:plc identifiers
+    pub plc_host: String,
+    /// Re-try DIDs which failed in a previous run
+    pub retry_errors: bool,
+}
+
+impl Default for SpiderConfig {
+    fn default() -> Self {
+        SpiderConfig {
+            concurrency: 4,
+            delay: Duration::from_millis(1000),
+            max_repos: None,
+            plc_host: "https://plc.directory".to_string(),
+            retry_errors: false,
+        }
+    }
+}
+
+/// Marks a worker as active (fetching a repository, and so possibly about to add to the frontier)
+/// for as long as it is held. Decrementing on drop means that workers which exit early with an
+/// error don't leave the others waiting forever.
+struct ActiveGuard<'a>(&'a Mutex<SpiderState>);
+
+impl Drop for ActiveGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = match self.0.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        state.active -= 1;
+    }
+}
+
+struct SpiderState {
+    srv: AtpService,
+    /// Number of workers currently fetching a repository (and which might add to the frontier)
+    active: usize,
+    /// Number of repositories claimed from the frontier in this run
+    claimed: u64,
+}
+
+/// Returns the de-duplicated DIDs referenced by follow and like records in a repository
+pub fn extract_linked_dids(repo: &mut RepoStore, mst_cid: &Cid) -> Result<Vec<Did>> {
+    let mut dids: BTreeSet<Did> = Default::default();
+    for (mst_key, cid) in repo.mst_to_map(mst_cid)?.iter() {
+        let linked = if mst_key.starts_with("app.bsky.graph.follow/") {
+            let val = ipld_into_json_value(repo.get_ipld(cid)?);
+            val["subject"]["did"]
+                .as_str()
+                .and_then(|v| Did::from_str(v).ok())
+        } else if mst_key.starts_with("app.bsky.feed.like/") {
+            let val = ipld_into_json_value(repo.get_ipld(cid)?);
+            match val["subject"]["uri"].as_str().map(AtUri::from_str) {
+                Some(Ok(AtUri {
+                    repository: DidOrHost::Did(did_type, did_body),
+                    ..
+                })) => Did::from_str(&format!("did:{did_type}:{did_body}")).ok(),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        if let Some(did) = linked {
+            dids.insert(did);
+        }
+    }
+    Ok(dids.into_iter().collect())
+}
+
+/// Placeholder signature on genesis commits written by older versions of adenosine-pds
+const LEGACY_GENESIS_SIG: &[u8] = b"XXX-dummy-sig";
+
+/// Resolves the DID document for a DID, and fetches the full repository from its PDS
+fn fetch_remote_repo(did: &Did, config: &SpiderConfig) -> Result<(Value, Vec<u8>)> {
+    let did_doc = match did.did_type().as_str() {
+        "web" => resolve::resolve_did_web(did)?,
+        "plc" => resolve::resolve_did_plc(&config.plc_host, did)?,
+        other => Err(anyhow!("unsupported DID method: {}", other))?,
+    };
+    let pds_url =
+        plc::did_doc_pds_url(&did_doc).ok_or(anyhow!("no PDS service in DID document: {}", did))?;
+    let car_bytes = resolve::fetch_repo_car(&pds_url, did, None)?;
+    Ok((did_doc, car_bytes))
+}
+
+/// Verifies and imports a fetched repository, points the DID alias at it, and re-indexes it.
+/// Returns the commit CID.
+fn import_remote_repo(
+    srv: &mut AtpService,
+    did: &Did,
+    did_doc: &Value,
+    car_bytes: &[u8],
+) -> Result<Cid> {
+    let signing_key = plc::did_doc_key(did_doc, "signingKey")
+        .ok_or(anyhow!("no signing key in DID document: {}", did))?;
+    // import_car_bytes() verifies the MST structure
+    let commit_cid = srv.repo.import_car_bytes(car_bytes, None)?;
+    let commit = srv.repo.get_commit(&commit_cid)?;
+    if &commit.did != did {
+        Err(anyhow!(
+            "fetched repository is for a different DID: {}",
+            commit.did
+        ))?;
+    }
+    // older versions of adenosine-pds wrote the genesis commit of new accounts with a placeholder
+    // signature. a repo which still has that as its head has no records, so there is nothing to
+    // verify; anything later must be properly signed.
+    let unsigned_genesis = commit.prev.is_none()
+        && &*commit.sig == LEGACY_GENESIS_SIG
+        && srv.repo.mst_to_map(&commit.mst_cid)?.is_empty();
+    if unsigned_genesis {
+        info!(
+            "accepting empty repository with unsigned genesis commit: {}",
+            did
+        );
+    } else {
+        srv.repo
+            .verify_commit_sig(&commit_cid, &PubKey::from_did_key(&signing_key)?)?;
+    }
+    srv.repo.link_repo(did, &commit_cid)?;
+    bsky_reindex(srv, did)?;
+    Ok(commit_cid)
+}
+
+fn spider_worker(state: &Mutex<SpiderState>, config: &SpiderConfig) -> Result<()> {
+    loop {
+        let did = {
+            let mut state = state.lock().or(Err(XrpcError::MutexPoisoned))?;
+            if let Some(max) = config.max_repos {
+                if state.claimed >= max {
+                    return Ok(());
+                }
+            }
+            match state.srv.atp_db.spider_claim_next()? {
+                Some(did) => {
+                    state.active += 1;
+                    state.claimed += 1;
+                    did
+                }
+                // other workers may still add to the frontier
+                None if state.active > 0 => {
+                    drop(state);
+                    std::thread::sleep(Duration::from_millis(500));
+                    continue;
+                }
+                None => return Ok(()),
+            }
+        };
+        let active = ActiveGuard(state);
+
+        // local accounts are not fetched or re-imported, but are still expanded
+        let is_local = {
+            let mut state = state.lock().or(Err(XrpcError::MutexPoisoned))?;
+            state.srv.atp_db.resolve_did(&did)?.is_some()
+        };
+        let fetched = if is_local {
+            None
+        } else {
+            info!("spider fetching: {}", did);
+            Some(fetch_remote_repo(&did, config))
+        };
+
+        let mut state = state.lock().or(Err(XrpcError::MutexPoisoned))?;
+        let result = match fetched {
+            None => state
+                .srv
+                .repo
+                .lookup_commit(&did)?
+                .ok_or(anyhow!("no repository for local account: {}", did)),
+            Some(Ok((did_doc, car_bytes))) => {
+                import_remote_repo(&mut state.srv, &did, &did_doc, &car_bytes)
+            }
+            Some(Err(e)) => Err(e),
+        };
+        match result {
+            Ok(commit_cid) => {
+                let commit = state.srv.repo.get_commit(&commit_cid)?;
+                let linked = extract_linked_dids(&mut state.srv.repo, &commit.mst_cid)?;
+                let added = state.srv.atp_db.spider_add_dids(&linked)?;
+                info!(
+                    "spider imported {} ({} linked DIDs, {} new)",
+                    did,
+                    linked.len(),
+                    added
+                );
+                state.srv.atp_db.spider_finish(&did, Ok(&commit_cid))?;
+            }
+            Err(e) => {
+                warn!("spider failed to mirror {}: {}", did, e);
+                state
+                    .srv
+                    .atp_db
+                    .spider_finish(&did, Err(e.to_string().as_str()))?;
+            }
+        }
+        drop(state);
+        drop(active);
+        if !is_local {
+            std::thread::sleep(config.delay);
+        }
+    }
+}
+
+/// Runs a crawl to completion (or until `max_repos`), starting from any seed DIDs plus whatever
+/// remains in the persisted frontier from previous runs.
+pub fn run_spider(mut srv: AtpService, seeds: &[Did], config: &SpiderConfig) -> Result<()> {
+    srv.atp_db.spider_reset(config.retry_errors)?;
+    srv.atp_db.spider_add_dids(seeds)?;
+    let state = Arc::new(Mutex::new(SpiderState {
+        srv,
+        active: 0,
+        claimed: 0,
+    }));
+    let mut workers = vec![];
+    for _ in 0..config.concurrency.max(1) {
+        let state = state.clone();
+        let config = config.clone();
+        workers.push(std::thread::spawn(move || spider_worker(&state, &config)));
+    }
+    for worker in workers {
+        if let Err(e) = worker
+            .join()
+            .map_err(|_| anyhow!("spider worker panicked"))?
+        {
+            warn!("spider worker failed: {}", e);
+        }
+    }
+    let mut state = state.lock().or(Err(XrpcError::MutexPoisoned))?;
+    let claimed = state.claimed;
+    let done = state.srv.atp_db.spider_count("done")?;
+    let errors = state.srv.atp_db.spider_count("error")?;
+    let pending = state.srv.atp_db.spider_count("pending")?;
+    info!(
+        "spider finished: {} repos processed this run; {} done, {} errors, {} pending overall",
+        claimed, done, errors, pending,
+    );
+    Ok(())
+}
+
+#[test]
+fn test_extract_linked_dids() {
+    use adenosine::crypto::KeyPair;
+    use adenosine::identifiers::{Nsid, Tid};
+    use adenosine::repo::Mutation;
+    use libipld::ipld;
+
+    let mut repo = RepoStore::open_ephemeral().unwrap();
+    let did = Did::from_str("did:plc:alice").unwrap();
+    let keypair = KeyPair::new_random();
+    let meta_cid = repo.write_metadata(&did).unwrap();
+    let map_cid = repo.mst_from_map(&Default::default()).unwrap();
+    let root_cid = repo.write_root(meta_cid, None, map_cid).unwrap();
+    repo.write_commit(&did, root_cid, "dummy-sig").unwrap();
+
+    let mutations = vec![
+        Mutation::Create(
+            Nsid::from_str("app.bsky.graph.follow").unwrap(),
+            Tid::from_str("3jqfcqzm3fo2j").unwrap(),
+            ipld!({"subject": {"did": "did:plc:bob", "declarationCid": "dummy"}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        Mutation::Create(
+            Nsid::from_str("app.bsky.feed.like").unwrap(),
+            Tid::from_str("3jqfcqzm3fp2j").unwrap(),
+            ipld!({"subject": {"uri": "at://did:plc:carol/app.bsky.feed.post/3jqfcqzm3fq2j", "cid": "dummy"}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        // duplicate
+        Mutation::Create(
+            Nsid::from_str("app.bsky.graph.follow").unwrap(),
+            Tid::from_str("3jqfcqzm3fr2j").unwrap(),
+            ipld!({"subject": {"did": "did:plc:bob", "declarationCid": "dummy"}, "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+        // not a link
+        Mutation::Create(
+            Nsid::from_str("app.bsky.feed.post").unwrap(),
+            Tid::from_str("3jqfcqzm3fs2j").unwrap(),
+            ipld!({"text": "did:plc:dave", "createdAt": "2022-11-01T00:00:00.000Z"}),
+        ),
+    ];
+    let commit_cid = repo.mutate_repo(&did, &mutations, &keypair).unwrap();
+    let commit = repo.get_commit(&commit_cid).unwrap();
+    assert_eq!(
+        extract_linked_dids(&mut repo, &commit.mst_cid).unwrap(),
+        vec![
+            Did::from_str("did:plc:bob").unwrap(),
+            Did::from_str("did:plc:carol").unwrap(),
+        ]
+    );
+}
+
+#[test]
+fn test_import_unsigned_genesis() {
+    use adenosine::crypto::KeyPair;
+    use adenosine::identifiers::{Nsid, Tid};
+    use adenosine::plc::DidDocMeta;
+    use adenosine::repo::Mutation;
+    use libipld::ipld;
+
+    let mut srv = AtpService::new_ephemeral().unwrap();
+    let did = Did::from_str("did:plc:alice").unwrap();
+    let keypair = KeyPair::new_random();
+    let did_doc = DidDocMeta {
+        did: did.clone(),
+        user_url: "https://alice.test".to_string(),
+        service_url: "https://pds.test".to_string(),
+        recovery_didkey: keypair.pubkey().to_did_key(),
+        signing_didkey: keypair.pubkey().to_did_key(),
+    }
+    .did_doc();
+
+    let mut repo = RepoStore::open_ephemeral().unwrap();
+    let meta_cid = repo.write_metadata(&did).unwrap();
+    let map_cid = repo.mst_from_map(&Default::default()).unwrap();
+    let root_cid = repo.write_root(meta_cid, None, map_cid).unwrap();
+    let genesis_cid = repo.write_commit(&did, root_cid, "XXX-dummy-sig").unwrap();
+    let car_bytes = repo.export_car(&genesis_cid, None).unwrap();
+    assert_eq!(
+        import_remote_repo(&mut srv, &did, &did_doc, &car_bytes).unwrap(),
+        genesis_cid
+    );
+
+    // later commits still need a valid signature
+    let mutations = vec![Mutation::Create(
+        Nsid::from_str("app.bsky.feed.post").unwrap(),
+        Tid::from_str("3jqfcqzm3fs2j").unwrap(),
+        ipld!({"text": "hello", "createdAt": "2022-11-01T00:00:00.000Z"}),
+    )];
+    let commit_cid = repo
+        .mutate_repo(&did, &mutations, &KeyPair::new_random())
+        .unwrap();
+    let car_bytes = repo.export_car(&commit_cid, None).unwrap();
+    assert!(import_remote_repo(&mut srv, &did, &did_doc, &car_bytes).is_err());
+    let commit_cid = repo.mutate_repo(&did, &mutations, &keypair).unwrap();
+    let car_bytes = repo.export_car(&commit_cid, None).unwrap();
+    assert_eq!(
+        import_remote_repo(&mut srv, &did, &did_doc, &car_bytes).unwrap(),
+        commit_cid
+    );
+}
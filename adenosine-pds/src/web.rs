@@ -1,8 +1,9 @@
 use adenosine::app_bsky;
 use adenosine::com_atproto;
-use adenosine::identifiers::{Did, Nsid, Tid};
+use adenosine::identifiers::{AtUri, Did, Nsid, Tid};
 use adenosine::repo::RepoCommit;
 use askama::Template;
+use std::str::FromStr;
 
 #[derive(Template)]
 #[template(path = "error.html")]
@@ -31,6 +32,7 @@ pub struct AccountView {
     pub did: Did,
     pub profile: app_bsky::ProfileView,
     pub feed: Vec<app_bsky::FeedPostView>,
+    pub cursor: Option<String>,
 }
 
 #[derive(Template)]
@@ -40,7 +42,96 @@ pub struct ThreadView {
     pub did: Did,
     pub collection: Nsid,
     pub tid: Tid,
+    /// Ancestors of the post, starting with the highest
+    pub parents: Vec<app_bsky::ThreadPostView>,
+    /// Thread page of the parent of the highest included ancestor, if that is itself a reply
+    pub earlier_posts_path: Option<String>,
     pub post: app_bsky::ThreadPostView,
+    pub replies: Vec<ThreadReplyItem>,
+    pub cursor: Option<String>,
+}
+
+/// A reply in the flattened (depth-first) reply tree of a thread page
+pub struct ThreadReplyItem {
+    /// Nesting level, starting at zero for direct replies
+    pub depth: usize,
+    pub post: Option<app_bsky::PostView>,
+    /// If the post has replies which were not fetched (because of the depth limit)
+    pub more_replies: bool,
+}
+
+impl ThreadView {
+    /// Flattens the parent chain and reply tree of a thread for rendering
+    pub fn new(
+        domain: String,
+        did: Did,
+        collection: Nsid,
+        tid: Tid,
+        thread: app_bsky::PostThread,
+    ) -> Self {
+        let mut post = thread.thread;
+        let mut parents = vec![];
+        let mut next_parent = post.parent.take();
+        while let Some(mut parent) = next_parent {
+            next_parent = parent.parent.take();
+            parents.push(*parent);
+        }
+        parents.reverse();
+        let earlier_posts_path = parents
+            .first()
+            .and_then(|p| p.post.as_ref())
+            .and_then(|p| p.record.reply.as_ref())
+            .and_then(|reply| thread_path(&reply.parent.uri));
+        let mut replies = vec![];
+        flatten_replies(post.replies.take().unwrap_or_default(), 0, &mut replies);
+        ThreadView {
+            domain,
+            did,
+            collection,
+            tid,
+            parents,
+            earlier_posts_path,
+            post,
+            replies,
+            cursor: thread.cursor,
+        }
+    }
+}
+
+/// Web path of the thread page for a post AT-URI, or `None` if it does not point to a record
+fn thread_path(aturi: &str) -> Option<String> {
+    let aturi = AtUri::from_str(aturi).ok()?;
+    Some(format!("/u/{}/post/{}", aturi.repository, aturi.record?))
+}
+
+fn flatten_replies(
+    replies: Vec<app_bsky::ThreadPostView>,
+    depth: usize,
+    items: &mut Vec<ThreadReplyItem>,
+) {
+    for reply in replies {
+        let more_replies = match (&reply.post, &reply.replies) {
+            (Some(post), None) => post.replyCount > 0,
+            _ => false,
+        };
+        items.push(ThreadReplyItem {
+            depth,
+            post: reply.post,
+            more_replies,
+        });
+        if let Some(nested) = reply.replies {
+            flatten_replies(nested, depth + 1, items);
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+pub struct SearchView {
+    pub domain: String,
+    pub query: String,
+    pub author: String,
+    pub results: Option<app_bsky::PostSearchResults>,
 }
 
 #[derive(Template)]
@@ -59,6 +150,7 @@ pub struct CollectionView {
     pub did: Did,
     pub collection: Nsid,
     pub records: Vec<serde_json::Value>,
+    pub cursor: Option<String>,
 }
 
 #[derive(Template)]
@@ -73,8 +165,57 @@ pub struct RecordView {
 
 mod filters {
     use crate::AtUri;
+    use adenosine::app_bsky;
     use std::str::FromStr;
 
+    fn escape_html(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#x27;")
+    }
+
+    /// Renders post text as (escaped) HTML, with mention and link entities as links. Entities with
+    /// invalid or overlapping byte offsets are ignored.
+    pub fn post_text_html(post: &app_bsky::Post) -> ::askama::Result<String> {
+        let text = &post.text;
+        let mut entities: Vec<&app_bsky::PostEntity> = post.entities.iter().flatten().collect();
+        entities.sort_by_key(|e| e.index.start);
+        let mut html = String::new();
+        let mut pos: usize = 0;
+        for entity in entities {
+            let (start, end) = (entity.index.start as usize, entity.index.end as usize);
+            if start < pos
+                || end <= start
+                || end > text.len()
+                || !text.is_char_boundary(start)
+                || !text.is_char_boundary(end)
+            {
+                continue;
+            }
+            let href = match entity.r#type.as_str() {
+                "mention" if entity.value.starts_with("did:") => format!("/at/{}", entity.value),
+                "link"
+                    if entity.value.starts_with("https://")
+                        || entity.value.starts_with("http://") =>
+                {
+                    entity.value.clone()
+                }
+                _ => continue,
+            };
+            html.push_str(&escape_html(&text[pos..start]));
+            html.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(&href),
+                escape_html(&text[start..end])
+            ));
+            pos = end;
+        }
+        html.push_str(&escape_html(&text[pos..]));
+        Ok(html)
+    }
+
     pub fn aturi_to_path(aturi: &str) -> ::askama::Result<String> {
         let aturi = AtUri::from_str(aturi).expect("aturi parse");
         if aturi.record.is_some() {
@@ -96,12 +237,9 @@ mod filters {
     }
 
     pub fn aturi_to_thread_path(aturi: &str) -> ::askama::Result<String> {
-        let aturi = AtUri::from_str(aturi).expect("aturi parse");
-        Ok(format!(
-            "/u/{}/post/{}",
-            aturi.repository,
-            aturi.record.unwrap()
-        ))
+        super::thread_path(aturi).ok_or_else(|| {
+            ::askama::Error::Custom(format!("not a record AT-URI: {}", aturi).into())
+        })
     }
 
     pub fn aturi_to_tid(aturi: &str) -> ::askama::Result<String> {
@@ -114,3 +252,33 @@ mod filters {
         }
     }
 }
+
+#[test]
+fn test_post_text_html() {
+    let post: app_bsky::Post = serde_json::from_value(serde_json::json!({
+        "text": "hi @bob.test <3 see https://example.com/?a=1&b=2 🙂",
+        "entities": [
+            {"index": {"start": 20, "end": 48}, "type": "link", "value": "https://example.com/?a=1&b=2"},
+            {"index": {"start": 3, "end": 12}, "type": "mention", "value": "did:plc:bob"},
+            // overlapping, out of range, and not a char boundary
+            {"index": {"start": 5, "end": 10}, "type": "mention", "value": "did:plc:bogus"},
+            {"index": {"start": 40, "end": 100}, "type": "link", "value": "https://example.com"},
+            {"index": {"start": 50, "end": 51}, "type": "link", "value": "https://example.com"},
+        ],
+    }))
+    .unwrap();
+    assert_eq!(
+        filters::post_text_html(&post).unwrap(),
+        "hi <a href=\"/at/did:plc:bob\">@bob.test</a> &lt;3 see <a href=\"https://example.com/?a=1&amp;b=2\">https://example.com/?a=1&amp;b=2</a> 🙂"
+    );
+}
+
+#[test]
+fn test_aturi_to_thread_path() {
+    assert_eq!(
+        filters::aturi_to_thread_path("at://did:plc:bob/app.bsky.feed.post/3jk4xyzabcd22").unwrap(),
+        "/u/did:plc:bob/post/3jk4xyzabcd22"
+    );
+    assert!(filters::aturi_to_thread_path("at://did:plc:bob").is_err());
+    assert!(filters::aturi_to_thread_path("not a uri").is_err());
+}
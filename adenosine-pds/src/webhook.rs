@@ -0,0 +1,137 @@
+/// Outbound webhook delivery for local repository writes.
+///
+/// Payloads are queued in the `webhook_outbox` table as part of each commit (see
+/// `commit_mutations`), and a background thread POSTs them to each subscribed URL, retrying with
+/// exponential backoff. Each request body is signed with HMAC-SHA256 using the webhook's secret.
+use crate::db::{AtpDatabase, WebhookDelivery};
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use sha2::Sha256;
+use std::time::Duration;
+
+/// Header containing `sha256=<hex HMAC of request body>`
+pub const SIGNATURE_HEADER: &str = "X-Adenosine-Signature";
+
+/// How often the delivery worker checks the outbox when idle
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Deliveries are given up on after this many failed attempts
+const MAX_ATTEMPTS: u32 = 12;
+
+/// Retry delay after the first failure; doubles with each attempt
+const BASE_RETRY_SECS: u64 = 30;
+
+/// Upper limit on the retry delay
+const MAX_RETRY_SECS: u64 = 6 * 60 * 60;
+
+/// Computes the hex-encoded HMAC-SHA256 of a request body
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    data_encoding::HEXLOWER.encode(&mac.finalize().into_bytes())
+}
+
+/// Retry delay (as an SQLite date modifier) after the given number of failed attempts, or None
+/// if delivery should be given up on.
+fn retry_delay(attempts: u32) -> Option<String> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+    let secs = BASE_RETRY_SECS
+        .saturating_mul(1 << (attempts.saturating_sub(1)).min(20))
+        .min(MAX_RETRY_SECS);
+    Some(format!("+{secs} seconds"))
+}
+
+fn deliver(client: &reqwest::blocking::Client, delivery: &WebhookDelivery) -> Result<()> {
+    let signature = sign_payload(&delivery.secret, delivery.payload_json.as_bytes());
+    let resp = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header(SIGNATURE_HEADER, format!("sha256={signature}"))
+        .body(delivery.payload_json.clone())
+        .send()?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("HTTP {}", resp.status()));
+    }
+    Ok(())
+}
+
+/// Runs forever, delivering queued webhook payloads. Intended to be run in a dedicated thread,
+/// with its own database connection.
+pub fn run_delivery_worker(mut db: AtpDatabase) {
+    let client = match reqwest::blocking::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("failed to start webhook delivery worker: {}", e);
+            return;
+        }
+    };
+    loop {
+        let deliveries = match db.due_webhook_deliveries(50) {
+            Ok(deliveries) => deliveries,
+            Err(e) => {
+                warn!("failed to read webhook outbox: {}", e);
+                vec![]
+            }
+        };
+        if deliveries.is_empty() {
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+        for delivery in deliveries.iter() {
+            let res = match deliver(&client, delivery) {
+                Ok(()) => {
+                    debug!(
+                        "delivered webhook payload {} to {}",
+                        delivery.id, delivery.url
+                    );
+                    db.complete_webhook_delivery(delivery.id)
+                }
+                Err(e) => {
+                    let delay = retry_delay(delivery.attempts + 1);
+                    warn!(
+                        "webhook delivery {} to {} failed (attempt {}, retry: {:?}): {}",
+                        delivery.id,
+                        delivery.url,
+                        delivery.attempts + 1,
+                        delay,
+                        e
+                    );
+                    db.fail_webhook_delivery(delivery.id, &e.to_string(), delay.as_deref())
+                }
+            };
+            if let Err(e) = res {
+                warn!("failed to update webhook outbox: {}", e);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sign_payload() {
+    // RFC 4231, test case 2
+    assert_eq!(
+        sign_payload("Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn test_retry_delay() {
+    assert_eq!(retry_delay(1), Some("+30 seconds".to_string()));
+    assert_eq!(retry_delay(2), Some("+60 seconds".to_string()));
+    assert_eq!(retry_delay(3), Some("+120 seconds".to_string()));
+    assert_eq!(retry_delay(11), Some(format!("+{MAX_RETRY_SECS} seconds")));
+    assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+}
@@ -1,6 +1,9 @@
 /// app.bsky types (manually entered)
+use crate::com_atproto::repo::RecordView;
 use serde_json::Value;
 
+pub mod text;
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub struct Subject {
     pub uri: String,
@@ -41,6 +44,19 @@ pub struct FollowRecord {
 pub struct ProfileRecord {
     pub displayName: String,
     pub description: Option<String>,
+    pub avatar: Option<Blob>,
+    pub banner: Option<Blob>,
+}
+
+/// Changes to a profile record; fields which are not set are left as they are, and an empty
+/// description removes it
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct UpdateProfile {
+    pub displayName: Option<String>,
+    pub description: Option<String>,
+    pub avatar: Option<Blob>,
+    pub banner: Option<Blob>,
 }
 
 // app.bsky.system.actorUser or app.bsky.system.actorScene
@@ -69,6 +85,10 @@ pub struct ProfileView {
     pub creator: String,
     pub displayName: Option<String>,
     pub description: Option<String>,
+    /// URL of the avatar image
+    pub avatar: Option<String>,
+    /// URL of the banner image
+    pub banner: Option<String>,
     pub followersCount: u64,
     pub followsCount: u64,
     pub membersCount: u64,
@@ -81,6 +101,8 @@ pub struct ProfileView {
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub struct GenericFeed {
     pub feed: Vec<FeedPostView>,
+    /// For fetching the next page; only set if there may be more items
+    pub cursor: Option<String>,
 }
 
 #[allow(non_snake_case)]
@@ -115,7 +137,17 @@ pub struct PostView {
     pub upvoteCount: u64,
     pub downvoteCount: u64,
     pub indexedAt: String,
-    pub viewer: Option<Value>,
+    pub viewer: Option<PostViewerState>,
+}
+
+/// State of a post relative to the requesting account
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct PostViewerState {
+    /// URI of the viewer's repost record, if they have reposted the post
+    pub repost: Option<String>,
+    /// URI of the viewer's like record, if they have liked the post
+    pub like: Option<String>,
 }
 
 #[allow(non_snake_case)]
@@ -166,6 +198,23 @@ pub struct PostReply {
     pub root: Subject,
 }
 
+impl PostReply {
+    /// References for a new post replying to `parent`. The thread root is the parent's own root,
+    /// unless the parent is itself the root.
+    pub fn new(parent: &RecordView) -> Self {
+        let parent_ref = Subject {
+            uri: parent.uri.clone(),
+            cid: Some(parent.cid.clone()),
+        };
+        let root = serde_json::from_value(parent.value["reply"]["root"].clone())
+            .unwrap_or_else(|_| parent_ref.clone());
+        PostReply {
+            parent: parent_ref,
+            root,
+        }
+    }
+}
+
 #[allow(non_snake_case)]
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub struct PostEntity {
@@ -239,12 +288,14 @@ pub struct EmbedImageView {
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub struct PostThread {
     pub thread: ThreadPostView,
+    /// For fetching the next page of direct replies; only set if there may be more
+    pub cursor: Option<String>,
 }
 
 #[allow(non_snake_case)]
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub struct FollowTarget {
-    // TODO: nested follow list?
+    /// The follow record itself
     pub subject: Subject,
     pub did: String,
     pub handle: String,
@@ -260,3 +311,124 @@ pub struct Follow {
     pub subject: Subject,
     pub follows: FollowTarget,
 }
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// The record which caused the notification (eg, a like or reply)
+    pub uri: String,
+    pub cid: String,
+    pub author: UserView,
+    /// "like", "repost", "follow", "mention", or "reply"
+    pub reason: String,
+    /// For likes, reposts, and replies, the post which was liked (etc)
+    pub reasonSubject: Option<String>,
+    pub record: Value,
+    pub isRead: bool,
+    pub indexedAt: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct NotificationList {
+    pub notifications: Vec<Notification>,
+    pub cursor: Option<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct UpdateSeen {
+    /// Notifications indexed up to this time are marked as seen
+    pub seenAt: String,
+}
+
+/// for getFollowers
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct FollowersList {
+    pub subject: UserView,
+    pub followers: Vec<FollowTarget>,
+    pub cursor: Option<String>,
+}
+
+/// for getFollows
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct FollowsList {
+    pub subject: UserView,
+    pub follows: Vec<FollowTarget>,
+    pub cursor: Option<String>,
+}
+
+/// An account which liked or reposted a post
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct RefActorView {
+    pub actor: UserView,
+    pub createdAt: String,
+    pub indexedAt: String,
+}
+
+/// for getLikedBy
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct LikedBy {
+    pub uri: String,
+    pub cid: Option<String>,
+    pub likedBy: Vec<RefActorView>,
+    pub cursor: Option<String>,
+}
+
+/// for getRepostedBy
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct RepostedBy {
+    pub uri: String,
+    pub cid: Option<String>,
+    pub repostedBy: Vec<RefActorView>,
+    pub cursor: Option<String>,
+}
+
+/// An account matching an `app.bsky.actor.search` query, or suggested by `getSuggestions`
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct ActorSearchView {
+    pub did: String,
+    pub handle: String,
+    pub declaration: DeclRef,
+    pub displayName: Option<String>,
+    pub description: Option<String>,
+    pub avatar: Option<String>,
+    pub indexedAt: Option<String>,
+}
+
+/// for app.bsky.actor.search
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct ActorSearchResults {
+    pub users: Vec<ActorSearchView>,
+    pub cursor: Option<String>,
+}
+
+/// for app.bsky.actor.searchTypeahead
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct ActorTypeahead {
+    pub users: Vec<UserView>,
+}
+
+/// for app.bsky.feed.searchPosts
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct PostSearchResults {
+    pub posts: Vec<PostView>,
+    pub cursor: Option<String>,
+}
+
+/// for app.bsky.actor.getSuggestions
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Suggestions {
+    pub actors: Vec<ActorSearchView>,
+    pub cursor: Option<String>,
+}
@@ -0,0 +1,158 @@
+/// Detection of mentions and links in post text, for filling in `Post.entities`.
+///
+/// Entity offsets are UTF-8 byte offsets in to the post text (not character offsets), and the
+/// slice for a mention includes the leading '@'.
+use crate::app_bsky::{PostEntity, TextSlice};
+use crate::identifiers::{Did, DidOrHost};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::str::FromStr;
+
+lazy_static! {
+    static ref MENTION_RE: Regex = Regex::new(r"(?:^|[\s(])(@[a-zA-Z0-9.-]+)").unwrap();
+    static ref LINK_RE: Regex = Regex::new(r#"(?:^|[\s(])(https?://[^\s<>"]+)"#).unwrap();
+}
+
+/// Returns the byte ranges and handles (without '@') of syntactically valid `@handle` mentions
+pub fn detect_mentions(text: &str) -> Vec<(TextSlice, String)> {
+    let mut mentions = vec![];
+    for caps in MENTION_RE.captures_iter(text) {
+        let m = caps.get(1).unwrap();
+        // a mention at the end of a sentence shouldn't include the period
+        let handle = m.as_str()[1..].trim_end_matches(&['.', '-'][..]);
+        if let Ok(DidOrHost::Host(_)) = DidOrHost::from_str(handle) {
+            mentions.push((
+                TextSlice {
+                    start: m.start() as u64,
+                    end: (m.start() + 1 + handle.len()) as u64,
+                },
+                handle.to_string(),
+            ));
+        }
+    }
+    mentions
+}
+
+/// Returns the byte ranges and URLs of `http://` and `https://` links
+pub fn detect_links(text: &str) -> Vec<(TextSlice, String)> {
+    let mut links = vec![];
+    for caps in LINK_RE.captures_iter(text) {
+        let m = caps.get(1).unwrap();
+        let mut url = m
+            .as_str()
+            .trim_end_matches(&['.', ',', ';', ':', '!', '?', '\''][..]);
+        // only include a closing paren if the URL itself has an opening one (eg, wikipedia)
+        if url.ends_with(')') && !url.contains('(') {
+            url = &url[..url.len() - 1];
+        }
+        if url.len() > "https://".len() {
+            links.push((
+                TextSlice {
+                    start: m.start() as u64,
+                    end: (m.start() + url.len()) as u64,
+                },
+                url.to_string(),
+            ));
+        }
+    }
+    links
+}
+
+/// Builds the entities for post text: mentions which `resolve_handle` can resolve to a DID
+/// (others are left as plain text), and links. Entities are sorted by offset.
+pub fn extract_entities<F>(text: &str, mut resolve_handle: F) -> Result<Vec<PostEntity>>
+where
+    F: FnMut(&str) -> Result<Option<Did>>,
+{
+    let mut entities = vec![];
+    for (index, handle) in detect_mentions(text) {
+        if let Some(did) = resolve_handle(&handle)? {
+            entities.push(PostEntity {
+                index,
+                r#type: "mention".to_string(),
+                value: did.to_string(),
+            });
+        }
+    }
+    for (index, url) in detect_links(text) {
+        entities.push(PostEntity {
+            index,
+            r#type: "link".to_string(),
+            value: url,
+        });
+    }
+    entities.sort_by_key(|e| e.index.start);
+    Ok(entities)
+}
+
+#[test]
+fn test_detect_mentions() {
+    let handles = |text: &str| -> Vec<String> {
+        detect_mentions(text)
+            .into_iter()
+            .map(|(_, handle)| handle)
+            .collect()
+    };
+    assert_eq!(handles("hi @alice.test!"), vec!["alice.test"]);
+    assert_eq!(handles("@alice.test."), vec!["alice.test"]);
+    assert_eq!(
+        handles("(@bob.test) @carol.test"),
+        vec!["bob.test", "carol.test"]
+    );
+    assert!(handles("email@alice.test").is_empty());
+    assert!(handles("@alice").is_empty());
+    assert!(handles("@.test @123.test").is_empty());
+
+    // byte offsets, not characters
+    let mentions = detect_mentions("héllo 🙂 @alice.test");
+    assert_eq!(mentions[0].0, TextSlice { start: 12, end: 23 });
+    assert_eq!(&"héllo 🙂 @alice.test"[12..23], "@alice.test");
+}
+
+#[test]
+fn test_detect_links() {
+    let urls = |text: &str| -> Vec<String> {
+        detect_links(text).into_iter().map(|(_, url)| url).collect()
+    };
+    assert_eq!(
+        urls("see https://example.com/page?a=b."),
+        vec!["https://example.com/page?a=b"]
+    );
+    assert_eq!(
+        urls("(http://example.com) and https://en.wikipedia.org/wiki/Rust_(programming_language)"),
+        vec![
+            "http://example.com",
+            "https://en.wikipedia.org/wiki/Rust_(programming_language)"
+        ]
+    );
+    assert!(urls("ftp://example.com https:// xhttps://example.com").is_empty());
+
+    let links = detect_links("ünïcode https://example.com");
+    assert_eq!(links[0].0, TextSlice { start: 10, end: 29 });
+}
+
+#[test]
+fn test_extract_entities() {
+    let text = "@bob.test look: https://example.com @nobody.test";
+    let entities = extract_entities(text, |handle| match handle {
+        "bob.test" => Ok(Some(Did::from_str("did:plc:bob").unwrap())),
+        _ => Ok(None),
+    })
+    .unwrap();
+    assert_eq!(
+        entities,
+        vec![
+            PostEntity {
+                index: TextSlice { start: 0, end: 9 },
+                r#type: "mention".to_string(),
+                value: "did:plc:bob".to_string(),
+            },
+            PostEntity {
+                index: TextSlice { start: 16, end: 35 },
+                r#type: "link".to_string(),
+                value: "https://example.com".to_string(),
+            },
+        ]
+    );
+}
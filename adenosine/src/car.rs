@@ -1,9 +1,10 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use crate::vendored::iroh_car::{CarHeader, CarReader, CarWriter};
 use futures::TryStreamExt;
 use ipfs_sqlite_block_store::BlockStore;
 use libipld::{Block, Cid};
+use std::collections::HashSet;
 use std::path::PathBuf;
 use tokio::fs::File;
 use tokio::io::{AsyncRead, BufReader};
@@ -36,14 +37,32 @@ pub fn load_car_path_to_blockstore(
     rt.block_on(inner_car_path_loader(db, car_path))
 }
 
+/// Synchronous wrapper for exporting all blocks under a root CID into in-memory CAR bytes.
+///
+/// If `exclude_root` is provided, any blocks which are also descendants of that CID (eg, a
+/// previous repo commit) are skipped, leaving only the "new" blocks.
 pub fn read_car_bytes_from_blockstore(
     db: &mut BlockStore<libipld::DefaultParams>,
     root: &Cid,
+    exclude_root: Option<&Cid>,
+) -> Result<Vec<u8>> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(inner_car_bytes_reader(db, root, exclude_root))
+}
+
+/// Synchronous wrapper for exporting a specific list of blocks into in-memory CAR bytes, with the
+/// given root CID in the header. Blocks are written in the order given.
+pub fn read_car_bytes_from_cids(
+    db: &mut BlockStore<libipld::DefaultParams>,
+    root: &Cid,
+    cids: &[Cid],
 ) -> Result<Vec<u8>> {
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
-    rt.block_on(inner_car_bytes_reader(db, root))
+    rt.block_on(inner_car_cids_reader(db, root, cids))
 }
 
 async fn inner_car_bytes_loader(
@@ -86,15 +105,40 @@ async fn inner_car_loader<R: AsyncRead + Send + Unpin>(
 async fn inner_car_bytes_reader(
     db: &mut BlockStore<libipld::DefaultParams>,
     root: &Cid,
+    exclude_root: Option<&Cid>,
 ) -> Result<Vec<u8>> {
     let car_header = CarHeader::new_v1(vec![*root]);
     let buf: Vec<u8> = Default::default();
     let mut car_writer = CarWriter::new(car_header, buf);
 
+    let exclude: HashSet<Cid> = match exclude_root {
+        Some(cid) => db.get_descendants::<HashSet<_>>(cid)?,
+        None => Default::default(),
+    };
     let cid_list = db.get_descendants::<Vec<_>>(root)?;
     for cid in cid_list {
+        if exclude.contains(&cid) {
+            continue;
+        }
         let block = db.get_block(&cid)?.expect("block content");
         car_writer.write(cid, block).await?;
     }
     Ok(car_writer.finish().await?)
 }
+
+async fn inner_car_cids_reader(
+    db: &mut BlockStore<libipld::DefaultParams>,
+    root: &Cid,
+    cids: &[Cid],
+) -> Result<Vec<u8>> {
+    let car_header = CarHeader::new_v1(vec![*root]);
+    let buf: Vec<u8> = Default::default();
+    let mut car_writer = CarWriter::new(car_header, buf);
+    for cid in cids {
+        let block = db
+            .get_block(cid)?
+            .ok_or(anyhow!("missing block for CAR export: {}", cid))?;
+        car_writer.write(*cid, block).await?;
+    }
+    Ok(car_writer.finish().await?)
+}
@@ -0,0 +1,55 @@
+/// com.atproto.admin types (manually entered)
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct AccountView {
+    pub did: String,
+    pub handle: String,
+    pub email: String,
+    pub disabled: bool,
+    pub createdAt: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct RepoView {
+    pub account: AccountView,
+    pub head: Option<String>,
+    pub collections: Vec<String>,
+    pub recordCount: u64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct AccountRef {
+    pub did: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct TakeDownRecord {
+    pub uri: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct CreateWebhook {
+    pub url: String,
+    pub secret: Option<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct WebhookView {
+    pub id: u64,
+    pub url: String,
+    pub createdAt: String,
+    pub pendingDeliveries: u64,
+    pub failedDeliveries: u64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct WebhookRef {
+    pub id: u64,
+}
@@ -1,5 +1,6 @@
 // com.atproto types (manually entered)
 
+pub mod admin;
 pub mod repo;
 
 #[allow(non_snake_case)]
@@ -10,6 +11,8 @@ pub struct AccountRequest {
     pub password: String,
     pub inviteCode: Option<String>,
     pub recoveryKey: Option<String>,
+    /// Existing (externally controlled) did:web identity to register, instead of creating one
+    pub did: Option<String>,
 }
 
 #[allow(non_snake_case)]
@@ -27,3 +30,93 @@ pub struct Session {
     pub accessJwt: String,
     pub refreshJwt: String,
 }
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct HandleUpdateRequest {
+    pub handle: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct TokenRequest {
+    pub token: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct AccountDeleteRequest {
+    pub did: String,
+    pub password: String,
+    pub token: String,
+}
+
+/// Sent to the old PDS to start a migration, with details of the new PDS
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct RequestMigrationRequest {
+    pub service: String,
+    pub signingKey: String,
+}
+
+/// Sent to the old PDS to finish a migration, with the signed DID update operation
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct CompleteMigrationRequest {
+    pub op: serde_json::Value,
+}
+
+/// Sent to the new PDS to create an account for an existing DID. The new PDS fetches the DID
+/// document and repository from `fromPds`.
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct ImportMigrationRequest {
+    pub did: String,
+    pub handle: String,
+    pub email: String,
+    pub password: String,
+    pub inviteCode: Option<String>,
+    pub fromPds: String,
+    pub op: serde_json::Value,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct CreateInviteCodeRequest {
+    pub useCount: u64,
+    pub forAccount: Option<String>,
+    pub expiresAt: Option<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct InviteCode {
+    pub code: String,
+    pub available: u64,
+    pub disabled: bool,
+    // DID of the account the code was created for, or "admin"
+    pub createdBy: String,
+    pub createdAt: String,
+    pub expiresAt: Option<String>,
+    pub uses: Vec<InviteCodeUse>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct InviteCodeUse {
+    pub usedBy: String,
+    pub usedAt: String,
+}
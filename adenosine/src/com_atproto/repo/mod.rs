@@ -10,6 +10,15 @@ pub struct Describe {
     pub nameIsCorrect: bool,
 }
 
+/// A single record, as returned by `com.atproto.repo.getRecord`
+#[allow(non_snake_case)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct RecordView {
+    pub uri: String,
+    pub cid: String,
+    pub value: serde_json::Value,
+}
+
 #[allow(non_snake_case)]
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 pub struct CreateRecord {
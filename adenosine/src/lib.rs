@@ -8,6 +8,7 @@ pub mod ipld;
 pub mod mst;
 pub mod plc;
 pub mod repo;
+pub mod resolve;
 pub mod xrpc;
 
 mod ucan_p256;
@@ -28,6 +29,18 @@ pub fn created_at_now() -> String {
         .unwrap()
 }
 
+/// Parses an RFC 3339 timestamp (with any UTC offset), and re-formats it as UTC with exactly
+/// millisecond precision, like "2022-11-22T09:21:15.640Z". Timestamps in this form can be compared
+/// as strings, including against SQLite `strftime('%Y-%m-%dT%H:%M:%fZ', 'now')`.
+pub fn normalize_timestamp(ts: &str) -> anyhow::Result<String> {
+    let ts = time::OffsetDateTime::parse(ts, &time::format_description::well_known::Rfc3339)?
+        .to_offset(time::UtcOffset::UTC);
+    let format = time::format_description::parse(
+        "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z",
+    )?;
+    Ok(ts.format(&format)?)
+}
+
 #[test]
 fn test_created_at_now() {
     // eg: 2022-11-22T09:20:44.123Z
@@ -38,3 +51,17 @@ fn test_created_at_now() {
     assert_eq!(&ts[10..11], "T");
     assert_eq!(&ts[23..24], "Z");
 }
+
+#[test]
+fn test_normalize_timestamp() {
+    assert_eq!(
+        normalize_timestamp("2022-11-22T09:21:15.640Z").unwrap(),
+        "2022-11-22T09:21:15.640Z"
+    );
+    assert_eq!(
+        normalize_timestamp("2022-11-22T10:21:15+01:00").unwrap(),
+        "2022-11-22T09:21:15.000Z"
+    );
+    assert!(normalize_timestamp("2022-11-22").is_err());
+    assert!(normalize_timestamp("tomorrow").is_err());
+}
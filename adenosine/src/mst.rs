@@ -10,6 +10,7 @@
 /// In the atproto MST implementation, SHA-256 is the hashing algorithm, and "leading zeros" are
 /// counted in blocks of 4 bits (so a leading zero byte counts as two zeros). This happens to match
 /// simple hex encoding of the SHA-256 hash.
+use crate::repo::BLOB_ALIAS_PREFIX;
 use anyhow::{anyhow, Context, Result};
 use ipfs_sqlite_block_store::BlockStore;
 use libipld::cbor::DagCborCodec;
@@ -19,7 +20,7 @@ use libipld::store::DefaultParams;
 use libipld::Block;
 use libipld::{Cid, DagCbor};
 use log::{debug, error, info};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::path::PathBuf;
 
 #[derive(Debug, DagCbor, PartialEq, Eq)]
@@ -101,7 +102,11 @@ pub fn print_mst_keys(db: &mut BlockStore<libipld::DefaultParams>, cid: &Cid) ->
 pub fn dump_mst_keys(db_path: &PathBuf) -> Result<()> {
     let mut db: BlockStore<libipld::DefaultParams> = BlockStore::open(db_path, Default::default())?;
 
-    let all_aliases: Vec<(Vec<u8>, Cid)> = db.aliases()?;
+    let all_aliases: Vec<(Vec<u8>, Cid)> = db
+        .aliases::<Vec<(Vec<u8>, Cid)>>()?
+        .into_iter()
+        .filter(|(alias, _)| !alias.starts_with(BLOB_ALIAS_PREFIX.as_bytes()))
+        .collect();
     if all_aliases.is_empty() {
         error!("expected at least one alias in block store");
         std::process::exit(-1);
@@ -171,6 +176,34 @@ pub fn collect_mst_keys(
     Ok(())
 }
 
+/// Collects the CIDs of MST nodes under (and including) `cid`, and of the values (records) those
+/// nodes point to. Subtrees rooted at any node in `skip_nodes` are not walked, which is how only the
+/// new parts of a tree are found when comparing against an older version of it.
+///
+/// Only MST nodes are read from the blockstore, not the records themselves.
+pub fn collect_mst_cids(
+    db: &mut BlockStore<libipld::DefaultParams>,
+    cid: &Cid,
+    skip_nodes: &HashSet<Cid>,
+    nodes: &mut HashSet<Cid>,
+    vals: &mut HashSet<Cid>,
+) -> Result<()> {
+    if skip_nodes.contains(cid) || !nodes.insert(*cid) {
+        return Ok(());
+    }
+    let node = get_mst_node(db, cid)?;
+    if let Some(ref left) = node.l {
+        collect_mst_cids(db, left, skip_nodes, nodes, vals)?;
+    }
+    for entry in node.e.iter() {
+        vals.insert(entry.v);
+        if let Some(ref right) = entry.t {
+            collect_mst_cids(db, right, skip_nodes, nodes, vals)?;
+        }
+    }
+    Ok(())
+}
+
 fn leading_zeros(key: &str) -> u8 {
     let digest = sha256::digest(key);
     let digest = digest.as_bytes();
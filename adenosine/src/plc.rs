@@ -5,7 +5,8 @@ use crate::crypto::{KeyPair, PubKey};
 /// DIDs (and DID documents) using a single 'create' genesis block. Key rotation, etc, is not
 /// supported.
 use crate::identifiers::Did;
-use anyhow::Result;
+use crate::ipld::json_value_into_ipld;
+use anyhow::{anyhow, Result};
 use libipld::cbor::DagCborCodec;
 use libipld::multihash::Code;
 use libipld::{Block, Cid, DagCbor, DefaultParams};
@@ -128,6 +129,279 @@ impl CreateOp {
     }
 }
 
+/// Signed operation which permanently retires a did:plc identity.
+///
+/// `prev` must be the CID of the most recent operation for the DID (see `op_cid()`), as fetched
+/// from the PLC directory. It is signed and serialized as a CID string (not a DAG-CBOR link), so
+/// that the signed bytes match the JSON form submitted to and returned by the directory.
+#[derive(Debug, DagCbor, PartialEq, Eq, Clone)]
+pub struct TombstoneOp {
+    #[ipld(rename = "type")]
+    pub op_type: String,
+    pub prev: Option<String>,
+    pub sig: String,
+}
+
+#[derive(Debug, DagCbor, PartialEq, Eq, Clone)]
+struct UnsignedTombstoneOp {
+    #[ipld(rename = "type")]
+    pub op_type: String,
+    pub prev: Option<String>,
+}
+
+impl TombstoneOp {
+    pub fn new(prev: Option<Cid>, keypair: &KeyPair) -> Self {
+        let unsigned = UnsignedTombstoneOp {
+            op_type: "plc_tombstone".to_string(),
+            prev: prev.map(|v| v.to_string()),
+        };
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR");
+        let sig = keypair.sign_bytes(block.data());
+        TombstoneOp {
+            op_type: unsigned.op_type,
+            prev: unsigned.prev,
+            sig,
+        }
+    }
+
+    pub fn verify(&self, key: &PubKey) -> Result<()> {
+        let unsigned = UnsignedTombstoneOp {
+            op_type: self.op_type.clone(),
+            prev: self.prev.clone(),
+        };
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR");
+        key.verify_bytes(block.data(), &self.sig)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": self.op_type,
+            "prev": self.prev,
+            "sig": self.sig,
+        })
+    }
+}
+
+/// Signed operation which changes the signing key and PDS service endpoint of a did:plc identity,
+/// eg when migrating an account between PDS instances.
+///
+/// As with `TombstoneOp`, `prev` must be the CID of the most recent operation for the DID.
+#[allow(non_snake_case)]
+#[derive(Debug, DagCbor, PartialEq, Eq, Clone)]
+pub struct UpdateOp {
+    #[ipld(rename = "type")]
+    pub op_type: String,
+    pub signingKey: String,
+    pub service: String,
+    pub prev: Option<String>,
+    pub sig: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, DagCbor, PartialEq, Eq, Clone)]
+struct UnsignedUpdateOp {
+    #[ipld(rename = "type")]
+    pub op_type: String,
+    pub signingKey: String,
+    pub service: String,
+    pub prev: Option<String>,
+}
+
+impl UpdateOp {
+    pub fn new(signing_key: String, service: String, prev: Option<Cid>, keypair: &KeyPair) -> Self {
+        let unsigned = UnsignedUpdateOp {
+            op_type: "update".to_string(),
+            signingKey: signing_key,
+            service,
+            prev: prev.map(|v| v.to_string()),
+        };
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR");
+        let sig = keypair.sign_bytes(block.data());
+        UpdateOp {
+            op_type: unsigned.op_type,
+            signingKey: unsigned.signingKey,
+            service: unsigned.service,
+            prev: unsigned.prev,
+            sig,
+        }
+    }
+
+    /// Checks the signature against the (current) signing key of the identity
+    pub fn verify(&self, key: &PubKey) -> Result<()> {
+        let unsigned = UnsignedUpdateOp {
+            op_type: self.op_type.clone(),
+            signingKey: self.signingKey.clone(),
+            service: self.service.clone(),
+            prev: self.prev.clone(),
+        };
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR");
+        key.verify_bytes(block.data(), &self.sig)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": self.op_type,
+            "signingKey": self.signingKey,
+            "service": self.service,
+            "prev": self.prev,
+            "sig": self.sig,
+        })
+    }
+
+    pub fn from_json(val: &serde_json::Value) -> Result<Self> {
+        let field = |name: &str| -> Result<String> {
+            val[name].as_str().map(|v| v.to_string()).ok_or(anyhow!(
+                "missing or invalid '{}' field in PLC operation",
+                name
+            ))
+        };
+        let op_type = field("type")?;
+        if op_type != "update" {
+            return Err(anyhow!("unexpected PLC operation type: {}", op_type));
+        }
+        let prev = match val["prev"].as_str() {
+            Some(v) => Some(Cid::from_str(v)?.to_string()),
+            None => None,
+        };
+        Ok(UpdateOp {
+            op_type,
+            signingKey: field("signingKey")?,
+            service: field("service")?,
+            prev,
+            sig: field("sig")?,
+        })
+    }
+
+    /// Returns a copy of a DID document with the signing key and PDS service endpoint updated
+    pub fn apply(&self, did_doc: &serde_json::Value) -> serde_json::Value {
+        let mut doc = did_doc.clone();
+        if let Some(methods) = doc["verificationMethod"].as_array_mut() {
+            for method in methods.iter_mut() {
+                if method["id"].as_str().unwrap_or("").contains("#signingKey") {
+                    method["publicKeyMultibase"] = json!(self.signingKey);
+                }
+            }
+        }
+        if let Some(services) = doc["service"].as_array_mut() {
+            for service in services.iter_mut() {
+                if service["type"] == "AtpPersonalDataServer" {
+                    service["serviceEndpoint"] = json!(self.service);
+                }
+            }
+        }
+        doc
+    }
+}
+
+/// Signed operation which changes the handle (username) of a did:plc identity, which shows up as
+/// `alsoKnownAs` in the DID document.
+///
+/// As with `TombstoneOp`, `prev` must be the CID of the most recent operation for the DID.
+#[derive(Debug, DagCbor, PartialEq, Eq, Clone)]
+pub struct UpdateHandleOp {
+    #[ipld(rename = "type")]
+    pub op_type: String,
+    pub handle: String,
+    pub prev: Option<String>,
+    pub sig: String,
+}
+
+#[derive(Debug, DagCbor, PartialEq, Eq, Clone)]
+struct UnsignedUpdateHandleOp {
+    #[ipld(rename = "type")]
+    pub op_type: String,
+    pub handle: String,
+    pub prev: Option<String>,
+}
+
+impl UpdateHandleOp {
+    pub fn new(handle: String, prev: Option<Cid>, keypair: &KeyPair) -> Self {
+        let unsigned = UnsignedUpdateHandleOp {
+            op_type: "update_handle".to_string(),
+            handle,
+            prev: prev.map(|v| v.to_string()),
+        };
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR");
+        let sig = keypair.sign_bytes(block.data());
+        UpdateHandleOp {
+            op_type: unsigned.op_type,
+            handle: unsigned.handle,
+            prev: unsigned.prev,
+            sig,
+        }
+    }
+
+    /// Checks the signature against the (current) signing key of the identity
+    pub fn verify(&self, key: &PubKey) -> Result<()> {
+        let unsigned = UnsignedUpdateHandleOp {
+            op_type: self.op_type.clone(),
+            handle: self.handle.clone(),
+            prev: self.prev.clone(),
+        };
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR");
+        key.verify_bytes(block.data(), &self.sig)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": self.op_type,
+            "handle": self.handle,
+            "prev": self.prev,
+            "sig": self.sig,
+        })
+    }
+
+    /// Returns a copy of a DID document with `alsoKnownAs` pointing to the new handle
+    pub fn apply(&self, did_doc: &serde_json::Value) -> serde_json::Value {
+        let mut doc = did_doc.clone();
+        doc["alsoKnownAs"] = json!([format!("https://{}", self.handle)]);
+        doc
+    }
+}
+
+/// Computes the CID of a signed operation (in JSON form, as returned in a PLC directory operation
+/// log), for use as the `prev` of a subsequent operation
+pub fn op_cid(op: &serde_json::Value) -> Result<Cid> {
+    let block = Block::<DefaultParams>::encode(
+        DagCborCodec,
+        Code::Sha2_256,
+        &json_value_into_ipld(op.clone()),
+    )?;
+    Ok(*block.cid())
+}
+
+/// Extracts a verification key ("signingKey" or "recoveryKey"), as a did:key string, from a DID
+/// document
+pub fn did_doc_key(did_doc: &serde_json::Value, key_name: &str) -> Option<String> {
+    did_doc["verificationMethod"]
+        .as_array()?
+        .iter()
+        .find(|m| {
+            m["id"]
+                .as_str()
+                .unwrap_or("")
+                .contains(&format!("#{key_name}"))
+        })
+        .and_then(|m| m["publicKeyMultibase"].as_str())
+        .map(|v| v.to_string())
+}
+
+/// Extracts the PDS service endpoint URL from a DID document
+pub fn did_doc_pds_url(did_doc: &serde_json::Value) -> Option<String> {
+    did_doc["service"]
+        .as_array()?
+        .iter()
+        .find(|s| s["type"] == "AtpPersonalDataServer")
+        .and_then(|s| s["serviceEndpoint"].as_str())
+        .map(|v| v.to_string())
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct DidDocMeta {
     pub did: Did,
@@ -366,3 +640,159 @@ fn test_known_key() {
     println!("{op:?}");
     op.verify_self().unwrap();
 }
+
+#[test]
+fn test_tombstone_op() {
+    let keypair = KeyPair::new_random();
+    let op = TombstoneOp::new(None, &keypair);
+    assert_eq!(op.op_type, "plc_tombstone");
+    op.verify(&keypair.pubkey()).unwrap();
+    let other = KeyPair::new_random();
+    assert!(op.verify(&other.pubkey()).is_err());
+}
+
+#[test]
+fn test_update_op() {
+    let old_keypair = KeyPair::new_random();
+    let new_keypair = KeyPair::new_random();
+    let create_op = CreateOp::new(
+        "dummy-handle".to_string(),
+        "https://old.service".to_string(),
+        &old_keypair,
+        None,
+    );
+    let did_doc = create_op.did_doc();
+    assert_eq!(
+        did_doc_key(&did_doc, "signingKey"),
+        Some(old_keypair.pubkey().to_did_key())
+    );
+
+    let op = UpdateOp::new(
+        new_keypair.pubkey().to_did_key(),
+        "https://new.service".to_string(),
+        None,
+        &old_keypair,
+    );
+    op.verify(&old_keypair.pubkey()).unwrap();
+    assert!(op.verify(&new_keypair.pubkey()).is_err());
+    assert_eq!(UpdateOp::from_json(&op.to_json()).unwrap(), op);
+
+    let updated = op.apply(&did_doc);
+    assert_eq!(
+        did_doc_key(&updated, "signingKey"),
+        Some(new_keypair.pubkey().to_did_key())
+    );
+    assert_eq!(
+        did_doc_key(&updated, "recoveryKey"),
+        Some(old_keypair.pubkey().to_did_key())
+    );
+    assert_eq!(
+        did_doc_pds_url(&updated),
+        Some("https://new.service".to_string())
+    );
+}
+
+#[test]
+fn test_op_cid() {
+    let keypair = KeyPair::new_random();
+    let create_op = CreateOp::new(
+        "alice.test".to_string(),
+        "https://pds.test".to_string(),
+        &keypair,
+        None,
+    );
+    let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &create_op)
+        .expect("encode DAG-CBOR");
+    let op_json = json!({
+        "type": create_op.op_type,
+        "signingKey": create_op.signingKey,
+        "recoveryKey": create_op.recoveryKey,
+        "username": create_op.username,
+        "service": create_op.service,
+        "prev": null,
+        "sig": create_op.sig,
+    });
+    assert_eq!(op_cid(&op_json).unwrap(), *block.cid());
+
+    let tombstone = TombstoneOp::new(Some(*block.cid()), &keypair);
+    tombstone.verify(&keypair.pubkey()).unwrap();
+    assert_eq!(tombstone.to_json()["prev"], json!(block.cid().to_string()));
+}
+
+#[test]
+fn test_op_with_prev() {
+    let keypair = KeyPair::new_random();
+    let create_op = CreateOp::new(
+        "alice.test".to_string(),
+        "https://pds.test".to_string(),
+        &keypair,
+        None,
+    );
+    let prev = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &create_op)
+        .expect("encode DAG-CBOR");
+    let prev = *prev.cid();
+
+    let op = UpdateOp::new(
+        keypair.pubkey().to_did_key(),
+        "https://new.service".to_string(),
+        Some(prev),
+        &keypair,
+    );
+    let op_json = op.to_json();
+    assert_eq!(op_json["prev"], json!(prev.to_string()));
+    let parsed = UpdateOp::from_json(&op_json).unwrap();
+    assert_eq!(parsed, op);
+    parsed.verify(&keypair.pubkey()).unwrap();
+
+    // the signature covers the same fields, in the same representation, as the JSON form
+    let mut unsigned_json = op_json.clone();
+    unsigned_json.as_object_mut().unwrap().remove("sig");
+    let unsigned = UnsignedUpdateOp {
+        op_type: op.op_type.clone(),
+        signingKey: op.signingKey.clone(),
+        service: op.service.clone(),
+        prev: op.prev.clone(),
+    };
+    assert_eq!(
+        op_cid(&unsigned_json).unwrap(),
+        *Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR")
+            .cid()
+    );
+
+    let tombstone = TombstoneOp::new(Some(op_cid(&op_json).unwrap()), &keypair);
+    tombstone.verify(&keypair.pubkey()).unwrap();
+    let mut unsigned_json = tombstone.to_json();
+    unsigned_json.as_object_mut().unwrap().remove("sig");
+    let unsigned = UnsignedTombstoneOp {
+        op_type: tombstone.op_type.clone(),
+        prev: tombstone.prev.clone(),
+    };
+    assert_eq!(
+        op_cid(&unsigned_json).unwrap(),
+        *Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &unsigned)
+            .expect("encode DAG-CBOR")
+            .cid()
+    );
+}
+
+#[test]
+fn test_update_handle_op() {
+    let keypair = KeyPair::new_random();
+    let create_op = CreateOp::new(
+        "alice.test".to_string(),
+        "https://pds.test".to_string(),
+        &keypair,
+        None,
+    );
+    let prev = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &create_op)
+        .expect("encode DAG-CBOR");
+    let op = UpdateHandleOp::new("alice.example.com".to_string(), Some(*prev.cid()), &keypair);
+    assert_eq!(op.op_type, "update_handle");
+    op.verify(&keypair.pubkey()).unwrap();
+    assert!(op.verify(&KeyPair::new_random().pubkey()).is_err());
+    assert_eq!(op.to_json()["prev"], json!(prev.cid().to_string()));
+
+    let updated = op.apply(&create_op.did_doc());
+    assert_eq!(updated["alsoKnownAs"], json!(["https://alice.example.com"]));
+}
@@ -1,9 +1,12 @@
 use crate::car::{
     load_car_bytes_to_blockstore, load_car_path_to_blockstore, read_car_bytes_from_blockstore,
+    read_car_bytes_from_cids,
 };
-use crate::crypto::KeyPair;
+use crate::crypto::{KeyPair, PubKey};
 use crate::identifiers::{Did, Nsid, Tid};
-use crate::mst::{collect_mst_keys, generate_mst, CommitNode, MetadataNode, RootNode};
+use crate::mst::{
+    collect_mst_cids, collect_mst_keys, generate_mst, CommitNode, MetadataNode, RootNode,
+};
 use anyhow::{anyhow, ensure, Context, Result};
 use ipfs_sqlite_block_store::BlockStore;
 use libipld::cbor::DagCborCodec;
@@ -11,6 +14,7 @@ use libipld::multihash::Code;
 use libipld::prelude::Codec;
 use libipld::store::DefaultParams;
 use libipld::{Block, Cid, Ipld};
+use log::warn;
 use serde_json::{json, Value};
 use std::borrow::Cow;
 use std::collections::BTreeMap;
@@ -18,6 +22,9 @@ use std::collections::HashSet;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+/// Prefix of the blockstore aliases which pin uploaded blobs (repository aliases are bare DIDs)
+pub const BLOB_ALIAS_PREFIX: &str = "blob:";
+
 #[derive(Debug, serde::Serialize)]
 pub struct RepoCommit {
     pub sig: Box<[u8]>,
@@ -54,6 +61,57 @@ pub enum Mutation {
     Create(Nsid, Tid, Ipld),
     Update(Nsid, Tid, Ipld),
     Delete(Nsid, Tid),
+    /// Like `Create`, for records with keys which are not TIDs (eg, "app.bsky.actor.profile/self").
+    /// Local writes always use TIDs for new records, so these only come from repo diffs.
+    CreateKeyed(Nsid, String, Ipld),
+    /// Like `Update`, for records with keys which are not TIDs
+    UpdateKeyed(Nsid, String, Ipld),
+    /// Like `Delete`, for records with keys which are not TIDs
+    DeleteKeyed(Nsid, String),
+}
+
+impl Mutation {
+    /// The CID of the record block this mutation writes (`None` for deletions), computed without
+    /// touching the blockstore.
+    pub fn record_cid(&self) -> Result<Option<Cid>> {
+        match self {
+            Mutation::Create(_, _, val)
+            | Mutation::Update(_, _, val)
+            | Mutation::CreateKeyed(_, _, val)
+            | Mutation::UpdateKeyed(_, _, val) => {
+                let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, val)?;
+                Ok(Some(*block.cid()))
+            }
+            Mutation::Delete(_, _) | Mutation::DeleteKeyed(_, _) => Ok(None),
+        }
+    }
+
+    /// The "create", "update" or "delete" action name of this mutation, as used in repo events
+    pub fn action(&self) -> &'static str {
+        match self {
+            Mutation::Create(..) | Mutation::CreateKeyed(..) => "create",
+            Mutation::Update(..) | Mutation::UpdateKeyed(..) => "update",
+            Mutation::Delete(..) | Mutation::DeleteKeyed(..) => "delete",
+        }
+    }
+
+    /// The collection and record key of the record this mutation applies to
+    pub fn record_key(&self) -> (&Nsid, String) {
+        match self {
+            Mutation::Create(collection, tid, _)
+            | Mutation::Update(collection, tid, _)
+            | Mutation::Delete(collection, tid) => (collection, tid.to_string()),
+            Mutation::CreateKeyed(collection, rkey, _)
+            | Mutation::UpdateKeyed(collection, rkey, _)
+            | Mutation::DeleteKeyed(collection, rkey) => (collection, rkey.clone()),
+        }
+    }
+
+    /// The MST key ("<collection>/<rkey>") of the record this mutation applies to
+    pub fn mst_key(&self) -> String {
+        let (collection, rkey) = self.record_key();
+        format!("{collection}/{rkey}")
+    }
 }
 
 impl RepoStore {
@@ -98,13 +156,17 @@ impl RepoStore {
         Ok(cid)
     }
 
-    /// Returns CID that was inserted
+    /// Returns CID that was inserted. The blob gets its own alias (see `BLOB_ALIAS_PREFIX`), so it
+    /// is kept by blockstore garbage collection even if no record references it.
     pub fn put_blob(&mut self, data: &[u8]) -> Result<Cid> {
         let block = Block::<DefaultParams>::encode(libipld::raw::RawCodec, Code::Sha2_256, data)?;
         let cid = *block.cid();
         self.db
             .put_block(block, None)
             .context("writing non-record blob to blockstore")?;
+        self.db
+            .alias(format!("{BLOB_ALIAS_PREFIX}{cid}").into_bytes(), Some(&cid))
+            .context("aliasing non-record blob in blockstore")?;
         Ok(cid)
     }
 
@@ -113,6 +175,20 @@ impl RepoStore {
         Ok(self.db.resolve(Cow::from(did.as_bytes()))?)
     }
 
+    /// Points a repository's alias (the DID) at a commit, eg after importing a repository from
+    /// elsewhere.
+    pub fn link_repo(&mut self, did: &Did, commit_cid: &Cid) -> Result<()> {
+        self.db.alias(did.as_bytes().to_vec(), Some(commit_cid))?;
+        Ok(())
+    }
+
+    /// Removes the alias pointing at a repository's latest commit. The blocks themselves are left
+    /// in place, but are no longer pinned, so blockstore garbage collection will remove them.
+    pub fn unlink_repo(&mut self, did: &Did) -> Result<()> {
+        self.db.alias(did.as_bytes().to_vec(), None)?;
+        Ok(())
+    }
+
     pub fn get_commit(&mut self, commit_cid: &Cid) -> Result<RepoCommit> {
         // read records by CID: commit, root, meta
         let commit_node: CommitNode = DagCborCodec
@@ -177,9 +253,9 @@ impl RepoStore {
         };
         let map = self.mst_to_map(&commit.mst_cid)?;
         let mut collections: HashSet<String> = Default::default();
-        // XXX: confirm that keys actually start with leading slash
+        // keys are "<collection>/<rkey>", with no leading slash
         for k in map.keys() {
-            let coll = k.split('/').nth(1).unwrap();
+            let coll = k.split('/').next().unwrap();
             collections.insert(coll.to_string());
         }
         Ok(collections.into_iter().collect())
@@ -200,6 +276,22 @@ impl RepoStore {
         self.get_mst_record_by_key(&commit.mst_cid, &record_key)
     }
 
+    /// Like `get_atp_record()`, but returns the CID of the record instead of the record itself
+    pub fn get_atp_record_cid(
+        &mut self,
+        did: &Did,
+        collection: &Nsid,
+        tid: &Tid,
+    ) -> Result<Option<Cid>> {
+        let commit = if let Some(c) = self.lookup_commit(did)? {
+            self.get_commit(&c)?
+        } else {
+            return Ok(None);
+        };
+        let record_key = format!("{collection}/{tid}");
+        Ok(self.mst_to_map(&commit.mst_cid)?.get(&record_key).copied())
+    }
+
     pub fn write_metadata(&mut self, did: &Did) -> Result<Cid> {
         self.put_ipld(&MetadataNode {
             datastore: "mst".to_string(),
@@ -242,16 +334,15 @@ impl RepoStore {
         let mut cid_map = self.mst_to_map(mst_cid)?;
         for m in mutations.iter() {
             match m {
-                Mutation::Create(collection, tid, val) => {
-                    let cid = self.put_ipld(val)?;
-                    cid_map.insert(format!("{collection}/{tid}"), cid);
-                }
-                Mutation::Update(collection, tid, val) => {
+                Mutation::Create(_, _, val)
+                | Mutation::Update(_, _, val)
+                | Mutation::CreateKeyed(_, _, val)
+                | Mutation::UpdateKeyed(_, _, val) => {
                     let cid = self.put_ipld(val)?;
-                    cid_map.insert(format!("{collection}/{tid}"), cid);
+                    cid_map.insert(m.mst_key(), cid);
                 }
-                Mutation::Delete(collection, tid) => {
-                    cid_map.remove(&format!("{collection}/{tid}"));
+                Mutation::Delete(_, _) | Mutation::DeleteKeyed(_, _) => {
+                    cid_map.remove(&m.mst_key());
                 }
             }
         }
@@ -259,6 +350,61 @@ impl RepoStore {
         Ok(mst_cid)
     }
 
+    /// Compares two MST trees (eg, from an older and a newer commit of the same repo), and returns
+    /// the record-level changes between them as mutations. If `old_mst_cid` is None, every record
+    /// in the new tree is returned as a creation.
+    ///
+    /// Records with keys which are not TIDs are returned as the "keyed" mutation variants. MST keys
+    /// which are not in the "<collection>/<rkey>" form at all are skipped.
+    pub fn mst_diff(
+        &mut self,
+        old_mst_cid: Option<&Cid>,
+        new_mst_cid: &Cid,
+    ) -> Result<Vec<Mutation>> {
+        let old_map = match old_mst_cid {
+            Some(cid) => self.mst_to_map(cid)?,
+            None => Default::default(),
+        };
+        let new_map = self.mst_to_map(new_mst_cid)?;
+        let parse_key = |mst_key: &str| -> Option<(Nsid, String)> {
+            let (collection, rkey) = mst_key.split_once('/')?;
+            match Nsid::from_str(collection) {
+                Ok(collection) if !rkey.is_empty() => Some((collection, rkey.to_string())),
+                _ => {
+                    warn!("skipping unexpected MST key: {}", mst_key);
+                    None
+                }
+            }
+        };
+        let mut mutations: Vec<Mutation> = vec![];
+        for (mst_key, cid) in new_map.iter() {
+            let old_cid = old_map.get(mst_key);
+            if old_cid == Some(cid) {
+                continue;
+            }
+            if let Some((collection, rkey)) = parse_key(mst_key) {
+                let val = self.get_ipld(cid)?;
+                mutations.push(match (old_cid, Tid::from_str(&rkey)) {
+                    (None, Ok(tid)) => Mutation::Create(collection, tid, val),
+                    (Some(_), Ok(tid)) => Mutation::Update(collection, tid, val),
+                    (None, Err(_)) => Mutation::CreateKeyed(collection, rkey, val),
+                    (Some(_), Err(_)) => Mutation::UpdateKeyed(collection, rkey, val),
+                });
+            }
+        }
+        for mst_key in old_map.keys() {
+            if !new_map.contains_key(mst_key) {
+                if let Some((collection, rkey)) = parse_key(mst_key) {
+                    mutations.push(match Tid::from_str(&rkey) {
+                        Ok(tid) => Mutation::Delete(collection, tid),
+                        Err(_) => Mutation::DeleteKeyed(collection, rkey),
+                    });
+                }
+            }
+        }
+        Ok(mutations)
+    }
+
     /// High-level helper to write a batch of mutations to the repo corresponding to the DID, and
     /// signing the resulting new root CID with the given keypair.
     pub fn mutate_repo(
@@ -313,6 +459,34 @@ impl RepoStore {
         Ok(())
     }
 
+    /// Checks the signature on a commit against the given public key (eg, the "signingKey" from the
+    /// DID document of the repository owner).
+    ///
+    /// Commits are signed the same way as in `mutate_repo()`.
+    pub fn verify_commit_sig(&mut self, commit_cid: &Cid, signing_key: &PubKey) -> Result<()> {
+        let commit = self.get_commit(commit_cid)?;
+        let sig = std::str::from_utf8(&commit.sig).context("commit signature encoding")?;
+        signing_key
+            .verify_bytes(commit.root_cid.to_string().as_bytes(), sig)
+            .context("commit signature did not verify")
+    }
+
+    /// Checks whether `ancestor_cid` is the same as, or an earlier commit of, `commit_cid`, by
+    /// walking back through `prev` links. Fails if a commit along the way is missing from the
+    /// blockstore.
+    pub fn commit_descends_from(&mut self, commit_cid: &Cid, ancestor_cid: &Cid) -> Result<bool> {
+        let mut cursor = *commit_cid;
+        loop {
+            if &cursor == ancestor_cid {
+                return Ok(true);
+            }
+            match self.get_commit(&cursor)?.prev {
+                Some(prev) => cursor = prev,
+                None => return Ok(false),
+            }
+        }
+    }
+
     /// Import blocks from a CAR file in memory, optionally setting an alias pointing to the input
     /// (eg, a DID identifier).
     ///
@@ -342,14 +516,61 @@ impl RepoStore {
 
     /// Exports in CAR format to a Writer
     ///
-    /// The "from" commit CID feature is not implemented.
+    /// If a "from" commit CID is provided, only blocks which are not also part of that commit
+    /// (eg, the new blocks in a later commit) are included.
     pub fn export_car(
         &mut self,
         commit_cid: &Cid,
-        _from_commit_cid: Option<&Cid>,
+        from_commit_cid: Option<&Cid>,
     ) -> Result<Vec<u8>> {
-        // TODO: from_commit_cid
-        read_car_bytes_from_blockstore(&mut self.db, commit_cid)
+        read_car_bytes_from_blockstore(&mut self.db, commit_cid, from_commit_cid)
+    }
+
+    /// Exports a CAR file of only the blocks which are new in `commit_cid` compared to an earlier
+    /// commit `prev_cid` of the same repo: the commit and root blocks back to `prev_cid`, and the
+    /// MST nodes and records which are not in the earlier tree.
+    ///
+    /// This only walks MST nodes, so it is much cheaper than `export_car()` with a
+    /// `from_commit_cid`, which walks every block of both commits. If `commit_cid` is several
+    /// commits ahead, the trees of the intermediate commits are not included.
+    pub fn export_commit_diff_car(&mut self, commit_cid: &Cid, prev_cid: &Cid) -> Result<Vec<u8>> {
+        let prev_commit = self.get_commit(prev_cid)?;
+        let mut cids: Vec<Cid> = vec![];
+        let mut cursor = *commit_cid;
+        let mut mst_cid = None;
+        while &cursor != prev_cid {
+            let commit = self.get_commit(&cursor)?;
+            cids.push(commit.commit_cid);
+            cids.push(commit.root_cid);
+            if commit.meta_cid != prev_commit.meta_cid && !cids.contains(&commit.meta_cid) {
+                cids.push(commit.meta_cid);
+            }
+            mst_cid.get_or_insert(commit.mst_cid);
+            cursor = commit
+                .prev
+                .ok_or(anyhow!("commit does not descend from {}", prev_cid))?;
+        }
+        if let Some(mst_cid) = mst_cid {
+            let (mut old_nodes, mut old_vals) = (HashSet::new(), HashSet::new());
+            collect_mst_cids(
+                &mut self.db,
+                &prev_commit.mst_cid,
+                &HashSet::new(),
+                &mut old_nodes,
+                &mut old_vals,
+            )?;
+            let (mut new_nodes, mut new_vals) = (HashSet::new(), HashSet::new());
+            collect_mst_cids(
+                &mut self.db,
+                &mst_cid,
+                &old_nodes,
+                &mut new_nodes,
+                &mut new_vals,
+            )?;
+            cids.extend(new_nodes);
+            cids.extend(new_vals.difference(&old_vals));
+        }
+        read_car_bytes_from_cids(&mut self.db, commit_cid, &cids)
     }
 }
 
@@ -364,6 +585,9 @@ fn test_repo_mst() {
     let blob = b"beware the swamp thing";
     let blob_cid = repo.put_blob(blob).unwrap();
 
+    // blobs are not garbage collected
+    repo.db.gc().unwrap();
+
     let record = ipld!({"some-thing": 123});
     let record_cid = repo.put_ipld(&record).unwrap();
 
@@ -450,3 +674,189 @@ fn test_repo_mst() {
     assert_eq!(commit.mst_cid, simple3_map_cid);
     assert_eq!(Some(simple3_commit_cid), repo.lookup_commit(&did).unwrap());
 }
+
+#[test]
+fn test_verify_commit_sig() {
+    use libipld::ipld;
+
+    let mut repo = RepoStore::open_ephemeral().unwrap();
+    let did = Did::from_str("did:plc:dummy").unwrap();
+    let keypair = KeyPair::new_random();
+    let other_keypair = KeyPair::new_random();
+
+    let meta_cid = repo.write_metadata(&did).unwrap();
+    let map_cid = repo.mst_from_map(&Default::default()).unwrap();
+    let root_cid = repo.write_root(meta_cid, None, map_cid).unwrap();
+    let unsigned_commit_cid = repo.write_commit(&did, root_cid, "dummy-sig").unwrap();
+    assert!(repo
+        .verify_commit_sig(&unsigned_commit_cid, &keypair.pubkey())
+        .is_err());
+
+    let mutations = vec![Mutation::Create(
+        Nsid::from_str("test.records").unwrap(),
+        Tid::from_str("44444444444444").unwrap(),
+        ipld!({"some-thing": 123}),
+    )];
+    let commit_cid = repo.mutate_repo(&did, &mutations, &keypair).unwrap();
+    repo.verify_commit_sig(&commit_cid, &keypair.pubkey())
+        .unwrap();
+    assert!(repo
+        .verify_commit_sig(&commit_cid, &other_keypair.pubkey())
+        .is_err());
+}
+
+#[test]
+fn test_mst_diff() {
+    use libipld::ipld;
+
+    let mut repo = RepoStore::open_ephemeral().unwrap();
+    let did = Did::from_str("did:plc:dummy").unwrap();
+    let keypair = KeyPair::new_random();
+    let meta_cid = repo.write_metadata(&did).unwrap();
+    let map_cid = repo.mst_from_map(&Default::default()).unwrap();
+    let root_cid = repo.write_root(meta_cid, None, map_cid).unwrap();
+    repo.write_commit(&did, root_cid, "dummy-sig").unwrap();
+
+    let collection = Nsid::from_str("test.records").unwrap();
+    let tid_a = Tid::from_str("22222222222222").unwrap();
+    let tid_b = Tid::from_str("33333333333333").unwrap();
+    let tid_c = Tid::from_str("44444444444444").unwrap();
+    let old_commit_cid = repo
+        .mutate_repo(
+            &did,
+            &[
+                Mutation::Create(collection.clone(), tid_a.clone(), ipld!({"a": 1})),
+                Mutation::Create(collection.clone(), tid_b.clone(), ipld!({"b": 1})),
+            ],
+            &keypair,
+        )
+        .unwrap();
+    let new_commit_cid = repo
+        .mutate_repo(
+            &did,
+            &[
+                Mutation::Update(collection.clone(), tid_a.clone(), ipld!({"a": 2})),
+                Mutation::Delete(collection.clone(), tid_b.clone()),
+                Mutation::Create(collection.clone(), tid_c.clone(), ipld!({"c": 1})),
+            ],
+            &keypair,
+        )
+        .unwrap();
+    let old_mst_cid = repo.get_commit(&old_commit_cid).unwrap().mst_cid;
+    let new_mst_cid = repo.get_commit(&new_commit_cid).unwrap().mst_cid;
+
+    assert!(repo
+        .mst_diff(Some(&new_mst_cid), &new_mst_cid)
+        .unwrap()
+        .is_empty());
+    assert_eq!(repo.mst_diff(None, &new_mst_cid).unwrap().len(), 2);
+
+    assert!(repo
+        .commit_descends_from(&new_commit_cid, &old_commit_cid)
+        .unwrap());
+    assert!(!repo
+        .commit_descends_from(&old_commit_cid, &new_commit_cid)
+        .unwrap());
+
+    let diff = repo.mst_diff(Some(&old_mst_cid), &new_mst_cid).unwrap();
+    assert_eq!(diff.len(), 3);
+    assert!(
+        matches!(&diff[0], Mutation::Update(_, tid, val) if tid == &tid_a && val == &ipld!({"a": 2}))
+    );
+    assert!(matches!(&diff[1], Mutation::Create(_, tid, _) if tid == &tid_c));
+    assert!(matches!(&diff[2], Mutation::Delete(_, tid) if tid == &tid_b));
+
+    // records with keys which are not TIDs
+    let keyed_commit_cid = repo
+        .mutate_repo(
+            &did,
+            &[Mutation::UpdateKeyed(
+                collection.clone(),
+                "self".to_string(),
+                ipld!({"self": 1}),
+            )],
+            &keypair,
+        )
+        .unwrap();
+    let keyed_mst_cid = repo.get_commit(&keyed_commit_cid).unwrap().mst_cid;
+    let diff = repo.mst_diff(Some(&new_mst_cid), &keyed_mst_cid).unwrap();
+    assert_eq!(diff.len(), 1);
+    assert!(matches!(&diff[0], Mutation::CreateKeyed(_, rkey, _) if rkey == "self"));
+    assert_eq!(diff[0].mst_key(), "test.records/self");
+    let diff = repo.mst_diff(Some(&keyed_mst_cid), &new_mst_cid).unwrap();
+    assert!(matches!(&diff[0], Mutation::DeleteKeyed(_, rkey) if rkey == "self"));
+}
+
+#[test]
+fn test_export_commit_diff_car() {
+    use libipld::ipld;
+
+    let mut repo = RepoStore::open_ephemeral().unwrap();
+    let did = Did::from_str("did:plc:dummy").unwrap();
+    let keypair = KeyPair::new_random();
+    let meta_cid = repo.write_metadata(&did).unwrap();
+    let map_cid = repo.mst_from_map(&Default::default()).unwrap();
+    let root_cid = repo.write_root(meta_cid, None, map_cid).unwrap();
+    let first_commit_cid = repo.write_commit(&did, root_cid, "dummy-sig").unwrap();
+    repo.link_repo(&did, &first_commit_cid).unwrap();
+
+    let collection = Nsid::from_str("test.records").unwrap();
+    let old_commit_cid = repo
+        .mutate_repo(
+            &did,
+            &(0..50)
+                .map(|i| {
+                    Mutation::Create(
+                        collection.clone(),
+                        Tid::from_str(&format!("2222222222{i:04}")).unwrap(),
+                        ipld!({ "i": i }),
+                    )
+                })
+                .collect::<Vec<Mutation>>(),
+            &keypair,
+        )
+        .unwrap();
+    let mutations = vec![
+        Mutation::Update(
+            collection.clone(),
+            Tid::from_str("22222222220007").unwrap(),
+            ipld!({"i": "updated"}),
+        ),
+        Mutation::Delete(collection.clone(), Tid::from_str("22222222220008").unwrap()),
+        Mutation::Create(
+            collection.clone(),
+            Tid::from_str("33333333333333").unwrap(),
+            ipld!({"new": true}),
+        ),
+    ];
+    let new_commit_cid = repo.mutate_repo(&did, &mutations, &keypair).unwrap();
+    let new_mst_cid = repo.get_commit(&new_commit_cid).unwrap().mst_cid;
+
+    let record_cid = mutations[2].record_cid().unwrap().unwrap();
+    assert_eq!(
+        repo.mst_to_map(&new_mst_cid).unwrap()["test.records/33333333333333"],
+        record_cid
+    );
+    assert!(mutations[1].record_cid().unwrap().is_none());
+
+    // the diff is smaller than the full repo, but (along with the earlier commit) complete
+    let full_car = repo.export_car(&new_commit_cid, None).unwrap();
+    for base_cid in [old_commit_cid, first_commit_cid] {
+        let diff_car = repo
+            .export_commit_diff_car(&new_commit_cid, &base_cid)
+            .unwrap();
+        assert!(diff_car.len() < full_car.len());
+        let mut other = RepoStore::open_ephemeral().unwrap();
+        other
+            .import_car_bytes(&repo.export_car(&base_cid, None).unwrap(), None)
+            .unwrap();
+        assert_eq!(
+            other.import_car_bytes(&diff_car, None).unwrap(),
+            new_commit_cid
+        );
+        assert_eq!(other.mst_to_map(&new_mst_cid).unwrap().len(), 50);
+    }
+    assert!(repo
+        .export_commit_diff_car(&old_commit_cid, &new_commit_cid)
+        .is_err());
+}
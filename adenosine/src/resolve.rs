@@ -0,0 +1,114 @@
+/// Network helpers for resolving handles and DIDs, and fetching content from other PDS instances.
+///
+/// These all make blocking HTTP requests, so callers in server contexts should be careful not to
+/// hold locks while calling them.
+use crate::identifiers::Did;
+use anyhow::{anyhow, Result};
+use libipld::Cid;
+use std::str::FromStr;
+use std::time::Duration;
+
+static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
+
+fn http_client() -> Result<reqwest::blocking::Client> {
+    Ok(reqwest::blocking::Client::builder()
+        .user_agent(APP_USER_AGENT)
+        .timeout(Duration::from_secs(10))
+        .build()?)
+}
+
+/// Resolves a handle to a DID using the HTTPS "well-known" method: fetches
+/// `https://<handle>/.well-known/atproto-did`, which should return the DID as plain text.
+pub fn resolve_handle_well_known(handle: &str) -> Result<Did> {
+    let res = http_client()?
+        .get(format!("https://{handle}/.well-known/atproto-did"))
+        .send()?
+        .error_for_status()?;
+    let body = res.text()?;
+    Did::from_str(body.trim())
+        .map_err(|_| anyhow!("unexpected atproto-did response for handle: {}", handle))
+}
+
+/// Fetches the DID document for a did:web identifier, from `https://<domain>/.well-known/did.json`.
+///
+/// Only bare domain did:web identifiers (no ports or paths) are supported.
+pub fn resolve_did_web(did: &Did) -> Result<serde_json::Value> {
+    let domain = did
+        .strip_prefix("did:web:")
+        .ok_or(anyhow!("not a did:web identifier: {}", did))?;
+    let res = http_client()?
+        .get(format!("https://{domain}/.well-known/did.json"))
+        .send()?
+        .error_for_status()?;
+    let did_doc: serde_json::Value = res.json()?;
+    if did_doc["id"] != did.to_string() {
+        return Err(anyhow!("DID document 'id' does not match: {}", did));
+    }
+    Ok(did_doc)
+}
+
+/// Fetches the DID document for a did:plc identifier from a PLC directory server (eg,
+/// "https://plc.directory").
+pub fn resolve_did_plc(plc_host: &str, did: &Did) -> Result<serde_json::Value> {
+    let res = http_client()?
+        .get(format!("{plc_host}/{did}"))
+        .send()?
+        .error_for_status()?;
+    let did_doc: serde_json::Value = res.json()?;
+    if did_doc["id"] != did.to_string() {
+        return Err(anyhow!("DID document 'id' does not match: {}", did));
+    }
+    Ok(did_doc)
+}
+
+/// Fetches the operation log (oldest first) for a did:plc identifier from a PLC directory server.
+/// Returns `None` if the directory has no record of the DID.
+pub fn fetch_plc_log(plc_host: &str, did: &Did) -> Result<Option<Vec<serde_json::Value>>> {
+    let res = http_client()?.get(format!("{plc_host}/{did}/log")).send()?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(res.error_for_status()?.json()?))
+}
+
+/// Submits a signed operation (eg, an update or tombstone) for a did:plc identifier to a PLC
+/// directory server
+pub fn submit_plc_op(plc_host: &str, did: &Did, op: &serde_json::Value) -> Result<()> {
+    http_client()?
+        .post(format!("{plc_host}/{did}"))
+        .json(op)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Fetches the current commit CID of a repository from a PDS, using `com.atproto.sync.getRoot`
+pub fn fetch_repo_root(pds_url: &str, did: &Did) -> Result<Cid> {
+    let res = http_client()?
+        .get(format!("{pds_url}/xrpc/com.atproto.sync.getRoot"))
+        .query(&[("did", did.to_string())])
+        .send()?
+        .error_for_status()?;
+    let body: serde_json::Value = res.json()?;
+    let root = body["root"]
+        .as_str()
+        .ok_or(anyhow!("no root in PDS response for: {}", did))?;
+    Ok(Cid::from_str(root)?)
+}
+
+/// Fetches a repository export (CAR file bytes) from a PDS, using `com.atproto.sync.getRepo`.
+///
+/// If a `from` commit is provided, only blocks added since that commit are requested.
+pub fn fetch_repo_car(pds_url: &str, did: &Did, from: Option<&Cid>) -> Result<Vec<u8>> {
+    let mut params = vec![("did", did.to_string())];
+    if let Some(from) = from {
+        params.push(("from", from.to_string()));
+    }
+    let res = http_client()?
+        .get(format!("{pds_url}/xrpc/com.atproto.sync.getRepo"))
+        .query(&params)
+        .timeout(Duration::from_secs(120))
+        .send()?
+        .error_for_status()?;
+    Ok(res.bytes()?.to_vec())
+}
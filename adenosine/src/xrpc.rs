@@ -30,6 +30,14 @@ impl FromStr for XrpcMethod {
     }
 }
 
+/// Whether an error from an `XrpcClient` request was an HTTP 404 (Not Found) response, as opposed
+/// to a network error or other failure
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        == Some(reqwest::StatusCode::NOT_FOUND)
+}
+
 #[derive(Debug, Clone)]
 pub struct XrpcClient {
     http_client: reqwest::blocking::Client,
@@ -227,6 +235,41 @@ impl XrpcClient {
         }
     }
 
+    /// POSTs raw bytes (eg, a blob upload) as the request body, with the given Content-Type
+    pub fn post_bytes(
+        &self,
+        nsid: &Nsid,
+        params: Option<HashMap<String, String>>,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<Option<Value>> {
+        let params: HashMap<String, String> = params.unwrap_or_default();
+        log::debug!(
+            "XRPC POST endpoint={} params={:?} content_type={} len={}",
+            nsid,
+            params,
+            content_type,
+            body.len()
+        );
+        let res = self
+            .http_client
+            .post(format!("{}/xrpc/{}", self.host, nsid))
+            .headers(self.auth_headers(nsid))
+            .query(&params)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()?;
+        if res.status() == 400 {
+            let val: Value = res.json()?;
+            return Err(anyhow!(
+                "XRPC Bad Request (400): {}",
+                val["message"].as_str().unwrap_or("unknown")
+            ));
+        }
+        let res = res.error_for_status()?;
+        Ok(res.json()?)
+    }
+
     pub fn post_cbor_from_reader<R: std::io::Read>(
         &self,
         nsid: &Nsid,